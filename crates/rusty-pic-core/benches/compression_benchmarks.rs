@@ -30,14 +30,40 @@ fn benchmark_image_analysis(c: &mut Criterion) {
     });
 }
 
+fn benchmark_analysis_downscale(c: &mut Criterion) {
+    // 较大的图像（约 8MP），对比全尺寸与降采样分析
+    let img = image::RgbImage::from_fn(3264, 2448, |x, y| {
+        image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8])
+    });
+    let mut png_data = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut png_data),
+            image::ImageFormat::Png,
+        )
+        .expect("Failed to encode test image");
+
+    let full = ImageAnalyzer::new();
+    let downscaled = ImageAnalyzer::with_analysis_downscale(1024);
+
+    let mut group = c.benchmark_group("analysis_downscale");
+    group.sample_size(10);
+    group.bench_function("full", |b| {
+        b.iter(|| full.analyze(black_box(&png_data)).unwrap())
+    });
+    group.bench_function("max_dim_1024", |b| {
+        b.iter(|| downscaled.analyze(black_box(&png_data)).unwrap())
+    });
+    group.finish();
+}
+
 fn benchmark_compression(c: &mut Criterion) {
     let engine = CompressionEngine::new();
     let test_data = create_test_image_data();
     let options = CompressionOptions {
         format: Some("png".to_string()),
         quality: Some(80),
-        resize: None,
-        optimize: None,
+        ..Default::default()
     };
 
     c.bench_function("compression", |b| {
@@ -59,8 +85,7 @@ fn benchmark_different_qualities(c: &mut Criterion) {
         let options = CompressionOptions {
             format: Some("jpeg".to_string()),
             quality: Some(*quality),
-            resize: None,
-            optimize: None,
+            ..Default::default()
         };
 
         group.bench_with_input(format!("quality_{}", quality), quality, |b, _| {
@@ -74,10 +99,63 @@ fn benchmark_different_qualities(c: &mut Criterion) {
     group.finish();
 }
 
+fn benchmark_preview_encode(c: &mut Criterion) {
+    // 1024x768 带纹理的图像，对比默认编码与预览编码
+    let img = image::RgbImage::from_fn(1024, 768, |x, y| {
+        let noise = ((x * 7919) ^ (y * 104_729)) % 32;
+        image::Rgb([
+            ((x / 4) as u8).wrapping_add(noise as u8),
+            ((y / 3) as u8).wrapping_add(noise as u8),
+            ((x + y) / 7) as u8,
+        ])
+    });
+    let mut png_data = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut png_data),
+            image::ImageFormat::Png,
+        )
+        .expect("Failed to encode test image");
+
+    let engine = CompressionEngine::new();
+    let mut formats = vec!["png"];
+    if cfg!(feature = "webp") {
+        formats.push("webp");
+    }
+
+    let mut group = c.benchmark_group("preview_encode");
+    group.sample_size(10);
+    for format in formats {
+        let options = CompressionOptions {
+            format: Some(format.to_string()),
+            quality: Some(75),
+            ..Default::default()
+        };
+
+        group.bench_function(format!("{format}_default"), |b| {
+            b.iter(|| {
+                engine
+                    .compress(black_box(&png_data), black_box(&options))
+                    .unwrap()
+            })
+        });
+        group.bench_function(format!("{format}_preview"), |b| {
+            b.iter(|| {
+                engine
+                    .compress_preview(black_box(&png_data), format, 75, None)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_image_analysis,
+    benchmark_analysis_downscale,
     benchmark_compression,
-    benchmark_different_qualities
+    benchmark_different_qualities,
+    benchmark_preview_encode
 );
 criterion_main!(benches);
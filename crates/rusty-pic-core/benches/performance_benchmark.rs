@@ -76,6 +76,28 @@ fn bench_simd_color_conversion(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_color_conversion_block_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("color_conversion_block_size");
+
+    for size in [256usize, 1024, 2048, 4096].iter() {
+        let pixel_count = size * size;
+        group.throughput(Throughput::Bytes(pixel_count as u64 * 3));
+
+        let rgb_data: Vec<u8> = (0..pixel_count * 3).map(|i| (i % 256) as u8).collect();
+        let adaptive = SimdProcessor::adaptive_block_pixels(pixel_count);
+
+        group.bench_with_input(BenchmarkId::new("fixed_4096", size), size, |b, _| {
+            b.iter(|| SimdProcessor::rgb_to_yuv_with_block(black_box(&rgb_data), 4096));
+        });
+
+        group.bench_with_input(BenchmarkId::new("adaptive", size), size, |b, _| {
+            b.iter(|| SimdProcessor::rgb_to_yuv_with_block(black_box(&rgb_data), adaptive));
+        });
+    }
+
+    group.finish();
+}
+
 fn bench_simd_quantization(c: &mut Criterion) {
     let mut group = c.benchmark_group("simd_quantization");
 
@@ -152,6 +174,77 @@ fn bench_parallel_processing(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_batch_chunk_size(c: &mut Criterion) {
+    let mut group = c.benchmark_group("batch_chunk_size");
+
+    // 大小悬殊的混合批次：少量大图夹在大量小图之间
+    let images: Vec<DynamicImage> = (0..32)
+        .map(|i| {
+            if i % 8 == 0 {
+                create_test_image(1024, 1024, 3)
+            } else {
+                create_test_image(64, 64, 3)
+            }
+        })
+        .collect();
+
+    group.bench_function("default", |b| {
+        b.iter(|| {
+            let results = ParallelProcessor::process_batch(black_box(images.clone()), |img| {
+                Ok(img.to_luma8().into_raw().len())
+            });
+            black_box(results);
+        });
+    });
+
+    for chunk_size in [1, 4, 16].iter() {
+        group.bench_with_input(
+            BenchmarkId::new("chunked", chunk_size),
+            chunk_size,
+            |b, &chunk_size| {
+                b.iter(|| {
+                    let results = ParallelProcessor::process_batch_chunked(
+                        black_box(images.clone()),
+                        |img| Ok(img.to_luma8().into_raw().len()),
+                        chunk_size,
+                    );
+                    black_box(results);
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_luma_histogram(c: &mut Criterion) {
+    let mut group = c.benchmark_group("luma_histogram");
+
+    // 4K 灰度图
+    let gray = create_test_image(3840, 2160, 3).to_luma8();
+    group.throughput(Throughput::Elements(3840 * 2160));
+
+    group.bench_function("parallel", |b| {
+        b.iter(|| ParallelProcessor::histogram(black_box(&gray)));
+    });
+
+    group.bench_function("serial", |b| {
+        b.iter(|| {
+            let mut hist = [0u32; 256];
+            for &value in black_box(&gray).as_raw() {
+                hist[value as usize] += 1;
+            }
+            hist
+        });
+    });
+
+    group.bench_function("lbp_parallel", |b| {
+        b.iter(|| ParallelProcessor::lbp_histogram(black_box(&gray)));
+    });
+
+    group.finish();
+}
+
 fn bench_memory_pool(c: &mut Criterion) {
     let mut group = c.benchmark_group("memory_pool");
 
@@ -230,15 +323,14 @@ fn bench_compression_engine_optimized(c: &mut Criterion) {
     let options = CompressionOptions {
         format: Some("webp".to_string()),
         quality: Some(80),
-        resize: None,
-        optimize: None,
+        ..Default::default()
     };
 
     group.bench_function("optimized_engine", |b| {
         let engine = CompressionEngine::new();
         b.iter(|| {
             let result = engine.compress(black_box(&test_data), black_box(&options));
-            black_box(result);
+            let _ = black_box(result);
         });
     });
 
@@ -258,8 +350,11 @@ fn bench_compression_engine_optimized(c: &mut Criterion) {
 criterion_group!(
     benches,
     bench_simd_color_conversion,
+    bench_color_conversion_block_size,
     bench_simd_quantization,
     bench_parallel_processing,
+    bench_batch_chunk_size,
+    bench_luma_histogram,
     bench_memory_pool,
     bench_optimized_image_buffer,
     bench_compression_engine_optimized
@@ -1,63 +1,183 @@
 //! Image analysis functionality
 
-use crate::{performance::SimdProcessor, CompressionError, Result};
+use crate::{
+    compression::CompressionOptions,
+    logger::{log_event, LogCallback, Logger},
+    performance::{ParallelProcessor, SimdProcessor},
+    CompressionError, Result,
+};
 use image::{DynamicImage, GenericImageView, ImageFormat};
 use rayon::prelude::*;
 use std::collections::HashMap;
 
+/// An RGB colour paired with the fraction of pixels it covers
+pub type ColorCoverage = ((u8, u8, u8), f32);
+
+/// Per-channel RMS error below which a quantized palette counts as faithful
+const PALETTE_RMSE_THRESHOLD: f32 = 4.0;
+
 /// Analyzes images to determine optimal compression strategies
 pub struct ImageAnalyzer {
-    #[cfg(feature = "logging")]
-    logger_enabled: bool,
+    /// Longer-side limit for the image used by complexity passes
+    analysis_max_dim: Option<u32>,
+    /// Inclusive range every lossy quality recommendation is clamped into
+    quality_bounds: Option<(u8, u8)>,
+    logger: Logger,
 }
 
 impl ImageAnalyzer {
     pub fn new() -> Self {
         Self {
-            #[cfg(feature = "logging")]
-            logger_enabled: true,
+            analysis_max_dim: None,
+            quality_bounds: None,
+            logger: Logger::new(),
+        }
+    }
+
+    /// Create an analyzer that downscales large images before complexity analysis.
+    ///
+    /// The Sobel/LBP passes run on a copy resized so its longer side is at most
+    /// `max_dim`; dimensions, format and metadata still come from the original.
+    /// This trades a little accuracy in the complexity estimate for large
+    /// speedups on huge (e.g. 50MP) inputs.
+    pub fn with_analysis_downscale(max_dim: u32) -> Self {
+        Self {
+            analysis_max_dim: Some(max_dim.max(1)),
+            ..Self::new()
+        }
+    }
+
+    /// Create an analyzer whose recommended quality stays within `min..=max`.
+    ///
+    /// Applies a global policy such as "never below 70" to auto mode. PNG
+    /// recommendations are lossless and keep their quality of 100. The bounds
+    /// are swapped if given in the wrong order.
+    pub fn with_quality_bounds(min: u8, max: u8) -> Self {
+        Self {
+            quality_bounds: Some((min.min(max), min.max(max))),
+            ..Self::new()
         }
     }
 
+    /// Forward diagnostic messages to `callback` as well
+    pub(crate) fn set_log_callback(&mut self, callback: LogCallback) {
+        self.logger.set_callback(callback);
+    }
+
     /// Analyze an image and provide compression recommendations
     pub fn analyze(&self, data: &[u8]) -> Result<ImageAnalysis> {
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::debug!("Starting image analysis for {} bytes", data.len());
-        }
+        log_event!(
+            self.logger,
+            Debug,
+            "Starting image analysis for {} bytes",
+            data.len()
+        );
 
         // Detect format and load image
+        self.detect_format(data)?;
+        let img = Self::safe_load(data)?;
+        self.analyze_loaded(data, &img)
+    }
+
+    /// [`Self::analyze`] for `data` that the caller has already decoded into `img`
+    pub(crate) fn analyze_loaded(&self, data: &[u8], img: &DynamicImage) -> Result<ImageAnalysis> {
         let format = self.detect_format(data)?;
-        let img = image::load_from_memory(data)?;
+        if img.width() == 0 || img.height() == 0 {
+            return Err(CompressionError::AnalysisError(format!(
+                "Cannot analyze a zero-area image ({}x{})",
+                img.width(),
+                img.height()
+            )));
+        }
+        let mut analysis = self.analyze_decoded(img, &format_to_string(&format));
+
+        if let Some(bit_depth) = png_bit_depth(data) {
+            // 解码器会把 1/2/4 位扩展为 8 位，这里以 IHDR 中的真实位深为准
+            analysis.metadata.bit_depth = bit_depth;
+        }
+        if png_has_trns(data) {
+            // 调色板/灰度/RGB 通过 tRNS 表达透明，颜色类型本身不带 alpha
+            analysis.has_alpha = true;
+            analysis.metadata.has_transparency = true;
+        }
+        if jpeg_cmyk_kind(data).is_some() {
+            // 解码后已转换为 RGB，这里保留源色彩空间信息
+            analysis.metadata.color_type = "cmyk".to_string();
+        }
+        analysis.metadata.srgb_compatible = crate::icc::is_srgb_compatible(data);
+
+        // 输入码率已不高于推荐编码的预期码率时，重新编码只会让文件变大
+        let pixel_count = (analysis.width as f32 * analysis.height as f32).max(1.0);
+        let input_bpp = data.len() as f32 * 8.0 / pixel_count;
+        let expected_bpp =
+            expected_compression_ratio(&analysis.recommended_format, analysis.recommended_quality)
+                * raw_bits_per_pixel(img.color());
+        if input_bpp <= expected_bpp * ALREADY_OPTIMIZED_MARGIN {
+            analysis.already_optimized = true;
+            analysis.estimated_savings = (1.0 - expected_bpp / input_bpp).max(-1.0);
+            log_event!(
+                self.logger,
+                Info,
+                "Input is already near codec efficiency ({input_bpp:.2} bpp vs ~{expected_bpp:.2} bpp expected); re-encoding is unlikely to help"
+            );
+        }
+
+        Ok(analysis)
+    }
 
+    /// Analyze an image and package its recommendations as ready-to-use options.
+    ///
+    /// Format and quality come from [`ImageAnalysis::recommended_format`] and
+    /// [`ImageAnalysis::recommended_quality`]; every other field keeps its
+    /// neutral value so callers can tweak the result before compressing.
+    pub fn recommended_options(&self, data: &[u8]) -> Result<CompressionOptions> {
+        let analysis = self.analyze(data)?;
+        Ok(CompressionOptions {
+            format: Some(analysis.recommended_format),
+            quality: Some(analysis.recommended_quality),
+            ..Default::default()
+        })
+    }
+
+    /// Decode `data`, turning a panic inside a codec into an error.
+    ///
+    /// Malformed input can trip rare panics in the `image` codecs; they are
+    /// caught with `catch_unwind` and reported as a decoding
+    /// [`CompressionError::ImageError`]. The panic hook is left untouched, so
+    /// the panic message is still reported. Builds with `panic = "abort"`
+    /// (the WASM default) cannot recover this way.
+    pub fn safe_load(data: &[u8]) -> Result<DynamicImage> {
+        catch_decode_panic(|| decode_image_unguarded(data))
+    }
+
+    /// Analyze an already decoded image; `format` names its source container
+    pub(crate) fn analyze_decoded(&self, img: &DynamicImage, format: &str) -> ImageAnalysis {
         // Extract basic metadata
-        let metadata = self.extract_metadata(&img, &format);
+        let metadata = self.extract_metadata(img, format);
 
         // Analyze image characteristics
-        let has_alpha = self.has_alpha_channel(&img);
-        let color_count = self.estimate_color_count(&img);
-        let complexity = self.calculate_complexity(&img);
+        let has_alpha = self.has_alpha_channel(img);
+        let color_count = self.estimate_color_count(img);
+        let complexity = self.calculate_complexity(&self.analysis_image(img));
 
         // Generate recommendations
         let (recommended_format, recommended_quality) =
-            self.recommend_compression(&img, has_alpha, complexity);
-        let estimated_savings =
-            self.estimate_savings(&img, &recommended_format, recommended_quality);
-
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::info!(
-                "Analysis complete: {}x{} {}, complexity: {:.2}, recommended: {} at quality {}",
-                metadata.width,
-                metadata.height,
-                metadata.format,
-                complexity,
-                recommended_format,
-                recommended_quality
-            );
-        }
+            self.recommend_compression(img, has_alpha, complexity);
+        let estimated_savings = self.estimate_savings(&recommended_format, recommended_quality);
+
+        log_event!(
+            self.logger,
+            Info,
+            "Analysis complete: {}x{} {}, complexity: {:.2}, recommended: {} at quality {}",
+            metadata.width,
+            metadata.height,
+            metadata.format,
+            complexity,
+            recommended_format,
+            recommended_quality
+        );
 
-        Ok(ImageAnalysis {
+        ImageAnalysis {
             width: metadata.width,
             height: metadata.height,
             format: metadata.format.clone(),
@@ -67,18 +187,300 @@ impl ImageAnalyzer {
             recommended_format,
             recommended_quality,
             estimated_savings,
+            already_optimized: false,
+            average_color: mean_rgb(img),
             metadata,
-        })
+        }
+    }
+
+    /// Extract frame `index` (0-based) of an animated GIF, WebP or APNG as a still image.
+    ///
+    /// Frames are fully composited onto the canvas, honouring GIF disposal
+    /// methods, so the result always has the canvas dimensions. Still images
+    /// are treated as a single frame.
+    pub fn extract_frame(&self, data: &[u8], index: u32) -> Result<DynamicImage> {
+        let Some(frames) = self.animation_frames(data)? else {
+            return self.single_frame(data, index);
+        };
+
+        let mut frame_count = 0u32;
+        for frame in frames {
+            let frame = frame?;
+            if frame_count == index {
+                log_event!(
+                    self.logger,
+                    Debug,
+                    "Extracted frame {index} from animated source"
+                );
+                return Ok(DynamicImage::ImageRgba8(frame.into_buffer()));
+            }
+            frame_count += 1;
+        }
+
+        Err(CompressionError::InvalidFormat(format!(
+            "Frame index {index} out of range: source has {frame_count} frame(s)"
+        )))
+    }
+
+    /// Decode every frame (composited to the full canvas, with delays).
+    ///
+    /// Still images yield a single frame with zero delay.
+    pub(crate) fn decode_frames(&self, data: &[u8]) -> Result<Vec<image::Frame>> {
+        match self.animation_frames(data)? {
+            Some(frames) => Ok(frames.collect_frames()?),
+            None => Ok(vec![image::Frame::new(decode_image(data)?.to_rgba8())]),
+        }
+    }
+
+    /// Frame iterator for animated GIF, WebP or APNG; `None` for still images
+    fn animation_frames<'a>(&self, data: &'a [u8]) -> Result<Option<image::Frames<'a>>> {
+        use image::AnimationDecoder;
+        use std::io::Cursor;
+
+        let frames = match self.detect_format(data)? {
+            #[cfg(feature = "gif")]
+            ImageFormat::Gif => {
+                image::codecs::gif::GifDecoder::new(Cursor::new(data))?.into_frames()
+            }
+            #[cfg(feature = "webp")]
+            ImageFormat::WebP => {
+                let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(data))?;
+                if !decoder.has_animation() {
+                    return Ok(None);
+                }
+                decoder.into_frames()
+            }
+            #[cfg(feature = "png")]
+            ImageFormat::Png => {
+                let decoder = image::codecs::png::PngDecoder::new(Cursor::new(data))?;
+                if !decoder.is_apng() {
+                    return Ok(None);
+                }
+                decoder.apng().into_frames()
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(frames))
+    }
+
+    /// Make pixels close to `key` transparent, producing an RGBA image.
+    ///
+    /// A pixel is keyed out when every channel is within `tolerance` of the
+    /// key colour; all other pixels keep their original alpha.
+    pub fn chroma_key(&self, data: &[u8], key: [u8; 3], tolerance: u8) -> Result<DynamicImage> {
+        let img = decode_image(data)?;
+        Ok(apply_chroma_key(&img, key, tolerance))
+    }
+
+    /// Per-channel minimum, maximum and mean, e.g. for exposure checks.
+    ///
+    /// Alpha is reported only for images with an alpha channel.
+    pub fn channel_stats(&self, data: &[u8]) -> Result<ChannelStats> {
+        Ok(channel_stats_of(&decode_image(data)?))
+    }
+
+    /// Mean RGB over all pixels; alpha is ignored
+    pub fn average_color(&self, data: &[u8]) -> Result<(u8, u8, u8)> {
+        Ok(mean_rgb(&decode_image(data)?))
+    }
+
+    /// Make the detected background colour transparent (see [`Self::chroma_key`])
+    pub fn key_background(
+        &self,
+        data: &[u8],
+        detect: BackgroundDetect,
+        tolerance: u8,
+    ) -> Result<DynamicImage> {
+        let img = decode_image(data)?;
+        let [r, g, b, _] = background_of(&img.to_rgba8(), detect).0;
+        Ok(apply_chroma_key(&img, [r, g, b], tolerance))
+    }
+
+    /// Infer the background colour with the given strategy
+    pub fn detect_background(&self, data: &[u8], detect: BackgroundDetect) -> Result<[u8; 3]> {
+        let [r, g, b, _] = background_of(&decode_image(data)?.to_rgba8(), detect).0;
+        Ok([r, g, b])
+    }
+
+    /// Return the `(x, y, width, height)` rectangle enclosing all non-background pixels.
+    ///
+    /// The background is the colour shared by most of the four corner pixels;
+    /// a pixel counts as background when every RGBA channel is within
+    /// `bg_tolerance` of it. A uniform image yields its full bounds.
+    pub fn content_bounds(&self, data: &[u8], bg_tolerance: u8) -> Result<(u32, u32, u32, u32)> {
+        self.content_bounds_with(data, bg_tolerance, BackgroundDetect::Corners)
+    }
+
+    /// [`Self::content_bounds`] with a chosen background detection strategy
+    pub fn content_bounds_with(
+        &self,
+        data: &[u8],
+        bg_tolerance: u8,
+        detect: BackgroundDetect,
+    ) -> Result<(u32, u32, u32, u32)> {
+        let img = decode_image(data)?;
+        Ok(content_bounds_of(&img, bg_tolerance, detect))
+    }
+
+    /// Return the `count` most common colours with the fraction of sampled pixels each covers.
+    ///
+    /// Large images are sampled (about 100k pixels), so coverages are
+    /// estimates; they sum to at most 1.0.
+    pub fn dominant_colors_with_coverage(
+        &self,
+        data: &[u8],
+        count: usize,
+    ) -> Result<Vec<ColorCoverage>> {
+        let rgb = decode_image(data)?.to_rgb8();
+        let pixel_count = rgb.width() as usize * rgb.height() as usize;
+        if pixel_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let step = std::cmp::max(1, pixel_count / 100_000);
+        let mut histogram: HashMap<(u8, u8, u8), u32> = HashMap::new();
+        let mut sampled = 0u32;
+        for pixel in rgb.pixels().step_by(step) {
+            *histogram.entry((pixel[0], pixel[1], pixel[2])).or_insert(0) += 1;
+            sampled += 1;
+        }
+
+        let mut colors: Vec<_> = histogram.into_iter().collect();
+        // 次序按覆盖率降序，颜色值作为并列时的稳定排序键
+        colors.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        Ok(colors
+            .into_iter()
+            .take(count)
+            .map(|(color, hits)| (color, hits as f32 / sampled as f32))
+            .collect())
+    }
+
+    /// Render the `count` most common colours as a horizontal PNG strip.
+    ///
+    /// Each colour from [`Self::dominant_colors_with_coverage`] fills one
+    /// `swatch_px` square, most common first, so the strip is
+    /// `count * swatch_px` wide (narrower if the image has fewer colours)
+    /// and `swatch_px` tall.
+    pub fn palette_swatch(&self, data: &[u8], count: usize, swatch_px: u32) -> Result<Vec<u8>> {
+        if count == 0 || swatch_px == 0 {
+            return Err(CompressionError::InvalidFormat(
+                "Palette swatch needs at least one colour and a non-zero size".to_string(),
+            ));
+        }
+        let colors = self.dominant_colors_with_coverage(data, count)?;
+        let strip = image::RgbImage::from_fn(colors.len() as u32 * swatch_px, swatch_px, |x, _| {
+            let ((r, g, b), _) = colors[(x / swatch_px) as usize];
+            image::Rgb([r, g, b])
+        });
+        crate::formats::png::encode_optimized(
+            &DynamicImage::ImageRgb8(strip),
+            &crate::formats::png::PngOptions::default(),
+        )
+    }
+
+    /// Smallest palette size (at most `max`) that quantizes the image faithfully.
+    ///
+    /// Median cut runs at doubling sizes (2, 4, 8, ... and finally `max`);
+    /// the first palette whose per-channel RMS error stays below a small
+    /// threshold wins, and `max` is returned when none does. Use the result
+    /// as `OptimizeOptions::max_colors` for pngquant-style output without
+    /// over-quantizing images that only use a few colours.
+    pub fn suggest_palette_size(&self, data: &[u8], max: usize) -> Result<usize> {
+        if max == 0 {
+            return Err(CompressionError::InvalidFormat(
+                "Palette size limit must be at least 1".to_string(),
+            ));
+        }
+        let rgb = decode_image(data)?.to_rgb8();
+        let pixel_count = rgb.width() as usize * rgb.height() as usize;
+        if pixel_count == 0 {
+            return Ok(1);
+        }
+
+        let step = std::cmp::max(1, pixel_count / 100_000);
+        let mut histogram: HashMap<[u8; 3], u32> = HashMap::new();
+        for pixel in rgb.pixels().step_by(step) {
+            *histogram.entry(pixel.0).or_insert(0) += 1;
+        }
+        // HashMap 遍历顺序不固定，排序后保证结果可复现
+        let mut colors: Vec<([u8; 3], u32)> = histogram.into_iter().collect();
+        colors.sort_unstable();
+        if colors.len() <= max {
+            return Ok(colors.len());
+        }
+
+        let sampled: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+        let rmse = |size: usize| {
+            let palette = crate::smart::quantize::median_cut_weighted(colors.clone(), size);
+            let squared: u64 = colors
+                .par_iter()
+                .map(|&(color, n)| {
+                    let nearest = palette[crate::smart::quantize::nearest_index(color, &palette)];
+                    let distance: u32 = (0..3)
+                        .map(|c| (color[c] as i32 - nearest[c] as i32).pow(2) as u32)
+                        .sum();
+                    distance as u64 * n as u64
+                })
+                .sum();
+            (squared as f64 / (sampled * 3) as f64).sqrt() as f32
+        };
+
+        let mut size = 2.min(max);
+        while size < max {
+            let error = rmse(size);
+            log_event!(
+                self.logger,
+                Debug,
+                "Palette of {size}: RMS error {error:.2}"
+            );
+            if error < PALETTE_RMSE_THRESHOLD {
+                return Ok(size);
+            }
+            size *= 2;
+        }
+        Ok(max)
+    }
+
+    /// Still images expose exactly one frame
+    fn single_frame(&self, data: &[u8], index: u32) -> Result<DynamicImage> {
+        if index != 0 {
+            return Err(CompressionError::InvalidFormat(format!(
+                "Frame index {index} out of range: source has 1 frame(s)"
+            )));
+        }
+        decode_image(data)
+    }
+
+    /// Image used for the expensive complexity passes (downscaled if configured)
+    fn analysis_image<'a>(&self, img: &'a DynamicImage) -> std::borrow::Cow<'a, DynamicImage> {
+        let (width, height) = img.dimensions();
+        match self.analysis_max_dim {
+            Some(max_dim) if width.max(height) > max_dim => {
+                log_event!(
+                    self.logger,
+                    Debug,
+                    "Downscaling {width}x{height} to fit {max_dim}px for analysis"
+                );
+                std::borrow::Cow::Owned(img.resize(
+                    max_dim,
+                    max_dim,
+                    image::imageops::FilterType::Triangle,
+                ))
+            }
+            _ => std::borrow::Cow::Borrowed(img),
+        }
     }
 
     /// Detect image format from raw data
-    fn detect_format(&self, data: &[u8]) -> Result<ImageFormat> {
+    pub(crate) fn detect_format(&self, data: &[u8]) -> Result<ImageFormat> {
         image::guess_format(data)
             .map_err(|e| CompressionError::InvalidFormat(format!("Could not detect format: {e}")))
     }
 
     /// Extract basic metadata from image
-    fn extract_metadata(&self, img: &DynamicImage, format: &ImageFormat) -> ImageMetadata {
+    fn extract_metadata(&self, img: &DynamicImage, format: &str) -> ImageMetadata {
         let (width, height) = img.dimensions();
         let color_type = img.color();
         let bit_depth = match color_type {
@@ -96,7 +498,7 @@ impl ImageAnalyzer {
         ImageMetadata {
             width,
             height,
-            format: format_to_string(format),
+            format: format.to_string(),
             color_type: color_type_to_string(&color_type),
             bit_depth,
             has_transparency: matches!(
@@ -106,6 +508,7 @@ impl ImageAnalyzer {
                     | image::ColorType::Rgba8
                     | image::ColorType::Rgba16
             ),
+            srgb_compatible: true,
         }
     }
 
@@ -257,44 +660,12 @@ impl ImageAnalyzer {
             return 0.0;
         }
 
-        let mut lbp_histogram = HashMap::new();
-        let mut total_patterns = 0u32;
-
-        // 8-point Local Binary Pattern
-        let offsets = [
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, 1),
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, -1),
-        ];
-
-        for y in 1..height - 1 {
-            for x in 1..width - 1 {
-                let center_val = gray_img.get_pixel(x, y)[0];
-                let mut lbp_code = 0u8;
-
-                for (i, (dx, dy)) in offsets.iter().enumerate() {
-                    let neighbor_x = (x as i32 + dx) as u32;
-                    let neighbor_y = (y as i32 + dy) as u32;
-                    let neighbor_val = gray_img.get_pixel(neighbor_x, neighbor_y)[0];
-
-                    if neighbor_val >= center_val {
-                        lbp_code |= 1 << i;
-                    }
-                }
-
-                *lbp_histogram.entry(lbp_code).or_insert(0) += 1;
-                total_patterns += 1;
-            }
-        }
+        let lbp_histogram = ParallelProcessor::lbp_histogram(&gray_img);
+        let total_patterns = (width - 2) * (height - 2);
 
         // Calculate texture complexity as histogram entropy
         let mut entropy = 0.0f32;
-        for &count in lbp_histogram.values() {
+        for &count in lbp_histogram.iter() {
             if count > 0 {
                 let probability = count as f32 / total_patterns as f32;
                 entropy -= probability * probability.log2();
@@ -306,9 +677,12 @@ impl ImageAnalyzer {
     }
 
     /// Calculate perceptual quality requirements based on image characteristics
+    ///
+    /// Always returns a finite score in `[0, 1]`, including for degenerate
+    /// (zero-width or zero-height) images.
     pub fn calculate_perceptual_quality_score(&self, img: &DynamicImage) -> f32 {
         let (width, height) = img.dimensions();
-        let pixel_count = width * height;
+        let pixel_count = width as u64 * height as u64;
 
         // Base quality score based on resolution
         let resolution_score = if pixel_count > 2_000_000 {
@@ -321,8 +695,8 @@ impl ImageAnalyzer {
             0.7 // Lower resolution can tolerate more compression
         };
 
-        // Adjust based on aspect ratio
-        let aspect_ratio = width as f32 / height as f32;
+        // Adjust based on aspect ratio（零边长时避免 Inf/NaN，并限制在合理范围内）
+        let aspect_ratio = (width.max(1) as f32 / height.max(1) as f32).clamp(1e-4, 1e4);
         let aspect_penalty = if !(0.33..=3.0).contains(&aspect_ratio) {
             0.9 // Extreme aspect ratios may be more tolerant to compression
         } else {
@@ -330,10 +704,15 @@ impl ImageAnalyzer {
         };
 
         // Consider color complexity
-        let color_complexity = self.estimate_color_count(img) as f32 / 65536.0;
+        let color_complexity = (self.estimate_color_count(img) as f32 / 65536.0).clamp(0.0, 1.0);
         let color_factor = 0.8 + (color_complexity * 0.2);
 
-        (resolution_score * aspect_penalty * color_factor).min(1.0)
+        let score = resolution_score * aspect_penalty * color_factor;
+        if score.is_finite() {
+            score.clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
     }
 
     /// Recommend optimal compression format and quality using advanced analysis
@@ -426,67 +805,90 @@ impl ImageAnalyzer {
         // Apply perceptual quality adjustment
         let adjusted_quality = if format != "png" {
             let perceptual_adjustment = 0.85 + (perceptual_score * 0.15);
-            ((base_quality as f32 * perceptual_adjustment) as u8).clamp(50, 100)
+            let quality = ((base_quality as f32 * perceptual_adjustment) as u8).clamp(50, 100);
+            match self.quality_bounds {
+                Some((min, max)) => quality.clamp(min, max),
+                None => quality,
+            }
         } else {
             base_quality
         };
 
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::debug!(
-                "Format recommendation: {format} at quality {adjusted_quality} (complexity: {complexity:.3}, texture: {texture_complexity:.3}, colors: {color_count}, perceptual: {perceptual_score:.3})"
-            );
-        }
+        log_event!(self.logger, Debug, "Format recommendation: {format} at quality {adjusted_quality} (complexity: {complexity:.3}, texture: {texture_complexity:.3}, colors: {color_count}, perceptual: {perceptual_score:.3})");
 
         (format, adjusted_quality)
     }
 
-    /// Estimate potential file size savings
-    fn estimate_savings(&self, img: &DynamicImage, format: &str, quality: u8) -> f32 {
-        let (width, height) = img.dimensions();
-        let pixel_count = width * height;
-        let bytes_per_pixel = match img.color() {
-            image::ColorType::L8 => 1,
-            image::ColorType::La8 => 2,
-            image::ColorType::Rgb8 => 3,
-            image::ColorType::Rgba8 => 4,
-            image::ColorType::L16 => 2,
-            image::ColorType::La16 => 4,
-            image::ColorType::Rgb16 => 6,
-            image::ColorType::Rgba16 => 8,
-            _ => 4,
-        };
+    /// Estimate the quality a JPEG was saved with from its luminance
+    /// quantization table, using the IJG quality scaling convention.
+    ///
+    /// Returns `None` for non-JPEG data or when no DQT segment is present.
+    pub fn estimate_jpeg_quality(&self, data: &[u8]) -> Option<u8> {
+        // 标准 IJG 亮度量化表各项之和（与 zigzag 排列无关）
+        const STD_LUMA_SUM: u32 = 3_688;
+
+        for (marker, payload) in jpeg_segments(data) {
+            if marker != 0xDB {
+                continue;
+            }
 
-        let _uncompressed_size = pixel_count * bytes_per_pixel;
+            let mut pos = 0usize;
+            while pos < payload.len() {
+                let precision = payload[pos] >> 4;
+                let table_id = payload[pos] & 0x0F;
+                let entry_size = if precision == 0 { 1 } else { 2 };
+                let end = pos + 1 + 64 * entry_size;
+                if end > payload.len() {
+                    return None;
+                }
 
-        // Rough estimation based on format and quality
-        let compression_ratio = match format {
-            "jpeg" => match quality {
-                q if q >= 90 => 0.15,
-                q if q >= 80 => 0.10,
-                q if q >= 70 => 0.08,
-                _ => 0.06,
-            },
-            "webp" => match quality {
-                q if q >= 90 => 0.12,
-                q if q >= 80 => 0.08,
-                q if q >= 70 => 0.06,
-                _ => 0.04,
-            },
-            "avif" => {
-                // AVIF typically achieves better compression than WebP
-                match quality {
-                    q if q >= 90 => 0.08,
-                    q if q >= 80 => 0.05,
-                    q if q >= 70 => 0.04,
-                    _ => 0.03,
+                if table_id == 0 {
+                    let sum: u32 = payload[pos + 1..end]
+                        .chunks(entry_size)
+                        .map(|c| c.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32))
+                        .sum();
+                    let scale = sum as f32 * 100.0 / STD_LUMA_SUM as f32;
+                    let quality = if scale <= 100.0 {
+                        (200.0 - scale) / 2.0
+                    } else {
+                        5000.0 / scale
+                    };
+                    return Some(quality.round().clamp(1.0, 100.0) as u8);
                 }
+                pos = end;
             }
-            "png" => 0.25, // PNG compression varies widely
-            _ => 0.15,
-        };
+        }
 
-        1.0 - compression_ratio
+        None
+    }
+
+    /// Decode a quick approximation of a progressive JPEG from its first `max_scans` scans.
+    ///
+    /// The stream is cut after the requested scan and closed with EOI, so the
+    /// decoder renders whatever coefficients have been refined so far at full
+    /// dimensions. Baseline JPEGs, other formats and progressive files with
+    /// no more than `max_scans` scans are decoded in full. `max_scans` of 0 is
+    /// treated as 1.
+    pub fn decode_preview(&self, data: &[u8], max_scans: u32) -> Result<DynamicImage> {
+        match progressive_scan_prefix(data, max_scans.max(1)) {
+            Some(prefix) => {
+                log_event!(
+                    self.logger,
+                    Debug,
+                    "Preview decode of {} of {} bytes ({max_scans} scans)",
+                    prefix.len(),
+                    data.len()
+                );
+                decode_image(&prefix)
+            }
+            None => decode_image(data),
+        }
+    }
+
+    /// Estimate potential file size savings
+    fn estimate_savings(&self, format: &str, quality: u8) -> f32 {
+        // Rough estimation based on format and quality
+        1.0 - expected_compression_ratio(format, quality)
     }
 }
 
@@ -497,7 +899,472 @@ impl Default for ImageAnalyzer {
 }
 
 // Helper functions
-fn format_to_string(format: &ImageFormat) -> String {
+
+/// Inputs within this factor of the expected output bitrate count as already optimized
+const ALREADY_OPTIMIZED_MARGIN: f32 = 1.1;
+
+/// Expected encoded size as a fraction of the raw pixel data
+fn expected_compression_ratio(format: &str, quality: u8) -> f32 {
+    match format {
+        "jpeg" => match quality {
+            q if q >= 90 => 0.15,
+            q if q >= 80 => 0.10,
+            q if q >= 70 => 0.08,
+            _ => 0.06,
+        },
+        "webp" => match quality {
+            q if q >= 90 => 0.12,
+            q if q >= 80 => 0.08,
+            q if q >= 70 => 0.06,
+            _ => 0.04,
+        },
+        "avif" => {
+            // AVIF typically achieves better compression than WebP
+            match quality {
+                q if q >= 90 => 0.08,
+                q if q >= 80 => 0.05,
+                q if q >= 70 => 0.04,
+                _ => 0.03,
+            }
+        }
+        "png" => 0.25, // PNG compression varies widely
+        _ => 0.15,
+    }
+}
+
+/// Bits per pixel of the decoded (uncompressed) image
+fn raw_bits_per_pixel(color: image::ColorType) -> f32 {
+    match color {
+        image::ColorType::L8 => 8.0,
+        image::ColorType::La8 | image::ColorType::L16 => 16.0,
+        image::ColorType::Rgb8 => 24.0,
+        image::ColorType::Rgba8 | image::ColorType::La16 => 32.0,
+        image::ColorType::Rgb16 => 48.0,
+        image::ColorType::Rgba16 => 64.0,
+        _ => 32.0,
+    }
+}
+
+/// Iterate over JPEG marker segments (marker byte, payload) up to the first SOS
+pub(crate) fn jpeg_segments(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let is_jpeg = data.len() >= 4 && data[0] == 0xFF && data[1] == 0xD8;
+    let mut pos = if is_jpeg { 2 } else { data.len() };
+
+    std::iter::from_fn(move || {
+        while pos + 4 <= data.len() {
+            if data[pos] != 0xFF {
+                return None;
+            }
+            let marker = data[pos + 1];
+            // 填充字节与无长度的独立标记
+            if marker == 0xFF {
+                pos += 1;
+                continue;
+            }
+            if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+                pos += 2;
+                continue;
+            }
+            if marker == 0xDA || marker == 0xD9 {
+                return None;
+            }
+
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            if len < 2 || pos + 2 + len > data.len() {
+                return None;
+            }
+            let payload = &data[pos + 4..pos + 2 + len];
+            pos += 2 + len;
+            return Some((marker, payload));
+        }
+        None
+    })
+}
+
+/// First `max_scans` scans of a progressive JPEG, terminated with EOI.
+///
+/// `None` when the data is not a progressive JPEG or has no scan beyond
+/// `max_scans`.
+fn progressive_scan_prefix(data: &[u8], max_scans: u32) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut pos = 2;
+    let mut progressive = false;
+    let mut scans = 0u32;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            0xD9 => return None,
+            // SOF2/6/10/14 为渐进式帧
+            0xC2 | 0xC6 | 0xCA | 0xCE => progressive = true,
+            _ => {}
+        }
+
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        pos += 2 + len;
+        if marker != 0xDA {
+            continue;
+        }
+        if !progressive {
+            return None;
+        }
+
+        // 跳过熵编码数据：0xFF00 为转义字节，RSTn 属于扫描内部
+        while pos + 1 < data.len()
+            && (data[pos] != 0xFF || matches!(data[pos + 1], 0x00 | 0xD0..=0xD7))
+        {
+            pos += 1;
+        }
+        scans += 1;
+        if scans == max_scans {
+            if pos + 1 >= data.len() || data[pos + 1] == 0xD9 {
+                return None;
+            }
+            let mut prefix = Vec::with_capacity(pos + 2);
+            prefix.extend_from_slice(&data[..pos]);
+            prefix.extend_from_slice(&[0xFF, 0xD9]);
+            return Some(prefix);
+        }
+    }
+    None
+}
+
+/// Colour encoding of a four-component (print workflow) JPEG
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum JpegCmykKind {
+    /// Plain CMYK; `adobe` marks an APP14 segment, which implies inverted storage
+    Cmyk { adobe: bool },
+    /// Adobe YCCK (YCbCr-encoded CMY plus K)
+    Ycck,
+}
+
+/// Detect CMYK/YCCK JPEGs from the SOF component count and the Adobe APP14 marker.
+pub(crate) fn jpeg_cmyk_kind(data: &[u8]) -> Option<JpegCmykKind> {
+    let mut components = None;
+    let mut adobe_transform = None;
+
+    for (marker, payload) in jpeg_segments(data) {
+        match marker {
+            // SOF0-SOF15（排除 DHT/JPG/DAC）
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                components = payload.get(5).copied();
+            }
+            0xEE if payload.len() >= 12 && payload.starts_with(b"Adobe") => {
+                adobe_transform = Some(payload[11]);
+            }
+            _ => {}
+        }
+    }
+
+    if components != Some(4) {
+        return None;
+    }
+    Some(match adobe_transform {
+        Some(2) => JpegCmykKind::Ycck,
+        Some(_) => JpegCmykKind::Cmyk { adobe: true },
+        None => JpegCmykKind::Cmyk { adobe: false },
+    })
+}
+
+/// Decode an input image, converting CMYK/YCCK JPEGs to RGB explicitly.
+pub(crate) fn decode_image(data: &[u8]) -> Result<DynamicImage> {
+    ImageAnalyzer::safe_load(data)
+}
+
+/// Run a decode, mapping a panic to a decoding error
+fn catch_decode_panic(decode: impl FnOnce() -> Result<DynamicImage>) -> Result<DynamicImage> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode)).unwrap_or_else(|payload| {
+        // panic 载荷通常是 &str 或 String
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(CompressionError::ImageError(image::ImageError::Decoding(
+            image::error::DecodingError::new(
+                image::error::ImageFormatHint::Unknown,
+                format!("decoder panicked: {message}"),
+            ),
+        )))
+    })
+}
+
+fn decode_image_unguarded(data: &[u8]) -> Result<DynamicImage> {
+    #[cfg(feature = "jpeg")]
+    if let Some(kind) = jpeg_cmyk_kind(data) {
+        return decode_cmyk_jpeg(data, kind);
+    }
+
+    Ok(image::load_from_memory(data)?)
+}
+
+#[cfg(feature = "jpeg")]
+fn decode_cmyk_jpeg(data: &[u8], kind: JpegCmykKind) -> Result<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder
+        .decode()
+        .map_err(|e| CompressionError::InvalidFormat(format!("CMYK JPEG decode failed: {e}")))?;
+    let info = decoder.info().ok_or_else(|| {
+        CompressionError::InvalidFormat("CMYK JPEG is missing frame info".to_string())
+    })?;
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+        return Err(CompressionError::InvalidFormat(format!(
+            "Expected CMYK pixel data, got {:?}",
+            info.pixel_format
+        )));
+    }
+
+    // 解码器总是按 Adobe 约定（反相存储）还原数据；
+    // 没有 APP14 标记的文件是正常存储的，需要再反相一次
+    let invert = kind == JpegCmykKind::Cmyk { adobe: false };
+
+    let rgb: Vec<u8> = pixels
+        .chunks_exact(4)
+        .flat_map(|px| {
+            let [c, m, y, k] = if invert {
+                [255 - px[0], 255 - px[1], 255 - px[2], 255 - px[3]]
+            } else {
+                [px[0], px[1], px[2], px[3]]
+            };
+            let white = 255 - k as u32;
+            [c, m, y].map(|v| ((255 - v as u32) * white / 255) as u8)
+        })
+        .collect();
+
+    image::RgbImage::from_raw(info.width as u32, info.height as u32, rgb)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| {
+            CompressionError::InvalidFormat("CMYK JPEG pixel buffer size mismatch".to_string())
+        })
+}
+
+/// Chroma-key an already decoded image (see [`ImageAnalyzer::chroma_key`])
+pub(crate) fn apply_chroma_key(img: &DynamicImage, key: [u8; 3], tolerance: u8) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    rgba.par_chunks_mut(4).for_each(|px| {
+        let within = px[..3]
+            .iter()
+            .zip(key.iter())
+            .all(|(&c, &k)| c.abs_diff(k) <= tolerance);
+        if within {
+            px[3] = 0;
+        }
+    });
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// How autocrop and background keying infer the background colour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackgroundDetect {
+    /// The colour shared by most of the four corner pixels
+    #[default]
+    Corners,
+    /// The most frequent colour along the one-pixel border ring; robust
+    /// to busy corners
+    MostFrequentEdge,
+    /// A known, opaque background colour
+    Explicit([u8; 3]),
+}
+
+/// Background colour of a non-empty image under the given strategy
+pub(crate) fn background_of(rgba: &image::RgbaImage, detect: BackgroundDetect) -> image::Rgba<u8> {
+    let (width, height) = rgba.dimensions();
+    match detect {
+        BackgroundDetect::Corners => {
+            // 取四角中出现次数最多的颜色作为背景，平票时以左上角为准
+            let corners = [
+                *rgba.get_pixel(0, 0),
+                *rgba.get_pixel(width - 1, 0),
+                *rgba.get_pixel(0, height - 1),
+                *rgba.get_pixel(width - 1, height - 1),
+            ];
+            corners
+                .iter()
+                .rev()
+                .max_by_key(|c| corners.iter().filter(|other| other == c).count())
+                .copied()
+                .unwrap_or(corners[0])
+        }
+        BackgroundDetect::MostFrequentEdge => {
+            let top_bottom = (0..width).flat_map(|x| [(x, 0), (x, height - 1)]);
+            let sides = (1..height.saturating_sub(1)).flat_map(|y| [(0, y), (width - 1, y)]);
+            let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+            for (x, y) in top_bottom.chain(sides) {
+                *counts.entry(rgba.get_pixel(x, y).0).or_insert(0) += 1;
+            }
+            // 平票时取数值最小的颜色，保证结果与遍历顺序无关
+            let modal = counts
+                .into_iter()
+                .max_by_key(|&(color, count)| (count, std::cmp::Reverse(color)))
+                .map_or([0, 0, 0, 255], |(color, _)| color);
+            image::Rgba(modal)
+        }
+        BackgroundDetect::Explicit([r, g, b]) => image::Rgba([r, g, b, 255]),
+    }
+}
+
+/// Content bounds of an already decoded image (see [`ImageAnalyzer::content_bounds`])
+pub(crate) fn content_bounds_of(
+    img: &DynamicImage,
+    bg_tolerance: u8,
+    detect: BackgroundDetect,
+) -> (u32, u32, u32, u32) {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if width == 0 || height == 0 {
+        return (0, 0, width, height);
+    }
+
+    let background = background_of(&rgba, detect);
+
+    let is_content = |x: u32, y: u32| {
+        rgba.get_pixel(x, y)
+            .0
+            .iter()
+            .zip(background.0.iter())
+            .any(|(&c, &b)| c.abs_diff(b) > bg_tolerance)
+    };
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            if is_content(x, y) {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+
+    if min_x > max_x {
+        // 全部为背景：不裁剪
+        return (0, 0, width, height);
+    }
+    (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)
+}
+
+/// Bit depth declared in a PNG's IHDR chunk
+fn png_bit_depth(data: &[u8]) -> Option<u8> {
+    // 8 字节签名 + 长度(4) + "IHDR"(4) + 宽(4) + 高(4) 之后即为位深
+    if data.len() > 24 && data.starts_with(b"\x89PNG\r\n\x1a\n") && &data[12..16] == b"IHDR" {
+        Some(data[24])
+    } else {
+        None
+    }
+}
+
+/// Rounded per-channel mean of the RGB samples
+fn mean_rgb(img: &DynamicImage) -> (u8, u8, u8) {
+    let rgb = img.to_rgb8();
+    let sums = rgb
+        .as_raw()
+        .par_chunks(3 * 4096)
+        .map(|chunk| {
+            chunk.chunks_exact(3).fold([0u64; 3], |mut acc, px| {
+                acc[0] += px[0] as u64;
+                acc[1] += px[1] as u64;
+                acc[2] += px[2] as u64;
+                acc
+            })
+        })
+        .reduce(|| [0u64; 3], |a, b| [a[0] + b[0], a[1] + b[1], a[2] + b[2]]);
+    let count = (rgb.width() as u64 * rgb.height() as u64).max(1);
+    let mean = |sum: u64| ((sum + count / 2) / count) as u8;
+    (mean(sums[0]), mean(sums[1]), mean(sums[2]))
+}
+
+/// Minimum, maximum and mean of the 8-bit samples of one channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStat {
+    pub min: u8,
+    pub max: u8,
+    pub mean: f32,
+}
+
+/// Per-channel statistics of an image (see [`ImageAnalyzer::channel_stats`])
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelStats {
+    pub red: ChannelStat,
+    pub green: ChannelStat,
+    pub blue: ChannelStat,
+    /// `None` when the image has no alpha channel
+    pub alpha: Option<ChannelStat>,
+}
+
+/// Channel statistics of an already decoded image, on its 8-bit RGBA samples
+pub(crate) fn channel_stats_of(img: &DynamicImage) -> ChannelStats {
+    let rgba = img.to_rgba8();
+    let (min, max, sum) = rgba
+        .as_raw()
+        .par_chunks(4 * 4096)
+        .map(|chunk| {
+            chunk.chunks_exact(4).fold(
+                ([u8::MAX; 4], [0u8; 4], [0u64; 4]),
+                |(mut min, mut max, mut sum), px| {
+                    for c in 0..4 {
+                        min[c] = min[c].min(px[c]);
+                        max[c] = max[c].max(px[c]);
+                        sum[c] += px[c] as u64;
+                    }
+                    (min, max, sum)
+                },
+            )
+        })
+        .reduce(
+            || ([u8::MAX; 4], [0u8; 4], [0u64; 4]),
+            |a, b| {
+                (
+                    std::array::from_fn(|c| a.0[c].min(b.0[c])),
+                    std::array::from_fn(|c| a.1[c].max(b.1[c])),
+                    std::array::from_fn(|c| a.2[c] + b.2[c]),
+                )
+            },
+        );
+
+    let count = rgba.width() as u64 * rgba.height() as u64;
+    let stat = |c: usize| {
+        if count == 0 {
+            ChannelStat {
+                min: 0,
+                max: 0,
+                mean: 0.0,
+            }
+        } else {
+            ChannelStat {
+                min: min[c],
+                max: max[c],
+                mean: (sum[c] as f64 / count as f64) as f32,
+            }
+        }
+    };
+    ChannelStats {
+        red: stat(0),
+        green: stat(1),
+        blue: stat(2),
+        alpha: img.color().has_alpha().then(|| stat(3)),
+    }
+}
+
+/// Whether a PNG carries a `tRNS` chunk (palette, grey or RGB transparency)
+fn png_has_trns(data: &[u8]) -> bool {
+    crate::icc::png_chunks(data).any(|(kind, _)| &kind == b"tRNS")
+}
+
+pub(crate) fn format_to_string(format: &ImageFormat) -> String {
     match format {
         ImageFormat::Png => "png".to_string(),
         ImageFormat::Jpeg => "jpeg".to_string(),
@@ -535,6 +1402,11 @@ pub struct ImageAnalysis {
     pub recommended_format: String,
     pub recommended_quality: u8,
     pub estimated_savings: f32,
+    /// The input's bitrate is already at or below what re-encoding is
+    /// expected to reach, so `estimated_savings` is near zero or negative
+    pub already_optimized: bool,
+    /// Mean RGB over all pixels (alpha ignored), e.g. for placeholder backgrounds
+    pub average_color: (u8, u8, u8),
     pub metadata: ImageMetadata,
 }
 
@@ -547,6 +1419,33 @@ pub struct ImageMetadata {
     pub color_type: String,
     pub bit_depth: u8,
     pub has_transparency: bool,
+    /// False when the source embeds an ICC profile other than sRGB
+    pub srgb_compatible: bool,
+}
+
+impl ImageMetadata {
+    /// Bytes per decoded pixel for [`color_type`](Self::color_type).
+    ///
+    /// Accepts the names produced by analysis (`rgba`, `grayscale16`, ...)
+    /// as well as `image` crate style ones (`rgba8`, `l8`, `la16`, ...).
+    /// Unknown types count as 4 (RGBA8), the layout most paths decode to.
+    pub fn bytes_per_pixel(&self) -> u8 {
+        match self.color_type.as_str() {
+            "grayscale" | "l8" => 1,
+            "grayscale+alpha" | "la8" | "grayscale16" | "l16" => 2,
+            // CMYK 源解码后为 RGB
+            "rgb" | "rgb8" | "cmyk" => 3,
+            "rgba" | "rgba8" | "grayscale16+alpha" | "la16" => 4,
+            "rgb16" => 6,
+            "rgba16" => 8,
+            _ => 4,
+        }
+    }
+
+    /// Size in bytes of the decoded pixel buffer
+    pub fn raw_size(&self) -> usize {
+        self.width as usize * self.height as usize * self.bytes_per_pixel() as usize
+    }
 }
 
 #[cfg(test)]
@@ -557,7 +1456,7 @@ mod tests {
     fn test_analyzer_new() {
         let analyzer = ImageAnalyzer::new();
         // Test that analyzer can be created
-        assert!(std::ptr::addr_of!(analyzer) as *const _ != std::ptr::null());
+        assert!(!std::ptr::addr_of!(analyzer).is_null());
     }
 
     #[test]
@@ -569,6 +1468,107 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_perceptual_score_is_finite_and_bounded() {
+        let analyzer = ImageAnalyzer::new();
+        // 简单 LCG 生成伪随机的小尺寸（含 0 边长）与像素内容
+        let mut state = 0x2545_f491_u32;
+        let mut next = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            state >> 16
+        };
+
+        for _ in 0..200 {
+            let width = next() % 48;
+            let height = next() % 48;
+            let seed = next();
+            let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+                let v = seed.wrapping_add(x * 31 + y * 17) as u8;
+                image::Rgb([v, v.wrapping_mul(3), v ^ 0x5a])
+            }));
+
+            let score = analyzer.calculate_perceptual_quality_score(&img);
+            assert!(
+                score.is_finite() && (0.0..=1.0).contains(&score),
+                "{width}x{height}: score {score}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_decoder_panic_becomes_error() {
+        let result = catch_decode_panic(|| panic!("corrupt huffman table"));
+        match result {
+            Err(CompressionError::ImageError(e)) => {
+                assert!(e.to_string().contains("corrupt huffman table"))
+            }
+            other => panic!("expected ImageError, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_estimate_jpeg_quality() {
+        let img =
+            image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 64]));
+        let mut jpeg_data = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg_data, 75)
+            .encode_image(&img)
+            .unwrap();
+
+        let analyzer = ImageAnalyzer::new();
+        let quality = analyzer.estimate_jpeg_quality(&jpeg_data).unwrap();
+        assert!((70..=80).contains(&quality), "estimated quality {quality}");
+        assert!(analyzer.estimate_jpeg_quality(b"not a jpeg").is_none());
+    }
+
+    #[test]
+    fn test_jpeg_cmyk_kind_detection() {
+        let cmyk = [0u8, 255, 255, 0].repeat(16 * 16);
+        let mut cmyk_jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut cmyk_jpeg, 90)
+            .encode(&cmyk, 16, 16, jpeg_encoder::ColorType::Cmyk)
+            .unwrap();
+        let mut ycck_jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut ycck_jpeg, 90)
+            .encode(&cmyk, 16, 16, jpeg_encoder::ColorType::CmykAsYcck)
+            .unwrap();
+        let mut rgb_jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut rgb_jpeg, 90)
+            .encode(&[128u8; 16 * 16 * 3], 16, 16, jpeg_encoder::ColorType::Rgb)
+            .unwrap();
+
+        assert_eq!(
+            jpeg_cmyk_kind(&cmyk_jpeg),
+            Some(JpegCmykKind::Cmyk { adobe: true })
+        );
+        assert_eq!(jpeg_cmyk_kind(&ycck_jpeg), Some(JpegCmykKind::Ycck));
+        assert_eq!(jpeg_cmyk_kind(&rgb_jpeg), None);
+    }
+
+    #[test]
+    fn test_dominant_colors_with_coverage() {
+        let img = image::RgbImage::from_fn(40, 20, |x, _| {
+            if x < 20 {
+                image::Rgb([255, 0, 0])
+            } else {
+                image::Rgb([0, 255, 0])
+            }
+        });
+        let mut png_data = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut std::io::Cursor::new(&mut png_data), ImageFormat::Png)
+            .unwrap();
+
+        let colors = ImageAnalyzer::new()
+            .dominant_colors_with_coverage(&png_data, 3)
+            .unwrap();
+        assert_eq!(colors.len(), 2);
+        for (color, coverage) in &colors {
+            assert!([(255, 0, 0), (0, 255, 0)].contains(color));
+            assert!((coverage - 0.5).abs() < 0.01, "coverage {coverage}");
+        }
+    }
+
     #[test]
     fn test_image_analysis_clone() {
         let metadata = ImageMetadata {
@@ -578,6 +1578,7 @@ mod tests {
             color_type: "rgba".to_string(),
             bit_depth: 8,
             has_transparency: true,
+            srgb_compatible: true,
         };
 
         let analysis = ImageAnalysis {
@@ -590,6 +1591,8 @@ mod tests {
             recommended_format: "webp".to_string(),
             recommended_quality: 80,
             estimated_savings: 0.3,
+            already_optimized: false,
+            average_color: (128, 128, 128),
             metadata,
         };
 
@@ -598,4 +1601,70 @@ mod tests {
         assert_eq!(analysis.height, cloned.height);
         assert_eq!(analysis.format, cloned.format);
     }
+
+    #[test]
+    fn test_average_color_of_half_black_half_white() {
+        let img =
+            image::GrayImage::from_fn(20, 10, |x, _| image::Luma([if x < 10 { 0 } else { 255 }]));
+        let mut png = Vec::new();
+        DynamicImage::ImageLuma8(img)
+            .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let analyzer = ImageAnalyzer::new();
+        let (r, g, b) = analyzer.average_color(&png).unwrap();
+        for channel in [r, g, b] {
+            assert!((127..=128).contains(&channel), "channel {channel}");
+        }
+        assert_eq!(analyzer.analyze(&png).unwrap().average_color, (r, g, b));
+    }
+
+    #[test]
+    fn test_channel_stats_of_gradient() {
+        // 红色通道横向 0→255，绿色恒为 100，蓝色在 40-42 间轻微波动
+        let img = image::RgbImage::from_fn(256, 8, |x, y| {
+            image::Rgb([x as u8, 100, 40 + (y % 3) as u8])
+        });
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let stats = ImageAnalyzer::new().channel_stats(&png).unwrap();
+        assert_eq!((stats.red.min, stats.red.max), (0, 255));
+        assert!((stats.red.mean - 127.5).abs() < 0.01);
+        assert_eq!((stats.green.min, stats.green.max), (100, 100));
+        assert_eq!(stats.green.mean, 100.0);
+        assert_eq!((stats.blue.min, stats.blue.max), (40, 42));
+        assert!(stats.alpha.is_none());
+
+        let translucent = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            4,
+            4,
+            image::Rgba([0, 0, 0, 64]),
+        ));
+        let alpha = channel_stats_of(&translucent).alpha.unwrap();
+        assert_eq!((alpha.min, alpha.max, alpha.mean), (64, 64, 64.0));
+    }
+
+    #[test]
+    fn test_metadata_bytes_per_pixel() {
+        let metadata = |color_type: &str| ImageMetadata {
+            width: 10,
+            height: 20,
+            format: "png".to_string(),
+            color_type: color_type.to_string(),
+            bit_depth: 8,
+            has_transparency: false,
+            srgb_compatible: true,
+        };
+
+        assert_eq!(metadata("rgba8").bytes_per_pixel(), 4);
+        assert_eq!(metadata("rgba").bytes_per_pixel(), 4);
+        assert_eq!(metadata("l8").bytes_per_pixel(), 1);
+        assert_eq!(metadata("grayscale").bytes_per_pixel(), 1);
+        assert_eq!(metadata("rgb16").bytes_per_pixel(), 6);
+        assert_eq!(metadata("grayscale16+alpha").bytes_per_pixel(), 4);
+        assert_eq!(metadata("rgb").raw_size(), 10 * 20 * 3);
+    }
 }
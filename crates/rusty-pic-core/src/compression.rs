@@ -1,45 +1,148 @@
 //! Core compression engine
 
 use crate::{
-    performance::{MemoryPool, SimdProcessor},
+    encoder::{FormatCapabilities, FormatEncoder},
+    logger::{log_event, LogCallback, Logger},
+    performance::{MemoryPool, ParallelProcessor, SimdProcessor},
     CompressionError, ImageAnalyzer, ImageMetadata, Result,
 };
 use image::{DynamicImage, GenericImageView};
 use rayon::prelude::*;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Per-channel tolerance used to detect the uniform border for `autocrop`
+const AUTOCROP_TOLERANCE: u8 = 8;
+
+/// Quality `max_output_bytes` retries start from when the options set none
+const CAP_START_QUALITY: u8 = 80;
+/// Lowest quality tried before `max_output_bytes` falls back to downscaling
+const CAP_MIN_QUALITY: u8 = 20;
+/// Factor applied to quality, then to each dimension, per cap retry
+const CAP_STEP: f32 = 0.8;
 
 /// Main compression engine that coordinates different format encoders
 pub struct CompressionEngine {
     analyzer: ImageAnalyzer,
     memory_pool: Arc<MemoryPool>,
-    #[cfg(feature = "logging")]
-    logger_enabled: bool,
+    logger: Logger,
+    /// Output encoders keyed by format name
+    encoders: HashMap<String, Box<dyn FormatEncoder>>,
+    /// Speed/size trade-off supplying defaults the options leave open
+    profile: Profile,
 }
 
 impl CompressionEngine {
     pub fn new() -> Self {
         // Create memory pool with 4MB buffers and 8 buffers in pool
-        let memory_pool = Arc::new(MemoryPool::new(4 * 1024 * 1024, 8));
-
-        Self {
-            analyzer: ImageAnalyzer::new(),
-            memory_pool,
-            #[cfg(feature = "logging")]
-            logger_enabled: true,
-        }
+        Self::with_memory_pool(4 * 1024 * 1024, 8)
     }
 
     /// Create a new compression engine with custom memory pool settings
     pub fn with_memory_pool(buffer_size: usize, pool_size: usize) -> Self {
         let memory_pool = Arc::new(MemoryPool::new(buffer_size, pool_size));
 
-        Self {
+        let mut engine = Self {
             analyzer: ImageAnalyzer::new(),
             memory_pool,
-            #[cfg(feature = "logging")]
-            logger_enabled: true,
+            logger: Logger::new(),
+            encoders: HashMap::new(),
+            profile: Profile::default(),
+        };
+        for encoder in crate::encoder::builtin_encoders() {
+            engine.register_encoder(encoder);
+        }
+        engine
+    }
+
+    /// Create an engine tuned for speed or output size as a whole.
+    ///
+    /// The profile sets the analysis downscale, the encoder effort used when
+    /// `CompressionOptions::optimize` is `None` (PNG filter search and deflate
+    /// level, WebP `method` unless set in `extra_params`, AVIF speed) and the
+    /// image size from which resizes run in parallel tiles. See [`Profile`].
+    pub fn with_profile(profile: Profile) -> Self {
+        let mut engine = Self::new();
+        engine.profile = profile;
+        if let Some(max_dim) = profile.analysis_max_dim() {
+            engine.analyzer = ImageAnalyzer::with_analysis_downscale(max_dim);
         }
+        engine
+    }
+
+    /// Register an encoder for its [`FormatEncoder::name`].
+    ///
+    /// An encoder registered under an existing name (including a built-in
+    /// format) replaces the previous one.
+    pub fn register_encoder(&mut self, encoder: Box<dyn FormatEncoder>) {
+        self.encoders.insert(encoder.name().to_string(), encoder);
+    }
+
+    /// Capabilities of the encoder registered for `format`.
+    ///
+    /// Aliases such as `jpg` are accepted. Returns `None` when no encoder is
+    /// registered, e.g. because the format's feature is disabled.
+    pub fn format_capabilities(&self, format: &str) -> Option<FormatCapabilities> {
+        self.encoders
+            .get(crate::encoder::canonical_name(format))
+            .map(|encoder| encoder.capabilities())
+    }
+
+    /// Send diagnostic messages to `callback`, independent of the `logging` feature.
+    ///
+    /// The callback receives the same messages the engine (and its analyzer)
+    /// sends to the `log` facade, which lets WASM hosts forward them to
+    /// `console.log`.
+    pub fn set_log_callback(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        self.set_shared_log_callback(Arc::new(callback));
+    }
+
+    pub(crate) fn set_shared_log_callback(&mut self, callback: LogCallback) {
+        self.analyzer.set_log_callback(Arc::clone(&callback));
+        self.logger.set_callback(callback);
+    }
+
+    /// Output formats the engine can encode with the enabled features.
+    ///
+    /// AVIF is never listed on wasm32, where its encoder is not compiled.
+    pub fn supported_formats() -> Vec<String> {
+        [
+            ("png", cfg!(feature = "png")),
+            ("apng", cfg!(feature = "png")),
+            ("jpeg", cfg!(feature = "jpeg")),
+            ("webp", cfg!(feature = "webp")),
+            ("tiff", cfg!(feature = "tiff")),
+            (
+                "avif",
+                cfg!(all(feature = "avif", not(target_arch = "wasm32"))),
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(format, _)| format.to_string())
+        .collect()
+    }
+
+    /// Encode `data` `runs` times and report whether every output is byte-identical.
+    ///
+    /// Runs are sequential so each encode sees the same conditions; fewer
+    /// than two runs are trivially deterministic.
+    pub fn is_deterministic(
+        &self,
+        data: &[u8],
+        options: &CompressionOptions,
+        runs: usize,
+    ) -> Result<bool> {
+        let first = self.compress(data, options)?.data;
+        for _ in 1..runs {
+            if self.compress(data, options)?.data != first {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 
     /// Compress multiple images in parallel with optimal performance
@@ -47,6 +150,17 @@ impl CompressionEngine {
         &self,
         images: Vec<&[u8]>,
         options: &CompressionOptions,
+    ) -> Vec<Result<CompressionResult>> {
+        self.compress_batch_with(images, options, &BatchOptions::default())
+    }
+
+    /// [`compress_batch`](Self::compress_batch) with batch-wide settings such
+    /// as the [`ErrorPolicy`] for images that fail
+    pub fn compress_batch_with(
+        &self,
+        images: Vec<&[u8]>,
+        options: &CompressionOptions,
+        batch: &BatchOptions,
     ) -> Vec<Result<CompressionResult>> {
         let options = Arc::new(options.clone());
 
@@ -55,72 +169,1229 @@ impl CompressionEngine {
             .into_par_iter()
             .map(|data| {
                 let opts = Arc::clone(&options);
-                self.compress_with_optimizations(data, &opts)
+                let result = self.compress_with_optimizations(data, &opts);
+                self.apply_error_policy(data, result, batch.on_error)
+            })
+            .collect()
+    }
+
+    /// Replace a failed result with the untouched input under [`ErrorPolicy::PassThrough`]
+    fn apply_error_policy(
+        &self,
+        data: &[u8],
+        result: Result<CompressionResult>,
+        policy: ErrorPolicy,
+    ) -> Result<CompressionResult> {
+        match (result, policy) {
+            (Err(e), ErrorPolicy::PassThrough) => {
+                log_event!(
+                    self.logger,
+                    Warn,
+                    "Passing {} bytes through unchanged after error: {e}",
+                    data.len()
+                );
+                let format = image::guess_format(data)
+                    .map(|format| crate::analyzer::format_to_string(&format))
+                    .unwrap_or_else(|_| "unknown".to_string());
+                // 头部可读时（如截断的文件）仍报告尺寸，否则为 0x0
+                let (width, height) = image::io::Reader::new(std::io::Cursor::new(data))
+                    .with_guessed_format()
+                    .ok()
+                    .and_then(|reader| reader.into_dimensions().ok())
+                    .unwrap_or((0, 0));
+                Ok(unchanged_result(
+                    data,
+                    ImageMetadata {
+                        width,
+                        height,
+                        format,
+                        color_type: "unknown".to_string(),
+                        bit_depth: 8,
+                        has_transparency: false,
+                        srgb_compatible: true,
+                    },
+                    0,
+                ))
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// Like [`compress_batch`](Self::compress_batch), but stops starting new images once `cancel` is set.
+    ///
+    /// The flag is checked before each image: images already being encoded
+    /// finish normally, every image not yet started yields
+    /// `CompressionError::Cancelled`. Results are returned in input order.
+    pub fn compress_batch_cancellable(
+        &self,
+        images: Vec<&[u8]>,
+        options: &CompressionOptions,
+        cancel: Arc<AtomicBool>,
+    ) -> Vec<Result<CompressionResult>> {
+        images
+            .into_par_iter()
+            .map(|data| {
+                if cancel.load(Ordering::Acquire) {
+                    return Err(CompressionError::Cancelled(
+                        "batch cancelled before this image was started".to_string(),
+                    ));
+                }
+                self.compress_with_optimizations(data, options)
             })
             .collect()
     }
 
+    /// Encode several images as one GIF sharing a single global palette.
+    ///
+    /// The palette is computed once by median cut over the colours of every
+    /// input (at most `max_colors` entries), so sprite sheets and frame
+    /// sequences keep consistent colours and no frame carries a local table.
+    /// Inputs become frames in order; the canvas is sized to the largest one.
+    pub fn compress_gif_shared_palette(
+        &self,
+        images: Vec<&[u8]>,
+        max_colors: u8,
+    ) -> Result<Vec<u8>> {
+        #[cfg(feature = "gif")]
+        {
+            let frames = images
+                .into_iter()
+                .map(|data| ImageAnalyzer::safe_load(data).map(|img| img.to_rgba8()))
+                .collect::<Result<Vec<_>>>()?;
+            log_event!(
+                self.logger,
+                Debug,
+                "Encoding {} images into a shared-palette GIF",
+                frames.len()
+            );
+            crate::formats::gif::encode_shared_palette(&frames, max_colors as usize)
+        }
+        #[cfg(not(feature = "gif"))]
+        {
+            let _ = (images, max_colors);
+            Err(CompressionError::UnsupportedFeature(
+                "GIF format requires the `gif` feature".to_string(),
+            ))
+        }
+    }
+
+    /// Build a multi-resolution ICO (favicon) from one source image.
+    ///
+    /// Every entry in `sizes` (1-256) becomes a square icon: the source is
+    /// scaled to fit and centred on a transparent canvas, so non-square
+    /// inputs are not distorted.
+    pub fn compress_ico(&self, data: &[u8], sizes: &[u32]) -> Result<Vec<u8>> {
+        let max = crate::formats::ico::MAX_ICON_SIZE;
+        if sizes.is_empty() {
+            return Err(CompressionError::InvalidFormat(
+                "ICO output needs at least one size".to_string(),
+            ));
+        }
+        if let Some(size) = sizes.iter().find(|&&size| size == 0 || size > max) {
+            return Err(CompressionError::InvalidFormat(format!(
+                "ICO sizes must be 1-{max}, got {size}"
+            )));
+        }
+
+        let img = ImageAnalyzer::safe_load(data)?;
+        log_event!(
+            self.logger,
+            Debug,
+            "Encoding {}x{} source as ICO with sizes {sizes:?}",
+            img.width(),
+            img.height()
+        );
+        let icons: Vec<_> = sizes
+            .par_iter()
+            .map(|&size| {
+                let scaled = img
+                    .resize(size, size, image::imageops::FilterType::Lanczos3)
+                    .to_rgba8();
+                let mut canvas = image::RgbaImage::new(size, size);
+                let x = (size - scaled.width()) / 2;
+                let y = (size - scaled.height()) / 2;
+                image::imageops::overlay(&mut canvas, &scaled, x as i64, y as i64);
+                canvas
+            })
+            .collect();
+        crate::formats::ico::encode(&icons)
+    }
+
+    /// Compress files on disk and write the results into `out_dir`.
+    ///
+    /// Each output keeps the input's file stem and takes the extension of the
+    /// encoded format. `out_dir` is created if missing; when a name is already
+    /// taken a numeric suffix is appended (`photo-1.webp`, `photo-2.webp`, ...).
+    /// Results are returned in input order.
+    pub fn compress_paths_to_dir(
+        &self,
+        paths: &[PathBuf],
+        out_dir: &Path,
+        options: &CompressionOptions,
+    ) -> Vec<Result<PathBuf>> {
+        self.compress_paths_to_dir_with(paths, out_dir, options, &BatchOptions::default())
+    }
+
+    /// [`compress_paths_to_dir`](Self::compress_paths_to_dir) with batch-wide
+    /// settings. Inputs passed through by [`ErrorPolicy::PassThrough`] keep
+    /// their original extension; files that cannot be read still fail.
+    pub fn compress_paths_to_dir_with(
+        &self,
+        paths: &[PathBuf],
+        out_dir: &Path,
+        options: &CompressionOptions,
+        batch: &BatchOptions,
+    ) -> Vec<Result<PathBuf>> {
+        if let Err(e) = std::fs::create_dir_all(out_dir) {
+            let message = e.to_string();
+            return paths
+                .iter()
+                .map(|_| {
+                    Err(CompressionError::IoError(std::io::Error::new(
+                        e.kind(),
+                        message.clone(),
+                    )))
+                })
+                .collect();
+        }
+
+        // 并行读取与压缩，命名与写入串行进行以保证冲突处理确定
+        let compressed: Vec<Result<CompressionResult>> = paths
+            .par_iter()
+            .map(|path| {
+                let data = std::fs::read(path)?;
+                let result = self.compress_with_optimizations(&data, options);
+                self.apply_error_policy(&data, result, batch.on_error)
+            })
+            .collect();
+
+        let mut taken = std::collections::HashSet::new();
+        paths
+            .iter()
+            .zip(compressed)
+            .map(|(path, result)| {
+                let result = result?;
+                let stem = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "image".to_string());
+                let extension = output_extension(&result, path);
+
+                let mut target = out_dir.join(format!("{stem}.{extension}"));
+                let mut suffix = 1;
+                while taken.contains(&target) || target.exists() {
+                    target = out_dir.join(format!("{stem}-{suffix}.{extension}"));
+                    suffix += 1;
+                }
+
+                std::fs::write(&target, &result.data)?;
+                taken.insert(target.clone());
+                Ok(target)
+            })
+            .collect()
+    }
+
+    /// Compress every input and pack the results into a ZIP archive.
+    ///
+    /// Each entry keeps its input name with the extension replaced by the
+    /// output format's (`photos/a.png` becomes `photos/a.webp`); clashing
+    /// names get a numeric suffix. Names are stored as relative
+    /// `/`-separated paths: a leading root or drive is dropped, `\` counts
+    /// as a separator, and names containing `..` are rejected. Entries use
+    /// the stored method, since the images are already compressed, and each
+    /// is written to the archive as soon as it is encoded. The first failing
+    /// input aborts the archive.
+    pub fn compress_to_zip(
+        &self,
+        named_inputs: Vec<(String, &[u8])>,
+        options: &CompressionOptions,
+    ) -> Result<Vec<u8>> {
+        let mut zip = crate::formats::zip::ZipWriter::new();
+        let mut taken = std::collections::HashSet::new();
+        for (name, data) in named_inputs {
+            let entry_path = zip_entry_path(&name)?;
+            let (dir, file) = match entry_path.rsplit_once('/') {
+                Some((dir, file)) => (Some(dir), file),
+                None => (None, entry_path.as_str()),
+            };
+            let result = self.compress_with_optimizations(data, options)?;
+            let extension = output_extension(&result, Path::new(file));
+            let stem = Path::new(file)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let in_dir = |file: String| match dir {
+                Some(dir) => format!("{dir}/{file}"),
+                None => file,
+            };
+
+            let mut entry = in_dir(format!("{stem}.{extension}"));
+            let mut suffix = 1;
+            while taken.contains(&entry) {
+                entry = in_dir(format!("{stem}-{suffix}.{extension}"));
+                suffix += 1;
+            }
+
+            log_event!(
+                self.logger,
+                Debug,
+                "Adding {entry} ({} bytes) to ZIP",
+                result.data.len()
+            );
+            zip.add_stored(&entry, &result.data)?;
+            taken.insert(entry);
+        }
+        zip.finish()
+    }
+
     /// Compress an image with the given options
     pub fn compress(&self, data: &[u8], options: &CompressionOptions) -> Result<CompressionResult> {
         self.compress_with_optimizations(data, options)
     }
 
+    /// Like [`Self::compress`], also returning the processed image that was encoded.
+    ///
+    /// The image is the pipeline's own post-crop/resize/adjust buffer, so
+    /// chained steps (e.g. watermarking a preview) need no decode of the
+    /// output. For APNG output it is the first frame.
+    pub fn compress_returning_image(
+        &self,
+        data: &[u8],
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        self.compress_pipeline(data, options)
+    }
+
+    /// Decode once and compress `tile_size` x `tile_size` tiles in parallel.
+    ///
+    /// Tiles are laid out row by row from the top-left corner; tiles on the
+    /// right and bottom edges may be smaller. Each entry carries the tile's
+    /// pixel offset `(x, y)` in the source. Every tile runs through the full
+    /// pipeline with `options`, and its `original_size` is its raw pixel size.
+    pub fn compress_tiles(
+        &self,
+        data: &[u8],
+        tile_size: u32,
+        options: &CompressionOptions,
+    ) -> Result<Vec<(u32, u32, CompressionResult)>> {
+        if tile_size == 0 {
+            return Err(CompressionError::InvalidFormat(
+                "Tile size must be at least 1".to_string(),
+            ));
+        }
+        let mut clock = StageClock::start();
+        let (img, analysis) = self.decode_and_analyze(data, options, &mut clock)?;
+        let (width, height) = img.dimensions();
+
+        let origins: Vec<(u32, u32)> = (0..height)
+            .step_by(tile_size as usize)
+            .flat_map(|y| (0..width).step_by(tile_size as usize).map(move |x| (x, y)))
+            .collect();
+        log_event!(
+            self.logger,
+            Debug,
+            "Splitting {width}x{height} image into {} tiles of {tile_size}px",
+            origins.len()
+        );
+
+        origins
+            .into_par_iter()
+            .map(|(x, y)| {
+                let tile = img.crop_imm(x, y, tile_size.min(width - x), tile_size.min(height - y));
+                let tile_analysis = self.analyzer.analyze_decoded(&tile, &analysis.format);
+                let raw_size = tile.as_bytes().len();
+                self.compress_decoded(tile, tile_analysis, data, raw_size, clock, options)
+                    .map(|result| (x, y, result))
+            })
+            .collect()
+    }
+
+    /// Encode the image in each of `formats` and report size, SSIM and timing.
+    ///
+    /// SSIM is measured on the decoded output against the decoded source, so
+    /// results can feed a "choose the best format" UI directly.
+    pub fn compare_formats(
+        &self,
+        data: &[u8],
+        formats: &[String],
+        quality: u8,
+    ) -> Result<Vec<FormatComparison>> {
+        let original = crate::analyzer::decode_image(data)?;
+
+        formats
+            .iter()
+            .map(|format| {
+                let options = CompressionOptions {
+                    format: Some(format.clone()),
+                    quality: Some(quality),
+                    ..Default::default()
+                };
+
+                let start = Instant::now();
+                let result = self.compress_with_optimizations(data, &options)?;
+                let encode_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+                let decoded = image::load_from_memory(&result.data)?;
+                let ssim = crate::metrics::ssim(&original, &decoded)?;
+
+                Ok(FormatComparison {
+                    format: result.format,
+                    size: result.compressed_size,
+                    ratio: result.compression_ratio,
+                    ssim,
+                    encode_ms,
+                })
+            })
+            .collect()
+    }
+
+    /// Convert an image to another format, choosing encoder settings from the source.
+    ///
+    /// Lossless sources (PNG, GIF, BMP, TIFF, lossless WebP) are converted
+    /// losslessly. Lossy sources (JPEG, lossy WebP) are re-encoded lossily at
+    /// no more than their own quality, since a higher setting only spends bytes
+    /// on artifacts that are already baked in.
+    pub fn convert(&self, data: &[u8], to_format: &str) -> Result<CompressionResult> {
+        let options = self.conversion_options(data, to_format)?;
+        self.compress_with_optimizations(data, &options)
+    }
+
+    /// Re-encode with optimization enabled, keeping the detected source format.
+    ///
+    /// Convenient for "optimize in place" flows where the file type must not
+    /// change: callers don't need to detect and pass the format themselves.
+    pub fn optimize_same_format(&self, data: &[u8], quality: u8) -> Result<CompressionResult> {
+        let source_format = crate::analyzer::format_to_string(&self.analyzer.detect_format(data)?);
+        log_event!(
+            self.logger,
+            Debug,
+            "Optimizing {source_format} in place at quality {quality}"
+        );
+
+        let options = CompressionOptions {
+            format: Some(source_format),
+            quality: Some(quality),
+            optimize: Some(OptimizeOptions {
+                colors: true,
+                progressive: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        self.compress_with_optimizations(data, &options)
+    }
+
+    /// Fast, low-effort encode for interactive previews (e.g. a quality slider).
+    ///
+    /// Uses the fastest encoder settings — PNG fast deflate without filter
+    /// search, WebP method 0, AVIF speed 10 — and, with `max_side`, first
+    /// shrinks the image to fit within that many pixels per side. Output is
+    /// larger than a regular encode at the same quality, so this is meant
+    /// for previews only, not for final output.
+    pub fn compress_preview(
+        &self,
+        data: &[u8],
+        format: &str,
+        quality: u8,
+        max_side: Option<u32>,
+    ) -> Result<CompressionResult> {
+        let fastest = Profile::FastestEncode;
+        let options = CompressionOptions {
+            format: Some(format.to_string()),
+            quality: Some(quality),
+            resize: max_side.map(|side| ResizeOptions {
+                width: Some(side),
+                height: Some(side),
+                fit: "contain".to_string(),
+                ..Default::default()
+            }),
+            optimize: Some(fastest.optimize_options()),
+            allow_upscale: false,
+            extra_params: fastest
+                .webp_method()
+                .map(|method| ("method".to_string(), method.to_string()))
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        self.compress_with_optimizations(data, &options)
+    }
+
+    /// Analyze and compress in one pass, returning both results.
+    ///
+    /// The source is decoded once and shared by both stages, where calling
+    /// [`ImageAnalyzer::analyze`] and [`Self::compress`] separately decodes it
+    /// twice. `skip_if_savings_below` and APNG output are not applied.
+    pub fn analyze_and_compress(
+        &self,
+        data: &[u8],
+        options: &CompressionOptions,
+    ) -> Result<(crate::ImageAnalysis, CompressionResult)> {
+        let mut clock = StageClock::start();
+        let (img, analysis) = self.decode_and_analyze(data, options, &mut clock)?;
+        check_deadline(clock.start, options)?;
+        let result =
+            self.compress_decoded(img, analysis.clone(), data, data.len(), clock, options)?;
+        Ok((analysis, result))
+    }
+
+    /// Produce one encoded variant per requested width (e.g. for `srcset`).
+    ///
+    /// The source is decoded once. Each variant keeps the aspect ratio and is
+    /// never upscaled: widths beyond the source width yield the source width.
+    /// Any `resize` in `options` is replaced by the per-variant width.
+    pub fn compress_responsive(
+        &self,
+        data: &[u8],
+        widths: &[u32],
+        options: &CompressionOptions,
+    ) -> Result<Vec<CompressionResult>> {
+        let (img, analysis) = self.decode_and_analyze(data, options, &mut StageClock::start())?;
+        let source_width = img.width();
+
+        widths
+            .par_iter()
+            .map(|&width| {
+                let variant_options = CompressionOptions {
+                    resize: Some(ResizeOptions {
+                        width: Some(width.min(source_width)),
+                        height: None,
+                        fit: "inside".to_string(),
+                        ..Default::default()
+                    }),
+                    ..options.clone()
+                };
+                self.compress_decoded(
+                    img.clone(),
+                    analysis.clone(),
+                    data,
+                    data.len(),
+                    StageClock::start(),
+                    &variant_options,
+                )
+            })
+            .collect()
+    }
+
+    /// Compress raw 8-bit pixels, skipping container decode and format detection.
+    ///
+    /// `channels` selects the layout: 1 = gray, 2 = gray+alpha, 3 = RGB,
+    /// 4 = RGBA. `pixels` must hold exactly `width * height * channels` bytes.
+    pub fn compress_raw(
+        &self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        channels: u8,
+        options: &CompressionOptions,
+    ) -> Result<CompressionResult> {
+        let mut clock = StageClock::start();
+        let expected = width as usize * height as usize * channels as usize;
+        if pixels.len() != expected {
+            return Err(CompressionError::InvalidFormat(format!(
+                "Raw buffer has {} bytes, expected {expected} for {width}x{height}x{channels}",
+                pixels.len()
+            )));
+        }
+
+        let buffer = pixels.to_vec();
+        let img = match channels {
+            1 => image::GrayImage::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+            2 => image::GrayAlphaImage::from_raw(width, height, buffer)
+                .map(DynamicImage::ImageLumaA8),
+            3 => image::RgbImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8),
+            4 => image::RgbaImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+            _ => {
+                return Err(CompressionError::InvalidFormat(format!(
+                    "Unsupported channel count {channels}; expected 1-4"
+                )))
+            }
+        }
+        .ok_or_else(|| CompressionError::InvalidFormat("Invalid raw image buffer".to_string()))?;
+        clock.decoded = clock.elapsed_ms();
+
+        log_event!(
+            self.logger,
+            Debug,
+            "Starting raw compression of {width}x{height}x{channels} pixels"
+        );
+
+        let analysis = self.analyzer.analyze_decoded(&img, "raw");
+        clock.analyzed = clock.elapsed_ms();
+        self.compress_decoded(img, analysis, &[], pixels.len(), clock, options)
+    }
+
+    /// Sanitize an upload with the default [`NormalizeOptions`]: auto-orient,
+    /// strip metadata, fit to 4MP and re-encode as WebP quality 82.
+    pub fn normalize(&self, data: &[u8]) -> Result<CompressionResult> {
+        self.normalize_with(data, &NormalizeOptions::default())
+    }
+
+    /// Run the normalization pipeline with custom preset settings
+    pub fn normalize_with(
+        &self,
+        data: &[u8],
+        preset: &NormalizeOptions,
+    ) -> Result<CompressionResult> {
+        let (mut width, mut height) = image::io::Reader::new(std::io::Cursor::new(data))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        // 方向 5-8 含 90° 旋转，像素上限按旋转后的宽高计算
+        if preset.auto_orient && matches!(crate::exif::orientation(data), Some(5..=8)) {
+            std::mem::swap(&mut width, &mut height);
+        }
+
+        let pixels = width as u64 * height as u64;
+        let resize = preset.max_pixels.filter(|&max| pixels > max).map(|max| {
+            let scale = (max as f64 / pixels as f64).sqrt();
+            ResizeOptions {
+                width: Some(((width as f64 * scale).round() as u32).max(1)),
+                height: Some(((height as f64 * scale).round() as u32).max(1)),
+                fit: "contain".to_string(),
+                ..Default::default()
+            }
+        });
+
+        let options = CompressionOptions {
+            format: Some(preset.format.clone()),
+            quality: Some(preset.quality),
+            resize,
+            auto_orient: preset.auto_orient,
+            ..Default::default()
+        };
+        self.compress_with_optimizations(data, &options)
+    }
+
+    /// Scale to roughly `target_pixels` total pixels, keeping the aspect ratio, then encode.
+    ///
+    /// Equal area keeps file sizes of mixed-aspect galleries comparable,
+    /// where equal widths would not. The computed size replaces the
+    /// dimensions and fit of `options.resize` (its filters are kept); with
+    /// `allow_upscale` false smaller images are left as they are.
+    pub fn compress_to_area(
+        &self,
+        data: &[u8],
+        target_pixels: u64,
+        options: &CompressionOptions,
+    ) -> Result<CompressionResult> {
+        let (mut width, mut height) = image::io::Reader::new(std::io::Cursor::new(data))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        if options.auto_orient && matches!(crate::exif::orientation(data), Some(5..=8)) {
+            std::mem::swap(&mut width, &mut height);
+        }
+
+        let pixels = (width as u64 * height as u64).max(1);
+        let scale = (target_pixels as f64 / pixels as f64).sqrt();
+        let target_width = ((width as f64 * scale).round() as u32).max(1);
+        let target_height = ((height as f64 * scale).round() as u32).max(1);
+        log_event!(
+            self.logger,
+            Debug,
+            "Scaling {width}x{height} to {target_width}x{target_height} for ~{target_pixels} pixels"
+        );
+
+        let options = CompressionOptions {
+            resize: Some(ResizeOptions {
+                width: Some(target_width),
+                height: Some(target_height),
+                fit: "fill".to_string(),
+                ..options.resize.clone().unwrap_or_default()
+            }),
+            ..options.clone()
+        };
+        self.compress_with_optimizations(data, &options)
+    }
+
+    /// Build the options `convert` uses for the given source data
+    fn conversion_options(&self, data: &[u8], to_format: &str) -> Result<CompressionOptions> {
+        let source_format = crate::analyzer::format_to_string(&self.analyzer.detect_format(data)?);
+
+        let source_quality = match source_format.as_str() {
+            "jpeg" => Some(self.analyzer.estimate_jpeg_quality(data).unwrap_or(85)),
+            // 简单格式 RIFF....WEBPVP8 ：'VP8 ' 为有损，'VP8L' 为无损
+            "webp" if !data.windows(4).take(64).any(|w| w == b"VP8L") => Some(85),
+            _ => None,
+        };
+
+        log_event!(
+            self.logger,
+            Debug,
+            "Converting {source_format} -> {to_format} ({})",
+            match source_quality {
+                Some(q) => format!("lossy, quality {q}"),
+                None => "lossless".to_string(),
+            }
+        );
+
+        Ok(CompressionOptions {
+            format: Some(to_format.to_string()),
+            quality: Some(source_quality.unwrap_or(100)),
+            optimize: Some(OptimizeOptions {
+                lossless: source_quality.is_none(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+    }
+
     /// Internal compression method with performance optimizations
     fn compress_with_optimizations(
         &self,
         data: &[u8],
         options: &CompressionOptions,
     ) -> Result<CompressionResult> {
-        let start_time = Instant::now();
+        self.compress_pipeline(data, options)
+            .map(|(result, _)| result)
+    }
+
+    /// Full container pipeline, also returning the processed image
+    fn compress_pipeline(
+        &self,
+        data: &[u8],
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        let mut clock = StageClock::start();
         let original_size = data.len();
 
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::debug!("Starting compression of {original_size} bytes");
+        log_event!(
+            self.logger,
+            Debug,
+            "Starting compression of {original_size} bytes"
+        );
+
+        let (img, analysis) = self.decode_and_analyze(data, options, &mut clock)?;
+        check_deadline(clock.start, options)?;
+        if let Some(threshold) = options.skip_if_savings_below {
+            if analysis.estimated_savings < threshold {
+                log_event!(
+                    self.logger,
+                    Info,
+                    "Estimated savings {:.1}% are below {:.1}%; returning the input unchanged",
+                    analysis.estimated_savings * 100.0,
+                    threshold * 100.0
+                );
+                let result = unchanged_result(data, analysis.metadata, clock.elapsed_ms());
+                return Ok((result, img));
+            }
+        }
+        let source = if options.format.as_deref() == Some("apng") {
+            DecodedSource::Frames(self.analyzer.decode_frames(data)?)
+        } else {
+            DecodedSource::Still(img)
+        };
+        self.encode_capped(source, analysis, data, original_size, clock, options)
+    }
+
+    /// Encode a decoded source, enforcing `max_output_bytes` when set.
+    ///
+    /// Retries re-run only the pixel transforms and the encode on `source`
+    /// and share `clock`, so `per_image_timeout` covers all attempts.
+    fn encode_capped(
+        &self,
+        source: DecodedSource,
+        analysis: crate::ImageAnalysis,
+        data: &[u8],
+        original_size: usize,
+        clock: StageClock,
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        let Some(cap) = options.max_output_bytes else {
+            return self.encode_source(source, analysis, data, original_size, clock, options);
+        };
+        let encode = |attempt: &CompressionOptions| {
+            self.encode_source(
+                source.clone(),
+                analysis.clone(),
+                data,
+                original_size,
+                clock,
+                attempt,
+            )
+        };
+        let (result, img) = encode(options)?;
+        if result.compressed_size <= cap {
+            return Ok((result, img));
+        }
+        self.compress_under_cap(options, cap, result, clock.start, encode)
+    }
+
+    /// Retry an encode that exceeded `cap` at lower quality, then lower resolution
+    fn compress_under_cap(
+        &self,
+        options: &CompressionOptions,
+        cap: usize,
+        first: CompressionResult,
+        start: Instant,
+        encode: impl Fn(&CompressionOptions) -> Result<(CompressionResult, DynamicImage)>,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        log_event!(
+            self.logger,
+            Info,
+            "Output of {} bytes exceeds the {cap} byte cap; retrying",
+            first.compressed_size
+        );
+        // 固定为首次选定的格式，避免自动选择在重试间切换
+        let mut attempt = CompressionOptions {
+            format: Some(first.format.clone()),
+            max_output_bytes: None,
+            ..options.clone()
+        };
+
+        // 先降低质量；体积不再变化（无损格式忽略质量）时转入降分辨率
+        let mut quality = options.quality.unwrap_or(CAP_START_QUALITY);
+        let mut previous_size = first.compressed_size;
+        while quality > CAP_MIN_QUALITY {
+            check_deadline(start, options)?;
+            quality = ((quality as f32 * CAP_STEP) as u8).max(CAP_MIN_QUALITY);
+            attempt.quality = Some(quality);
+            let (result, img) = encode(&attempt)?;
+            log_event!(
+                self.logger,
+                Debug,
+                "Cap retry at quality {quality}: {} bytes",
+                result.compressed_size
+            );
+            if result.compressed_size <= cap {
+                return Ok((result, img));
+            }
+            if result.compressed_size == previous_size {
+                break;
+            }
+            previous_size = result.compressed_size;
+        }
+
+        let (mut width, mut height) = (first.output_width, first.output_height);
+        while width > 1 || height > 1 {
+            check_deadline(start, options)?;
+            width = ((width as f32 * CAP_STEP) as u32).max(1);
+            height = ((height as f32 * CAP_STEP) as u32).max(1);
+            attempt.resize = Some(ResizeOptions {
+                width: Some(width),
+                height: Some(height),
+                fit: "fill".to_string(),
+                ..options.resize.clone().unwrap_or_default()
+            });
+            let (result, img) = encode(&attempt)?;
+            log_event!(
+                self.logger,
+                Debug,
+                "Cap retry at {width}x{height}: {} bytes",
+                result.compressed_size
+            );
+            if result.compressed_size <= cap {
+                return Ok((result, img));
+            }
         }
 
-        // Load and analyze the image
-        let img = image::load_from_memory(data)?;
-        let analysis = self.analyzer.analyze(data)?;
+        Err(CompressionError::EncodingError(format!(
+            "Cannot encode {} output within {cap} bytes",
+            first.format
+        )))
+    }
+
+    /// One encode of a decoded source: still images go through
+    /// [`Self::compress_decoded_image`], frames through [`Self::compress_to_apng`]
+    fn encode_source(
+        &self,
+        source: DecodedSource,
+        analysis: crate::ImageAnalysis,
+        data: &[u8],
+        original_size: usize,
+        clock: StageClock,
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        match source {
+            DecodedSource::Still(img) => {
+                self.compress_decoded_image(img, analysis, data, original_size, clock, options)
+            }
+            DecodedSource::Frames(frames) => {
+                self.compress_to_apng(frames, analysis, original_size, clock, options)
+            }
+        }
+    }
+
+    /// Re-encode every frame of the source as an animated PNG.
+    ///
+    /// The returned image is the first processed frame.
+    fn compress_to_apng(
+        &self,
+        frames: Vec<image::Frame>,
+        analysis: crate::ImageAnalysis,
+        original_size: usize,
+        mut clock: StageClock,
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        let mut tile_count = 0;
+        let mut upscaled = false;
+        let deadline = deadline(clock.start, options);
+        // 所有帧共用一个裁剪框（各帧内容范围的并集），保证帧尺寸一致
+        let crop = if options.autocrop {
+            frames
+                .iter()
+                .map(|frame| {
+                    autocrop_bounds(&DynamicImage::ImageRgba8(frame.buffer().clone()), options)
+                })
+                .reduce(|(x0, y0, w0, h0), (x1, y1, w1, h1)| {
+                    let (left, top) = (x0.min(x1), y0.min(y1));
+                    let (right, bottom) = ((x0 + w0).max(x1 + w1), (y0 + h0).max(y1 + h1));
+                    (left, top, right - left, bottom - top)
+                })
+        } else {
+            None
+        };
+        let frames = frames
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                let img =
+                    prepare_source(DynamicImage::ImageRgba8(frame.into_buffer()), crop, options);
+                let (resized, tiles) = self.apply_resize_optimized(
+                    &img,
+                    &options.resize,
+                    options.allow_upscale,
+                    deadline,
+                )?;
+                tile_count = tile_count.max(tiles);
+                upscaled |= resized.width() > img.width() || resized.height() > img.height();
+                let img = finish_pixels(resized, options)?;
+                check_deadline(clock.start, options)?;
+                Ok(image::Frame::from_parts(img.to_rgba8(), 0, 0, delay))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let frames = match options.elide_similar_frames {
+            Some(threshold) => {
+                let before = frames.len();
+                let kept = elide_similar_frames(frames, threshold)?;
+                log_event!(
+                    self.logger,
+                    Debug,
+                    "Elided {} near-duplicate frame(s) (SSIM > {threshold})",
+                    before - kept.len()
+                );
+                kept
+            }
+            None => frames,
+        };
+        clock.resized = clock.elapsed_ms();
+
+        log_event!(
+            self.logger,
+            Debug,
+            "Encoding {} frame(s) as APNG",
+            frames.len()
+        );
+
+        let first = DynamicImage::ImageRgba8(
+            frames
+                .first()
+                .ok_or_else(|| CompressionError::InvalidFormat("Source has no frames".to_string()))?
+                .buffer()
+                .clone(),
+        );
+        let output = EncodedOutput {
+            width: first.width(),
+            height: first.height(),
+            raw_size: frames
+                .iter()
+                .map(|frame| frame.buffer().as_raw().len())
+                .sum(),
+            tile_count,
+            upscaled,
+        };
+        let mut compressed_data =
+            crate::formats::png::encode_apng(&frames, ::png::Compression::Best, deadline)?;
+        if options.assume_srgb {
+            compressed_data = self.tag_srgb(compressed_data, &analysis.metadata)?;
+        }
+        if options.verify_output {
+            self.verify_output(&compressed_data, output.width, output.height)?;
+        }
+        let result = self.finish_result(
+            compressed_data,
+            "apng".to_string(),
+            original_size,
+            output,
+            clock,
+            analysis.metadata,
+        );
+        Ok((result, first))
+    }
+
+    /// Copy the source's EXIF and XMP into WebP output
+    fn carry_webp_metadata(
+        &self,
+        webp: Vec<u8>,
+        source: &[u8],
+        img: &DynamicImage,
+        options: &CompressionOptions,
+    ) -> Result<Vec<u8>> {
+        let xmp = crate::exif::source_xmp(source);
+        // 已按方向旋转过像素时，复制的方向标签需复位，避免查看器再次旋转
+        let exif = crate::exif::source_exif(source).map(|exif| {
+            if options.auto_orient {
+                crate::exif::with_upright_orientation(exif)
+            } else {
+                exif.to_vec()
+            }
+        });
+        if exif.is_none() && xmp.is_none() {
+            return Ok(webp);
+        }
+        log_event!(
+            self.logger,
+            Debug,
+            "Carrying source metadata into WebP (EXIF: {}, XMP: {})",
+            exif.is_some(),
+            xmp.is_some()
+        );
+        crate::exif::embed_webp_metadata(
+            &webp,
+            exif.as_deref(),
+            xmp,
+            (img.width(), img.height(), img.color().has_alpha()),
+        )
+    }
+
+    /// Re-decode freshly encoded bytes and check they have the expected size
+    fn verify_output(&self, data: &[u8], width: u32, height: u32) -> Result<()> {
+        let decoded = ImageAnalyzer::safe_load(data).map_err(|e| {
+            CompressionError::EncodingError(format!("Encoded output failed to decode: {e}"))
+        })?;
+        if decoded.dimensions() != (width, height) {
+            return Err(CompressionError::EncodingError(format!(
+                "Encoded output is {}x{}, expected {width}x{height}",
+                decoded.width(),
+                decoded.height()
+            )));
+        }
+        log_event!(
+            self.logger,
+            Debug,
+            "Verified {width}x{height} output decodes"
+        );
+        Ok(())
+    }
+
+    /// Decode, analyze and (optionally) auto-orient container input,
+    /// marking the decode and analysis stages on `clock`
+    fn decode_and_analyze(
+        &self,
+        data: &[u8],
+        options: &CompressionOptions,
+        clock: &mut StageClock,
+    ) -> Result<(DynamicImage, crate::ImageAnalysis)> {
+        let img = ImageAnalyzer::safe_load(data)?;
+        clock.decoded = clock.elapsed_ms();
+
+        // 分析复用已解码的图像（描述源文件，因此在方向校正之前进行）
+        let analysis = self.analyzer.analyze_loaded(data, &img)?;
+        let analyzed = clock.elapsed_ms();
+
+        let mut img = img;
+        if options.auto_orient {
+            if let Some(orientation) = crate::exif::orientation(data) {
+                img = crate::exif::apply_orientation(img, orientation);
+            }
+        }
+        // 方向校正计入解码阶段
+        let now = clock.elapsed_ms();
+        clock.decoded += now - analyzed;
+        clock.analyzed = now;
+
+        Ok((img, analysis))
+    }
+
+    /// Encode pipeline shared by container input and raw pixel buffers.
+    ///
+    /// `source` is the container the image was decoded from (empty for raw
+    /// pixels); only its metadata is read.
+    fn compress_decoded(
+        &self,
+        img: DynamicImage,
+        analysis: crate::ImageAnalysis,
+        source: &[u8],
+        original_size: usize,
+        clock: StageClock,
+        options: &CompressionOptions,
+    ) -> Result<CompressionResult> {
+        let img = DecodedSource::Still(img);
+        self.encode_capped(img, analysis, source, original_size, clock, options)
+            .map(|(result, _)| result)
+    }
+
+    /// [`Self::compress_decoded`], also returning the processed image
+    fn compress_decoded_image(
+        &self,
+        mut img: DynamicImage,
+        analysis: crate::ImageAnalysis,
+        source: &[u8],
+        original_size: usize,
+        mut clock: StageClock,
+        options: &CompressionOptions,
+    ) -> Result<(CompressionResult, DynamicImage)> {
+        let crop = options.autocrop.then(|| autocrop_bounds(&img, options));
+        img = prepare_source(img, crop, options);
 
         // Determine target format
-        let target_format = self.determine_target_format(options, &analysis);
+        let mut target_format = self.determine_target_format(options, &analysis);
+        // 分析发生在抠色之前；自动选择的格式必须能保留抠出的透明度
+        if options.chroma_key.is_some() && is_auto_format(options) {
+            target_format = self.alpha_capable_format(target_format);
+        }
+        let options = &self.clamp_quality(options, &target_format);
+
+        // 4:2:0 输出要求偶数尺寸，缩放时自动对齐
+        let resize = match &options.resize {
+            Some(resize) if !resize.snap_even && subsamples_chroma(&target_format, options) => {
+                Some(ResizeOptions {
+                    snap_even: true,
+                    ..resize.clone()
+                })
+            }
+            other => other.clone(),
+        };
 
         // Apply resize if specified with memory optimization
-        let processed_img = self.apply_resize_optimized(&img, &options.resize)?;
+        let (processed_img, tile_count) = self.apply_resize_optimized(
+            &img,
+            &resize,
+            options.allow_upscale,
+            deadline(clock.start, options),
+        )?;
+        let mut processed_img = finish_pixels(processed_img, options)?;
+        if options.preprocess_color {
+            processed_img = self.apply_simd_color_optimization(&processed_img, &target_format)?;
+        }
+        clock.resized = clock.elapsed_ms();
+        check_deadline(clock.start, options)?;
 
         // Perform compression with SIMD optimizations
-        let compressed_data =
-            self.compress_to_format_optimized(&processed_img, &target_format, options)?;
+        let compressed_data = self.compress_to_format_optimized(
+            &processed_img,
+            &target_format,
+            options,
+            deadline(clock.start, options),
+        )?;
 
-        let processing_time = start_time.elapsed().as_millis() as u64;
+        #[cfg(feature = "jpeg")]
+        let compressed_data = if options.regenerate_embedded_thumbnail
+            && matches!(target_format.as_str(), "jpeg" | "jpg")
+        {
+            let thumbnail = crate::formats::jpeg::encode(
+                &processed_img.thumbnail(
+                    processed_img.width().min(160),
+                    processed_img.height().min(160),
+                ),
+                &crate::formats::jpeg::JpegOptions {
+                    quality: 75,
+                    ..Default::default()
+                },
+            )?;
+            crate::exif::embed_thumbnail(&compressed_data, &thumbnail)?
+        } else {
+            compressed_data
+        };
+        let compressed_data = if options.assume_srgb && target_format == "png" {
+            self.tag_srgb(compressed_data, &analysis.metadata)?
+        } else {
+            compressed_data
+        };
+        let compressed_data = if options.preserve_metadata && target_format == "webp" {
+            self.carry_webp_metadata(compressed_data, source, &processed_img, options)?
+        } else {
+            compressed_data
+        };
+        check_deadline(clock.start, options)?;
+
+        let output = EncodedOutput {
+            width: processed_img.width(),
+            height: processed_img.height(),
+            raw_size: processed_img.width() as usize
+                * processed_img.height() as usize
+                * processed_img.color().bytes_per_pixel() as usize,
+            tile_count,
+            upscaled: processed_img.width() > img.width() || processed_img.height() > img.height(),
+        };
+        if options.verify_output {
+            self.verify_output(&compressed_data, output.width, output.height)?;
+        }
+        let result = self.finish_result(
+            compressed_data,
+            target_format,
+            original_size,
+            output,
+            clock,
+            analysis.metadata,
+        );
+        Ok((result, processed_img))
+    }
+
+    /// Add an `sRGB` chunk to PNG output unless the source declared another colour space
+    fn tag_srgb(&self, png: Vec<u8>, metadata: &ImageMetadata) -> Result<Vec<u8>> {
+        if !metadata.srgb_compatible {
+            log_event!(
+                self.logger,
+                Warn,
+                "Source has a non-sRGB ICC profile; not tagging output as sRGB"
+            );
+            return Ok(png);
+        }
+        crate::icc::tag_srgb_png(&png)
+    }
+
+    /// Wrap encoded bytes into a [`CompressionResult`] and log the outcome
+    fn finish_result(
+        &self,
+        compressed_data: Vec<u8>,
+        format: String,
+        original_size: usize,
+        output: EncodedOutput,
+        clock: StageClock,
+        metadata: ImageMetadata,
+    ) -> CompressionResult {
+        let processing_time = clock.elapsed_ms();
+        let timings = clock.timings(processing_time);
         let compressed_size = compressed_data.len();
         let compression_ratio = if original_size > 0 {
             compressed_size as f32 / original_size as f32
         } else {
             1.0
         };
+        let ratio_vs_raw = if output.raw_size > 0 {
+            compressed_size as f32 / output.raw_size as f32
+        } else {
+            1.0
+        };
 
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::info!(
-                "Compression complete: {} -> {} bytes ({:.1}% of original) in {}ms",
-                original_size,
-                compressed_size,
-                compression_ratio * 100.0,
-                processing_time
-            );
-        }
+        log_event!(
+            self.logger,
+            Info,
+            "Compression complete: {} -> {} bytes ({:.1}% of original) in {}ms",
+            original_size,
+            compressed_size,
+            compression_ratio * 100.0,
+            processing_time
+        );
 
-        Ok(CompressionResult {
+        CompressionResult {
             data: compressed_data,
             original_size,
             compressed_size,
             compression_ratio,
-            format: target_format,
+            ratio_vs_raw,
+            output_width: output.width,
+            output_height: output.height,
+            format,
             processing_time,
-            metadata: analysis.metadata,
-        })
+            timings,
+            metadata,
+            used_tiled_resize: output.tile_count > 0,
+            tile_count: output.tile_count,
+            upscaled: output.upscaled,
+            fell_back: false,
+        }
     }
 
     /// Determine the target format based on options and analysis
@@ -129,48 +1400,195 @@ impl CompressionEngine {
         options: &CompressionOptions,
         analysis: &crate::ImageAnalysis,
     ) -> String {
-        if let Some(ref format) = options.format {
-            if format == "auto" {
-                analysis.recommended_format.clone()
-            } else {
-                format.clone()
+        match &options.format {
+            Some(format) if !is_auto_format(options) => format.clone(),
+            _ => analysis.recommended_format.clone(),
+        }
+    }
+
+    /// `format` if its encoder keeps transparency, else WebP (PNG when WebP
+    /// cannot keep it either)
+    fn alpha_capable_format(&self, format: String) -> String {
+        let keeps_alpha = |name: &str| {
+            self.format_capabilities(name)
+                .is_some_and(|capabilities| capabilities.supports_alpha)
+        };
+        if keeps_alpha(&format) {
+            return format;
+        }
+        let fallback = if keeps_alpha("webp") { "webp" } else { "png" };
+        log_event!(
+            self.logger,
+            Debug,
+            "{format} cannot keep chroma-keyed transparency; using {fallback}"
+        );
+        fallback.to_string()
+    }
+
+    /// Clamp `quality` into the valid range of the target encoder.
+    ///
+    /// Lossy encoders accept 1-100; 0 or values above 100 are clamped (with a
+    /// warning when `logging` is enabled) rather than handed to the encoder.
+    fn clamp_quality(&self, options: &CompressionOptions, format: &str) -> CompressionOptions {
+        let mut options = options.clone();
+
+        if let Some(quality) = options.quality {
+            let (min, max) = match format {
+                "jpeg" | "jpg" | "webp" | "avif" => (1, 100),
+                _ => (0, 100),
+            };
+            let clamped = quality.clamp(min, max);
+
+            if clamped != quality {
+                log_event!(self.logger, Warn, "Quality {quality} is outside the valid range {min}-{max} for {format}; clamped to {clamped}");
+                options.quality = Some(clamped);
             }
-        } else {
-            analysis.recommended_format.clone()
         }
+
+        options
     }
 
-    /// Apply resize operations with memory optimization
+    /// Apply resize operations with memory optimization.
+    ///
+    /// Also returns how many row tiles the parallel large-image path split
+    /// the image into (0 when it did not run).
     fn apply_resize_optimized(
         &self,
         img: &DynamicImage,
         resize_options: &Option<ResizeOptions>,
+        allow_upscale: bool,
+        deadline: Option<Instant>,
+    ) -> Result<(DynamicImage, u32)> {
+        let mut tiles = 0;
+        let resized =
+            self.resize_scaled(img, resize_options, allow_upscale, deadline, &mut tiles)?;
+        let Some(resize) = resize_options else {
+            return Ok((resized, tiles));
+        };
+
+        match crop_box(resize, resized.dimensions()) {
+            Some((width, height)) => {
+                let x = (resized.width() - width) / 2;
+                let y = (resized.height() - height) / 2;
+                Ok((resized.crop_imm(x, y, width, height), tiles))
+            }
+            None => Ok((resized, tiles)),
+        }
+    }
+
+    /// Dimensions [`Self::apply_resize_optimized`] produces for a
+    /// `width`x`height` input, without touching any pixels
+    pub(crate) fn output_dimensions(
+        &self,
+        (width, height): (u32, u32),
+        resize_options: &Option<ResizeOptions>,
+        allow_upscale: bool,
+    ) -> Result<(u32, u32)> {
+        let Some(resize) = resize_options else {
+            return Ok((width, height));
+        };
+        let scaled = self.scaled_dimensions((width, height), resize, allow_upscale)?;
+        Ok(crop_box(resize, scaled).unwrap_or(scaled))
+    }
+
+    /// Target size of the scaling step, before any crop to the box
+    fn scaled_dimensions(
+        &self,
+        (current_width, current_height): (u32, u32),
+        resize: &ResizeOptions,
+        allow_upscale: bool,
+    ) -> Result<(u32, u32)> {
+        let (mut new_width, mut new_height) = self.calculate_resize_dimensions(
+            current_width,
+            current_height,
+            resize.width,
+            resize.height,
+            &resize.fit,
+        )?;
+        if !allow_upscale {
+            new_width = new_width.min(current_width);
+            new_height = new_height.min(current_height);
+        }
+        if resize.snap_even {
+            new_width = snap_to_even(new_width);
+            new_height = snap_to_even(new_height);
+        }
+        Ok((new_width, new_height))
+    }
+
+    /// Scale `img` to the dimensions computed from the resize options,
+    /// recording the tile count in `tiles` when the tiled path runs.
+    ///
+    /// The tiled path checks `deadline` before and after each tile.
+    fn resize_scaled(
+        &self,
+        img: &DynamicImage,
+        resize_options: &Option<ResizeOptions>,
+        allow_upscale: bool,
+        deadline: Option<Instant>,
+        tiles: &mut u32,
     ) -> Result<DynamicImage> {
         if let Some(resize) = resize_options {
             let (current_width, current_height) = img.dimensions();
+            let (new_width, new_height) =
+                self.scaled_dimensions((current_width, current_height), resize, allow_upscale)?;
 
-            let (new_width, new_height) = self.calculate_resize_dimensions(
-                current_width,
-                current_height,
-                resize.width,
-                resize.height,
-                &resize.fit,
-            )?;
+            if new_width != current_width || new_height != current_height {
+                let (filter_x, filter_y) =
+                    resize.filters_for((current_width, current_height), (new_width, new_height));
+                log_event!(self.logger, Debug, "Resizing from {current_width}x{current_height} to {new_width}x{new_height} with {filter_x:?}/{filter_y:?} (optimized)");
+
+                // 最近邻整数倍放大：直接复制像素，比通用重采样更快且不会产生混色
+                let nearest = image::imageops::FilterType::Nearest;
+                if filter_x == nearest
+                    && filter_y == nearest
+                    && new_width.is_multiple_of(current_width)
+                    && new_height.is_multiple_of(current_height)
+                {
+                    return Ok(replicate_pixels(
+                        img,
+                        new_width / current_width,
+                        new_height / current_height,
+                    ));
+                }
 
-            if new_width != current_width || new_height != current_height {
-                #[cfg(feature = "logging")]
-                log::debug!(
-                    "Resizing from {current_width}x{current_height} to {new_width}x{new_height} (optimized)"
-                );
+                // 大倍率缩小前先做盒式模糊，半径取缩放倍数的一半
+                let prefiltered;
+                let img = if resize.prefilter_blur {
+                    let factor =
+                        (current_width / new_width.max(1)).min(current_height / new_height.max(1));
+                    if factor >= 2 {
+                        prefiltered = prefilter_box_blur(img, factor / 2)?;
+                        &prefiltered
+                    } else {
+                        img
+                    }
+                } else {
+                    img
+                };
 
-                let filter = match resize.fit.as_str() {
-                    "cover" | "fill" => image::imageops::FilterType::Lanczos3,
-                    _ => image::imageops::FilterType::Triangle,
+                let resample = |img: &DynamicImage, width, height, filter| {
+                    if resize.linear_light_resize {
+                        resize_linear_light(img, width, height, filter)
+                    } else {
+                        img.resize_exact(width, height, filter)
+                    }
                 };
 
+                // 一个方向放大、另一个方向缩小：分两次单轴缩放，各用对应滤波器
+                if filter_x != filter_y {
+                    let horizontal = resample(img, new_width, current_height, filter_x);
+                    return Ok(resample(&horizontal, new_width, new_height, filter_y));
+                }
+                let filter = filter_x;
+
+                if resize.linear_light_resize {
+                    return Ok(resample(img, new_width, new_height, filter));
+                }
+
                 // 大图分块并行缩放：按行块切片后并行 resize，再拼接
                 let pixel_count = current_width as usize * current_height as usize;
-                if pixel_count > 4_000_000 {
+                if pixel_count > self.profile.tiled_resize_min_pixels() {
                     // 将图像转为 RGBA8 以便安全切片
                     let rgba = img.to_rgba8();
                     let src_width = current_width;
@@ -189,21 +1607,25 @@ impl CompressionEngine {
                         parts.push((y, h));
                         y += h;
                     }
+                    *tiles = parts.len() as u32;
 
                     use image::{imageops, RgbaImage};
                     // 并行处理每个块：裁剪 -> 缩放到对应目标高度比例
                     let scaled_parts: Vec<RgbaImage> = parts
                         .into_par_iter()
                         .map(|(y0, h)| {
+                            check_until(deadline)?;
                             let view = imageops::crop_imm(&rgba, 0, y0, src_width, h).to_image();
                             // 计算该块在目标图中的高度（按比例）
                             let dest_h = std::cmp::max(
                                 1,
                                 ((h as u64 * new_height as u64) / src_height as u64) as u32,
                             );
-                            imageops::resize(&view, new_width, dest_h, filter)
+                            let scaled = imageops::resize(&view, new_width, dest_h, filter);
+                            check_until(deadline)?;
+                            Ok(scaled)
                         })
-                        .collect();
+                        .collect::<Result<_>>()?;
 
                     // 拼接各块
                     let mut out = RgbaImage::new(new_width, new_height);
@@ -239,41 +1661,51 @@ impl CompressionEngine {
         target_height: Option<u32>,
         fit: &str,
     ) -> Result<(u32, u32)> {
-        match (target_width, target_height) {
+        if target_width == Some(0) || target_height == Some(0) {
+            return Err(CompressionError::InvalidFormat(
+                "Resize width and height must be greater than 0".to_string(),
+            ));
+        }
+
+        // 极端长宽比下按比例缩放可能舍入为 0，统一钳制到至少 1 像素
+        let (width, height) = match (target_width, target_height) {
             (Some(w), Some(h)) => {
                 match fit {
-                    "fill" => Ok((w, h)),
+                    "fill" => (w, h),
                     "contain" => {
                         let ratio_w = w as f32 / current_width as f32;
                         let ratio_h = h as f32 / current_height as f32;
                         let ratio = ratio_w.min(ratio_h);
-                        Ok((
+                        (
                             (current_width as f32 * ratio) as u32,
                             (current_height as f32 * ratio) as u32,
-                        ))
+                        )
                     }
-                    "cover" => {
+                    "cover" | "outside" => {
                         let ratio_w = w as f32 / current_width as f32;
                         let ratio_h = h as f32 / current_height as f32;
                         let ratio = ratio_w.max(ratio_h);
-                        Ok((
-                            (current_width as f32 * ratio) as u32,
-                            (current_height as f32 * ratio) as u32,
-                        ))
+                        // 浮点截断可能少 1 像素，钳制保证完全覆盖目标框
+                        (
+                            ((current_width as f32 * ratio) as u32).max(w),
+                            ((current_height as f32 * ratio) as u32).max(h),
+                        )
                     }
-                    _ => Ok((w, h)), // Default to fill
+                    _ => (w, h), // Default to fill
                 }
             }
             (Some(w), None) => {
                 let ratio = w as f32 / current_width as f32;
-                Ok((w, (current_height as f32 * ratio) as u32))
+                (w, (current_height as f32 * ratio) as u32)
             }
             (None, Some(h)) => {
                 let ratio = h as f32 / current_height as f32;
-                Ok(((current_width as f32 * ratio) as u32, h))
+                ((current_width as f32 * ratio) as u32, h)
             }
-            (None, None) => Ok((current_width, current_height)),
-        }
+            (None, None) => (current_width, current_height),
+        };
+
+        Ok((width.max(1), height.max(1)))
     }
 
     /// Compress image to specified format with SIMD optimizations
@@ -282,17 +1714,19 @@ impl CompressionEngine {
         img: &DynamicImage,
         format: &str,
         options: &CompressionOptions,
+        deadline: Option<Instant>,
     ) -> Result<Vec<u8>> {
         // Try zero-copy transfer first
         if let Some(data) = crate::performance::ZeroCopyTransfer::transfer_compatible(img, format) {
-            #[cfg(feature = "logging")]
-            if self.logger_enabled {
-                log::debug!("Using zero-copy transfer for format: {format}");
-            }
+            log_event!(
+                self.logger,
+                Debug,
+                "Using zero-copy transfer for format: {format}"
+            );
             return Ok(data);
         }
 
-        self.compress_to_format_with_simd(img, format, options)
+        self.compress_to_format_with_simd(img, format, options, deadline)
     }
 
     /// Internal compression method with SIMD acceleration
@@ -301,64 +1735,40 @@ impl CompressionEngine {
         img: &DynamicImage,
         format: &str,
         options: &CompressionOptions,
+        deadline: Option<Instant>,
     ) -> Result<Vec<u8>> {
         // Get a buffer from the memory pool for temporary operations
         let _temp_buffer = self.memory_pool.get_buffer();
 
-        match format {
-            "jpeg" | "jpg" => {
-                // JPEG support will be added in future versions
-                Err(CompressionError::UnsupportedFeature(
-                    "JPEG format not yet implemented".to_string(),
-                ))
-            }
-            "png" => {
-                // 纯 Rust PNG 编码路径：使用 image::codecs::png::PngEncoder
-                use image::codecs::png::{CompressionType, FilterType, PngEncoder};
-                use image::ImageEncoder;
-
-                // 编码参数：在 wasm 环境避免引入任何 C 依赖
-                let lossless = options.optimize.as_ref().is_some_and(|o| o.lossless);
-                // 压缩级别与过滤器选择做一个简单映射
-                let (compression, filter) = if lossless {
-                    (CompressionType::Best, FilterType::Paeth)
-                } else {
-                    (CompressionType::Default, FilterType::Sub)
-                };
+        let name = crate::encoder::canonical_name(format);
+        let encoder = self
+            .encoders
+            .get(name)
+            .ok_or_else(|| crate::encoder::unsupported_format(name))?;
 
-                // 将 DynamicImage 规范化为 RGBA8，保持通用性（含透明）
-                let rgba = img.to_rgba8();
-                let (w, h) = (rgba.width(), rgba.height());
-                let data = rgba.as_raw();
+        let mut optimize = options
+            .optimize
+            .clone()
+            .unwrap_or_else(|| self.profile.optimize_options());
+        optimize.deadline = optimize.deadline.or(deadline);
+        let quality = options
+            .quality
+            .unwrap_or_else(|| encoder.default_quality(&optimize));
 
-                let mut out: Vec<u8> = Vec::with_capacity((w * h * 4) as usize / 2 + 1024);
-                {
-                    let enc = PngEncoder::new_with_quality(&mut out, compression, filter);
-                    enc.write_image(data, w, h, image::ColorType::Rgba8)
-                        .map_err(|e| CompressionError::EncodingError(e.to_string()))?;
-                }
-                Ok(out)
-            }
-            "webp" => {
-                // WebP support will be added in future versions
-                Err(CompressionError::UnsupportedFeature(
-                    "WebP format not yet implemented".to_string(),
-                ))
+        let mut params = std::borrow::Cow::Borrowed(&options.extra_params);
+        if let Some(method) = self.profile.webp_method() {
+            if !params.contains_key("method") {
+                params
+                    .to_mut()
+                    .insert("method".to_string(), method.to_string());
             }
-            "avif" => {
-                // AVIF support will be added in future versions
-                Err(CompressionError::UnsupportedFeature(
-                    "AVIF format not yet implemented".to_string(),
-                ))
-            }
-            _ => Err(CompressionError::UnsupportedFeature(format!(
-                "Format '{format}' not supported (feature not enabled)"
-            ))),
         }
+        encoder.encode_with_params(img, quality, &optimize, &params)
     }
 
-    /// Apply color optimizations with parallel preprocessing for large images
-    #[allow(dead_code)]
+    /// Apply color optimizations with parallel preprocessing for large images.
+    ///
+    /// Both transforms are lossy, so this only runs with `preprocess_color`.
     fn apply_simd_color_optimization(
         &self,
         img: &DynamicImage,
@@ -425,6 +1835,144 @@ pub struct CompressionOptions {
     pub quality: Option<u8>,
     pub resize: Option<ResizeOptions>,
     pub optimize: Option<OptimizeOptions>,
+    /// Make pixels within `tolerance` of the key colour transparent before encoding
+    pub chroma_key: Option<([u8; 3], u8)>,
+    /// For JPEG output, embed a fresh EXIF thumbnail of the processed image.
+    /// When false the output carries no EXIF, so a stale thumbnail never survives.
+    pub regenerate_embedded_thumbnail: bool,
+    /// Rotate/flip by the source's EXIF orientation before any other processing
+    pub auto_orient: bool,
+    /// Trim uniform borders (background found per `background_detect`) before
+    /// resizing. Animated output crops every frame to the union of their
+    /// content, so the frames keep a common size.
+    pub autocrop: bool,
+    /// Fail an image still being processed after this long. The deadline is
+    /// checked between pipeline stages and inside their long loops (resize
+    /// tiles, PNG filter search passes, animation frames). A single codec
+    /// call is not interrupted: it runs to completion and the image then
+    /// fails with [`CompressionError::Timeout`] if the budget was exceeded.
+    /// Other images in a batch are unaffected.
+    pub per_image_timeout: Option<Duration>,
+    /// Unsharp-mask amount applied after resizing (e.g. 0.5-1.5) to counter
+    /// the softness of downscaling
+    pub sharpen: Option<f32>,
+    /// Tone adjustments applied after resizing
+    pub adjust: Option<Adjustments>,
+    /// When false, resize targets are clamped to the source size per
+    /// dimension so images are only ever shrunk
+    pub allow_upscale: bool,
+    /// For animated output, drop a frame whose SSIM against the previous
+    /// kept frame exceeds this threshold (e.g. 0.99) and add its delay to
+    /// that frame. Frames whose alpha differs are always kept.
+    pub elide_similar_frames: Option<f32>,
+    /// Histogram-equalize the luma channel after tone adjustments, spreading
+    /// low-contrast images (e.g. faded scans) over the full tonal range
+    pub equalize: bool,
+    /// Convert images to `"grayscale"`, `"rgb"` or `"rgba"` before encoding,
+    /// for consumers that cannot handle some layouts (e.g. alpha). Dropping
+    /// alpha flattens the image against `background`. APNG frames are still
+    /// stored as RGBA, so there only the pixel values change.
+    pub force_color_type: Option<String>,
+    /// Colour that transparent pixels are composited onto when alpha is
+    /// dropped; white when unset
+    pub background: Option<[u8; 3]>,
+    /// Decode the encoded bytes again and check their dimensions before
+    /// returning, failing with `EncodingError` on corrupt output. Only
+    /// formats the crate can decode can be verified.
+    pub verify_output: bool,
+    /// Hard cap on the encoded size. Output over the cap is re-encoded at
+    /// lower quality, then at lower resolution, until it fits; compression
+    /// fails only when even a 1x1 image does not. Retries reuse the decoded
+    /// source and count against `per_image_timeout`. Tiles, responsive
+    /// variants and raw buffers are each capped on their own.
+    pub max_output_bytes: Option<usize>,
+    /// For WebP output, copy the source's EXIF and XMP into the container
+    /// (other formats still drop metadata). With `auto_orient` the copied
+    /// orientation tag is reset to 1, as the pixels are already upright.
+    pub preserve_metadata: bool,
+    /// Run the lossy SIMD colour preprocessing before encoding: 64-level
+    /// quantisation for JPEG and an RGB→YUV→RGB round trip for WebP/AVIF,
+    /// on images of 100k pixels or more. Leave off when lossless output must
+    /// reproduce the source pixels exactly.
+    pub preprocess_color: bool,
+    /// How `autocrop` infers the background colour; `None` uses the corners
+    pub background_detect: Option<crate::analyzer::BackgroundDetect>,
+    /// Raw encoder-specific parameters for knobs the typed options do not
+    /// cover. Each encoder reads the keys it recognises and ignores the
+    /// rest; a recognised key with an unparsable value is an error.
+    ///
+    /// - WebP: `method` (0-6), `filter_strength` (0-100),
+    ///   `filter_sharpness` (0-7), `sns_strength` (0-100)
+    /// - AVIF: `speed` (1-10), `bit_depth` (8 or 10)
+    ///
+    /// Other built-in formats recognise no keys.
+    pub extra_params: HashMap<String, String>,
+    /// Return the input unchanged (with `fell_back` set) without encoding
+    /// when analysis estimates savings below this fraction (e.g. `0.1` for
+    /// 10%); pairs with [`ImageAnalysis::already_optimized`](crate::ImageAnalysis::already_optimized)
+    pub skip_if_savings_below: Option<f32>,
+    /// For PNG output, write a compact `sRGB` chunk when the source has no
+    /// ICC profile or an sRGB one. JPEG output never carries a profile and
+    /// is already read as sRGB, so it is left untouched.
+    pub assume_srgb: bool,
+}
+
+impl Default for CompressionOptions {
+    /// Encoder defaults with every optional step off; upscaling stays
+    /// allowed so resize targets are honoured as given
+    fn default() -> Self {
+        Self {
+            format: None,
+            quality: None,
+            resize: None,
+            optimize: None,
+            chroma_key: None,
+            regenerate_embedded_thumbnail: false,
+            auto_orient: false,
+            autocrop: false,
+            per_image_timeout: None,
+            sharpen: None,
+            adjust: None,
+            allow_upscale: true,
+            elide_similar_frames: None,
+            equalize: false,
+            force_color_type: None,
+            background: None,
+            verify_output: false,
+            max_output_bytes: None,
+            preserve_metadata: false,
+            preprocess_color: false,
+            background_detect: None,
+            extra_params: HashMap::new(),
+            skip_if_savings_below: None,
+            assume_srgb: false,
+        }
+    }
+}
+
+/// Pipeline settings used by [`CompressionEngine::normalize`]
+///
+/// Outputs never carry the source's metadata (EXIF, ICC, XMP), so
+/// normalization always strips it.
+#[derive(Debug, Clone)]
+pub struct NormalizeOptions {
+    /// Apply the EXIF orientation so the output displays upright without it
+    pub auto_orient: bool,
+    /// Downscale (keeping aspect ratio) to at most this many pixels
+    pub max_pixels: Option<u64>,
+    pub format: String,
+    pub quality: u8,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        Self {
+            auto_orient: true,
+            max_pixels: Some(4_000_000),
+            format: "webp".to_string(),
+            quality: 82,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -432,13 +1980,116 @@ pub struct ResizeOptions {
     pub width: Option<u32>,
     pub height: Option<u32>,
     pub fit: String, // "cover", "contain", "fill", "inside", "outside"
+    /// Resample in linear light instead of gamma-encoded sRGB, so fine
+    /// bright-on-dark detail is not darkened when downscaling
+    pub linear_light_resize: bool,
+    /// Box-blur before downscaling (radius from the scale factor) to avoid
+    /// aliasing in aggressive thumbnails
+    pub prefilter_blur: bool,
+    /// Resampling filter for a dimension that grows. `Nearest` with an
+    /// exact integer scale factor replicates pixels directly (crisp pixel art)
+    pub upscale_filter: image::imageops::FilterType,
+    /// Resampling filter for a dimension that shrinks
+    pub downscale_filter: image::imageops::FilterType,
+    /// For "cover" and "outside" with both dimensions set, centre-crop the
+    /// scaled image so the output is exactly `width` x `height`
+    pub crop_to_box: bool,
+    /// Round the computed target dimensions down to even numbers, as 4:2:0
+    /// chroma subsampling needs. Enabled automatically for output that
+    /// subsamples (lossy WebP, JPEG below quality 90 or with forced 4:2:0)
+    pub snap_even: bool,
 }
 
-#[derive(Debug, Clone)]
+impl ResizeOptions {
+    /// `(horizontal, vertical)` filters for resizing `source` to `target`.
+    ///
+    /// Each axis takes `upscale_filter` when it grows and `downscale_filter`
+    /// when it shrinks; an axis that keeps its size follows the other one.
+    pub fn filters_for(
+        &self,
+        source: (u32, u32),
+        target: (u32, u32),
+    ) -> (image::imageops::FilterType, image::imageops::FilterType) {
+        let pick = |src: u32, dst: u32| match dst.cmp(&src) {
+            std::cmp::Ordering::Greater => Some(self.upscale_filter),
+            std::cmp::Ordering::Less => Some(self.downscale_filter),
+            std::cmp::Ordering::Equal => None,
+        };
+        let x = pick(source.0, target.0);
+        let y = pick(source.1, target.1);
+        (
+            x.or(y).unwrap_or(self.downscale_filter),
+            y.or(x).unwrap_or(self.downscale_filter),
+        )
+    }
+}
+
+impl Default for ResizeOptions {
+    fn default() -> Self {
+        Self {
+            width: None,
+            height: None,
+            fit: "fill".to_string(),
+            linear_light_resize: false,
+            prefilter_blur: false,
+            upscale_filter: image::imageops::FilterType::CatmullRom,
+            downscale_filter: image::imageops::FilterType::Lanczos3,
+            crop_to_box: false,
+            snap_even: false,
+        }
+    }
+}
+
+/// Tone adjustments on the RGB channels (alpha is left untouched)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustments {
+    /// Added to every channel on a 0-1 scale; 0.0 is neutral
+    pub brightness: f32,
+    /// Scales the distance from mid-grey; 1.0 is neutral
+    pub contrast: f32,
+    /// Scales the distance from the pixel's luma; 0.0 gives greyscale, 1.0 is neutral
+    pub saturation: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct OptimizeOptions {
     pub colors: bool,
     pub progressive: bool,
     pub lossless: bool,
+    /// JPEG: vary effective quantization by local 8x8 block variance
+    /// (see `JpegOptions::adaptive_quantization`)
+    pub adaptive_regions: bool,
+    /// WebP near-lossless preprocessing level (0-100, lower = stronger).
+    /// Implies lossless encoding when set.
+    pub near_lossless: Option<u8>,
+    /// WebP/AVIF: alpha plane quality (0-100), independent of the colour quality
+    pub alpha_quality: Option<u8>,
+    /// PNG: quantize to an indexed palette of at most this many colours (2-256)
+    pub max_colors: Option<usize>,
+    /// How palette quantization hides banding (see `max_colors`)
+    pub dither_method: crate::formats::png::DitherMethod,
+    /// PNG output settings (bit depth reduction etc.)
+    pub png: Option<crate::formats::png::PngOptions>,
+    /// JPEG output settings (quality is taken from `CompressionOptions`)
+    pub jpeg: Option<crate::formats::jpeg::JpegOptions>,
+    /// TIFF output settings (compression scheme)
+    pub tiff: Option<crate::formats::tiff::TiffOptions>,
+    /// AVIF output settings (speed, bit depth, colour space)
+    pub avif: Option<crate::formats::avif::AvifOptions>,
+    /// Multi-pass encoders (e.g. the PNG filter search) stop with
+    /// `Timeout` once this instant passes. The engine fills it in from
+    /// `per_image_timeout` when unset.
+    pub deadline: Option<Instant>,
 }
 
 /// Result of compression operation
@@ -448,9 +2099,810 @@ pub struct CompressionResult {
     pub original_size: usize,
     pub compressed_size: usize,
     pub compression_ratio: f32,
+    /// Output size relative to the uncompressed pixels (`w * h * bytes per
+    /// pixel`) of the encoded image; unlike `compression_ratio` this does
+    /// not depend on how the input was stored
+    pub ratio_vs_raw: f32,
+    /// Dimensions of the encoded image (after crop/resize)
+    pub output_width: u32,
+    pub output_height: u32,
     pub format: String,
     pub processing_time: u64, // milliseconds
+    /// Per-stage split of `processing_time`, which is their sum
+    pub timings: Timings,
     pub metadata: ImageMetadata,
+    /// The resize ran through the parallel row-tiled path used for sources
+    /// over 4 megapixels (1 with [`Profile::FastestEncode`]), which explains
+    /// timing differences on large images
+    pub used_tiled_resize: bool,
+    /// Number of row tiles that path used (0 when it did not run)
+    pub tile_count: u32,
+    /// The resize enlarged the image along at least one axis, so the
+    /// output holds interpolated detail (see `allow_upscale`)
+    pub upscaled: bool,
+    /// `data` is the untouched input: compression failed under
+    /// [`ErrorPolicy::PassThrough`] or was skipped by `skip_if_savings_below`.
+    /// Dimensions are then the input's; a pass-through input whose header
+    /// cannot be read reports 0x0 with `ratio_vs_raw` 1.0.
+    pub fell_back: bool,
+}
+
+/// What a batch returns for an image that fails to compress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Report the error for that image
+    #[default]
+    Fail,
+    /// Return the original bytes unchanged as a result with `fell_back` set,
+    /// e.g. for mirrors and CDNs that must always serve something
+    PassThrough,
+}
+
+/// Engine-wide trade-off between encoding speed and output size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    /// Lowest latency: analysis on a downscaled copy, fast deflate without
+    /// PNG filter search, WebP method 0, AVIF speed 10, and parallel tiled
+    /// resizing from 1 megapixel
+    FastestEncode,
+    /// The engine's standard settings
+    #[default]
+    Balanced,
+    /// Smallest output: exhaustive PNG filter search with maximum deflate,
+    /// WebP method 6 and AVIF speed 3, at the cost of encode time
+    SmallestFile,
+}
+
+impl Profile {
+    /// Longer-side limit for the analysis image, if the profile downscales
+    fn analysis_max_dim(self) -> Option<u32> {
+        match self {
+            Profile::FastestEncode => Some(1024),
+            Profile::Balanced | Profile::SmallestFile => None,
+        }
+    }
+
+    /// Encoder settings used when the options carry no [`OptimizeOptions`]
+    fn optimize_options(self) -> OptimizeOptions {
+        let (png, avif_speed) = match self {
+            Profile::FastestEncode => (
+                crate::formats::png::PngOptions {
+                    optimization_level: 0,
+                    deflate_optimization: false,
+                    bit_depth_reduction: false,
+                    ..Default::default()
+                },
+                10,
+            ),
+            Profile::Balanced => return OptimizeOptions::default(),
+            Profile::SmallestFile => (
+                crate::formats::png::PngOptions {
+                    optimization_level: 6,
+                    ..Default::default()
+                },
+                3,
+            ),
+        };
+        OptimizeOptions {
+            png: Some(png),
+            avif: Some(crate::formats::avif::AvifOptions {
+                speed: avif_speed,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// WebP `method` (0-6) used unless `extra_params` sets one
+    fn webp_method(self) -> Option<u8> {
+        match self {
+            Profile::FastestEncode => Some(0),
+            Profile::Balanced => None,
+            Profile::SmallestFile => Some(6),
+        }
+    }
+
+    /// Source pixel count above which resizes run in parallel row tiles
+    fn tiled_resize_min_pixels(self) -> usize {
+        match self {
+            Profile::FastestEncode => 1_000_000,
+            Profile::Balanced | Profile::SmallestFile => 4_000_000,
+        }
+    }
+}
+
+/// Settings that apply to a whole batch rather than to each image
+#[derive(Debug, Clone, Default)]
+pub struct BatchOptions {
+    pub on_error: ErrorPolicy,
+}
+
+/// Milliseconds spent in each stage of one compression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Container decode (plus EXIF auto-orient)
+    pub decode_ms: u64,
+    /// Format detection and image analysis
+    pub analyze_ms: u64,
+    /// Crop, resize and the other pixel operations before encoding
+    pub resize_ms: u64,
+    /// Encoding plus container post-processing (metadata, verification)
+    pub encode_ms: u64,
+}
+
+impl Timings {
+    /// Sum of all stages
+    pub fn total_ms(&self) -> u64 {
+        self.decode_ms + self.analyze_ms + self.resize_ms + self.encode_ms
+    }
+}
+
+/// Per-format outcome of [`CompressionEngine::compare_formats`]
+#[derive(Debug, Clone)]
+pub struct FormatComparison {
+    pub format: String,
+    pub size: usize,
+    pub ratio: f32,
+    pub ssim: f32,
+    pub encode_ms: f64,
+}
+
+impl CompressionResult {
+    /// Encode the compressed bytes as a `data:` URI ready for embedding
+    pub fn to_data_uri(&self) -> String {
+        use base64::Engine;
+
+        format!(
+            "data:{};base64,{}",
+            mime_type(&self.format),
+            base64::engine::general_purpose::STANDARD.encode(&self.data)
+        )
+    }
+}
+
+/// Convert `img` to the named layout, flattening alpha when it is dropped
+fn force_color_type(
+    img: &DynamicImage,
+    color_type: &str,
+    background: Option<[u8; 3]>,
+) -> Result<DynamicImage> {
+    let flattened = || {
+        let background = background.unwrap_or([255, 255, 255]);
+        let rgba = img.to_rgba8();
+        image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+            let [r, g, b, a] = rgba.get_pixel(x, y).0;
+            let blend = |c: u8, bg: u8| {
+                ((c as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8
+            };
+            image::Rgb([
+                blend(r, background[0]),
+                blend(g, background[1]),
+                blend(b, background[2]),
+            ])
+        })
+    };
+
+    match color_type {
+        "grayscale" | "gray" => Ok(DynamicImage::ImageLuma8(
+            DynamicImage::ImageRgb8(flattened()).to_luma8(),
+        )),
+        "rgb" => Ok(DynamicImage::ImageRgb8(flattened())),
+        "rgba" => Ok(DynamicImage::ImageRgba8(img.to_rgba8())),
+        other => Err(CompressionError::InvalidFormat(format!(
+            "Unknown color type '{other}'; expected grayscale, rgb or rgba"
+        ))),
+    }
+}
+
+/// Largest even number not above `value` (1 stays 1)
+fn snap_to_even(value: u32) -> u32 {
+    if value > 1 {
+        value & !1
+    } else {
+        value
+    }
+}
+
+/// Centred crop box for `crop_to_box` cover resizes of a `scaled` image
+fn crop_box(resize: &ResizeOptions, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+    match (resize.width, resize.height) {
+        (Some(box_width), Some(box_height))
+            if resize.crop_to_box && matches!(resize.fit.as_str(), "cover" | "outside") =>
+        {
+            // 禁止放大时缩放结果可能小于目标框，此时按实际尺寸裁剪
+            let crop = (box_width.min(width), box_height.min(height));
+            Some(if resize.snap_even {
+                (snap_to_even(crop.0), snap_to_even(crop.1))
+            } else {
+                crop
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Content rectangle `(x, y, width, height)` that `autocrop` keeps
+fn autocrop_bounds(img: &DynamicImage, options: &CompressionOptions) -> (u32, u32, u32, u32) {
+    crate::analyzer::content_bounds_of(
+        img,
+        AUTOCROP_TOLERANCE,
+        options.background_detect.unwrap_or_default(),
+    )
+}
+
+/// Transforms applied before resizing, shared by still and animated output:
+/// crop to `crop` (the autocrop rectangle), then chroma key
+fn prepare_source(
+    mut img: DynamicImage,
+    crop: Option<(u32, u32, u32, u32)>,
+    options: &CompressionOptions,
+) -> DynamicImage {
+    if let Some((x, y, width, height)) = crop {
+        img = img.crop_imm(x, y, width, height);
+    }
+    if let Some((key, tolerance)) = options.chroma_key {
+        img = crate::analyzer::apply_chroma_key(&img, key, tolerance);
+    }
+    img
+}
+
+/// Transforms applied after resizing, shared by still and animated output:
+/// sharpen, tone adjustments, equalization and colour-type conversion
+fn finish_pixels(mut img: DynamicImage, options: &CompressionOptions) -> Result<DynamicImage> {
+    if let Some(amount) = options.sharpen {
+        img = apply_sharpen(&img, amount);
+    }
+    if let Some(adjust) = &options.adjust {
+        img = apply_adjustments(&img, adjust);
+    }
+    if options.equalize {
+        img = equalize_luma(&img);
+    }
+    if let Some(color_type) = &options.force_color_type {
+        img = force_color_type(&img, color_type, options.background)?;
+    }
+    Ok(img)
+}
+
+/// Whether the analyzer picks the output format (`None` or `"auto"`)
+fn is_auto_format(options: &CompressionOptions) -> bool {
+    options
+        .format
+        .as_deref()
+        .is_none_or(|format| format == "auto")
+}
+
+/// Whether `format` output will use 4:2:0 chroma subsampling
+fn subsamples_chroma(format: &str, options: &CompressionOptions) -> bool {
+    let optimize = options.optimize.clone().unwrap_or_default();
+    match format {
+        "jpeg" | "jpg" => {
+            let jpeg = optimize.jpeg.unwrap_or_default();
+            // 未指定时与编码器一致：质量 90 以下使用 4:2:0
+            jpeg.subsample_chroma
+                .unwrap_or(options.quality.unwrap_or(jpeg.quality) < 90)
+        }
+        "webp" => !optimize.lossless,
+        _ => false,
+    }
+}
+
+/// Integer-factor upscale by repeating every pixel into an `fx` x `fy` block
+fn replicate_pixels(img: &DynamicImage, fx: u32, fy: u32) -> DynamicImage {
+    fn replicate<P: image::Pixel>(
+        src: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+        fx: u32,
+        fy: u32,
+    ) -> image::ImageBuffer<P, Vec<P::Subpixel>> {
+        let (width, height) = src.dimensions();
+        let channels = P::CHANNEL_COUNT as usize;
+        let mut out = Vec::with_capacity(src.as_raw().len() * (fx * fy) as usize);
+        for row in src.as_raw().chunks_exact(width as usize * channels) {
+            // 先横向展开一行，再整行复制 fy - 1 次
+            let start = out.len();
+            for px in row.chunks_exact(channels) {
+                for _ in 0..fx {
+                    out.extend_from_slice(px);
+                }
+            }
+            let end = out.len();
+            for _ in 1..fy {
+                out.extend_from_within(start..end);
+            }
+        }
+        image::ImageBuffer::from_raw(width * fx, height * fy, out)
+            .expect("replicated buffer matches scaled dimensions")
+    }
+
+    match img {
+        DynamicImage::ImageLuma8(b) => DynamicImage::ImageLuma8(replicate(b, fx, fy)),
+        DynamicImage::ImageLumaA8(b) => DynamicImage::ImageLumaA8(replicate(b, fx, fy)),
+        DynamicImage::ImageRgb8(b) => DynamicImage::ImageRgb8(replicate(b, fx, fy)),
+        DynamicImage::ImageRgba8(b) => DynamicImage::ImageRgba8(replicate(b, fx, fy)),
+        DynamicImage::ImageLuma16(b) => DynamicImage::ImageLuma16(replicate(b, fx, fy)),
+        DynamicImage::ImageLumaA16(b) => DynamicImage::ImageLumaA16(replicate(b, fx, fy)),
+        DynamicImage::ImageRgb16(b) => DynamicImage::ImageRgb16(replicate(b, fx, fy)),
+        DynamicImage::ImageRgba16(b) => DynamicImage::ImageRgba16(replicate(b, fx, fy)),
+        other => DynamicImage::ImageRgba8(replicate(&other.to_rgba8(), fx, fy)),
+    }
+}
+
+/// Merge runs of near-identical frames, summing their delays
+fn elide_similar_frames(frames: Vec<image::Frame>, threshold: f32) -> Result<Vec<image::Frame>> {
+    let mut kept: Vec<image::Frame> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        if let Some(last) = kept.last() {
+            let same_alpha = last
+                .buffer()
+                .pixels()
+                .zip(frame.buffer().pixels())
+                .all(|(a, b)| a[3] == b[3]);
+            if same_alpha
+                && crate::metrics::ssim(
+                    &DynamicImage::ImageRgba8(last.buffer().clone()),
+                    &DynamicImage::ImageRgba8(frame.buffer().clone()),
+                )? > threshold
+            {
+                // 丢弃当前帧，其显示时长并入上一保留帧
+                let last = kept.pop().expect("checked above");
+                let delay = Duration::from(last.delay()) + Duration::from(frame.delay());
+                let (left, top) = (last.left(), last.top());
+                kept.push(image::Frame::from_parts(
+                    last.into_buffer(),
+                    left,
+                    top,
+                    image::Delay::from_saturating_duration(delay),
+                ));
+                continue;
+            }
+        }
+        kept.push(frame);
+    }
+    Ok(kept)
+}
+
+/// Start of one compression and the stage boundaries it has passed, in
+/// milliseconds since the start
+#[derive(Clone, Copy)]
+struct StageClock {
+    start: Instant,
+    decoded: u64,
+    analyzed: u64,
+    resized: u64,
+}
+
+impl StageClock {
+    fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            decoded: 0,
+            analyzed: 0,
+            resized: 0,
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /// Split `total` at the recorded boundaries; stages that were never
+    /// marked get 0 so the parts always add up to `total`
+    fn timings(&self, total: u64) -> Timings {
+        let decoded = self.decoded.min(total);
+        let analyzed = self.analyzed.clamp(decoded, total);
+        let resized = self.resized.clamp(analyzed, total);
+        Timings {
+            decode_ms: decoded,
+            analyze_ms: analyzed - decoded,
+            resize_ms: resized - analyzed,
+            encode_ms: total - resized,
+        }
+    }
+}
+
+/// Pixels decoded once per call, which `max_output_bytes` retries re-encode
+#[derive(Clone)]
+enum DecodedSource {
+    Still(DynamicImage),
+    /// Every frame of an animated source, for APNG output
+    Frames(Vec<image::Frame>),
+}
+
+/// Shape of the encoded image, used for the size statistics of a result
+struct EncodedOutput {
+    width: u32,
+    height: u32,
+    /// Uncompressed pixel bytes of everything encoded (all frames)
+    raw_size: usize,
+    /// Row tiles of the parallel resize path (0 when it did not run)
+    tile_count: u32,
+    /// The resize made the image wider or taller than its input
+    upscaled: bool,
+}
+
+/// Box-blur the colour channels, keeping alpha untouched
+fn prefilter_box_blur(img: &DynamicImage, radius: u32) -> Result<DynamicImage> {
+    let (width, height) = img.dimensions();
+    let rgb = if img.color().has_alpha() {
+        SimdProcessor::rgba_to_rgb(img.to_rgba8().as_raw())
+    } else {
+        img.to_rgb8().into_raw()
+    };
+    let blurred = SimdProcessor::box_blur_rgb(&rgb, width, height, radius);
+
+    let buffer_error = || CompressionError::MemoryError("Failed to create blurred buffer".into());
+    if img.color().has_alpha() {
+        let mut rgba = img.to_rgba8();
+        for (px, src) in rgba.pixels_mut().zip(blurred.chunks_exact(3)) {
+            px.0[..3].copy_from_slice(src);
+        }
+        Ok(DynamicImage::ImageRgba8(rgba))
+    } else {
+        image::RgbImage::from_raw(width, height, blurred)
+            .map(DynamicImage::ImageRgb8)
+            .ok_or_else(buffer_error)
+    }
+}
+
+/// Resize in linear light: decode sRGB, resample, then re-encode to sRGB
+fn resize_linear_light(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: image::imageops::FilterType,
+) -> DynamicImage {
+    // sRGB -> 线性查找表（8 位输入只有 256 种取值）
+    let to_linear: Vec<f32> = (0..256)
+        .map(|v| {
+            let c = v as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+
+    let rgba = img.to_rgba8();
+    let linear = image::ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        image::Rgba([
+            to_linear[p[0] as usize],
+            to_linear[p[1] as usize],
+            to_linear[p[2] as usize],
+            p[3] as f32 / 255.0,
+        ])
+    });
+
+    let resized = image::imageops::resize(&linear, width, height, filter);
+
+    let to_srgb = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        let v = if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (v * 255.0).round() as u8
+    };
+    let out = image::RgbaImage::from_fn(width, height, |x, y| {
+        let p = resized.get_pixel(x, y);
+        image::Rgba([
+            to_srgb(p[0]),
+            to_srgb(p[1]),
+            to_srgb(p[2]),
+            (p[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    });
+
+    DynamicImage::ImageRgba8(out)
+}
+
+/// Integer sample types the per-pixel filters work on in place
+trait Sample: Copy + Into<f32> + Send + Sync {
+    /// Value of a full-intensity sample
+    const MAX: f32;
+
+    /// Round and clamp a value on the `0..=MAX` scale
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Sample for u8 {
+    const MAX: f32 = 255.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.round().clamp(0.0, <Self as Sample>::MAX) as u8
+    }
+}
+
+impl Sample for u16 {
+    const MAX: f32 = 65535.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.round().clamp(0.0, <Self as Sample>::MAX) as u16
+    }
+}
+
+/// Run `$body` on the image's packed samples in place, keeping its colour
+/// type. `$data` is bound to the `&mut [u8]` or `&mut [u16]` buffer and
+/// `$channels` to the samples per pixel; float images become RGBA8 first.
+macro_rules! with_samples {
+    ($img:expr, |$data:ident, $channels:ident| $body:expr) => {{
+        let mut img = $img;
+        match &mut img {
+            DynamicImage::ImageLuma8(b) => with_samples!(@run b, 1, $data, $channels, $body),
+            DynamicImage::ImageLumaA8(b) => with_samples!(@run b, 2, $data, $channels, $body),
+            DynamicImage::ImageRgb8(b) => with_samples!(@run b, 3, $data, $channels, $body),
+            DynamicImage::ImageRgba8(b) => with_samples!(@run b, 4, $data, $channels, $body),
+            DynamicImage::ImageLuma16(b) => with_samples!(@run b, 1, $data, $channels, $body),
+            DynamicImage::ImageLumaA16(b) => with_samples!(@run b, 2, $data, $channels, $body),
+            DynamicImage::ImageRgb16(b) => with_samples!(@run b, 3, $data, $channels, $body),
+            DynamicImage::ImageRgba16(b) => with_samples!(@run b, 4, $data, $channels, $body),
+            other => {
+                let mut rgba = other.to_rgba8();
+                with_samples!(@run &mut rgba, 4, $data, $channels, $body);
+                *other = DynamicImage::ImageRgba8(rgba);
+            }
+        }
+        img
+    }};
+    (@run $buf:expr, $n:expr, $data:ident, $channels:ident, $body:expr) => {{
+        let ($data, $channels): (&mut [_], usize) = (&mut **$buf, $n);
+        $body
+    }};
+}
+
+/// Unsharp-mask the colour channels with a 1px blur radius, keeping the colour type
+fn apply_sharpen(img: &DynamicImage, amount: f32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    with_samples!(img.clone(), |data, channels| sharpen_samples(
+        data, width, height, channels, amount
+    ))
+}
+
+/// Unsharp-mask packed samples in place.
+///
+/// The mask runs on 8-bit levels; for 16-bit samples only its change is
+/// scaled back onto the original values, so their extra precision survives.
+fn sharpen_samples<S: Sample>(
+    data: &mut [S],
+    width: u32,
+    height: u32,
+    channels: usize,
+    amount: f32,
+) {
+    let scale = S::MAX / 255.0;
+    let levels: Vec<u8> = data
+        .iter()
+        .map(|&v| u8::from_f32(v.into() / scale))
+        .collect();
+    let sharpened =
+        SimdProcessor::unsharp_mask(&levels, width, height, channels as u8, amount, 1.0);
+    data.par_iter_mut()
+        .zip(levels.par_iter().zip(sharpened.par_iter()))
+        .for_each(|(v, (&before, &after))| {
+            *v = S::from_f32((*v).into() + (after as f32 - before as f32) * scale);
+        });
+}
+
+/// Apply brightness, contrast and saturation per pixel in that order,
+/// keeping the colour type
+fn apply_adjustments(img: &DynamicImage, adjust: &Adjustments) -> DynamicImage {
+    if *adjust == Adjustments::default() {
+        return img.clone();
+    }
+    with_samples!(img.clone(), |data, channels| adjust_samples(
+        data, channels, adjust
+    ))
+}
+
+/// Tone-adjust packed samples in place; alpha (the last of 2 or 4
+/// channels) is left untouched and grayscale ignores saturation
+fn adjust_samples<S: Sample>(data: &mut [S], channels: usize, adjust: &Adjustments) {
+    let color_channels = if channels.is_multiple_of(2) {
+        channels - 1
+    } else {
+        channels
+    };
+    data.par_chunks_mut(channels).for_each(|px| {
+        let mut rgb = [0f32; 3];
+        for (v, &c) in rgb.iter_mut().zip(&px[..color_channels]) {
+            *v = (c.into() / S::MAX + adjust.brightness - 0.5) * adjust.contrast + 0.5;
+        }
+        // 饱和度：以 BT.601 亮度为中心缩放色度
+        if color_channels == 3 {
+            let luma = 0.299 * rgb[0] + 0.587 * rgb[1] + 0.114 * rgb[2];
+            for v in &mut rgb {
+                *v = luma + (*v - luma) * adjust.saturation;
+            }
+        }
+        for (c, v) in px[..color_channels].iter_mut().zip(rgb) {
+            *c = S::from_f32(v.clamp(0.0, 1.0) * S::MAX);
+        }
+    });
+}
+
+/// Equalize the BT.601 luma histogram, remapping each level through the CDF.
+///
+/// Colour images shift all three channels by the luma change, which keeps
+/// Cb/Cr (and alpha) unchanged. The colour type is kept: grayscale stays
+/// grayscale and 16-bit samples are shifted at full precision.
+fn equalize_luma(img: &DynamicImage) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    with_samples!(img.clone(), |data, channels| equalize_samples(
+        data, width, height, channels
+    ))
+}
+
+/// Histogram-equalize packed samples in place (see [`equalize_luma`])
+fn equalize_samples<S: Sample>(data: &mut [S], width: u32, height: u32, channels: usize) {
+    // 亮度按 8 位灰阶统计；16 位样本按比例映射到灰阶
+    let scale = S::MAX / 255.0;
+    let luma_of = |px: &[S]| -> f32 {
+        if channels >= 3 {
+            0.299 * px[0].into() + 0.587 * px[1].into() + 0.114 * px[2].into()
+        } else {
+            px[0].into()
+        }
+    };
+    let levels: Vec<u8> = data
+        .par_chunks(channels)
+        .map(|px| u8::from_f32(luma_of(px) / scale))
+        .collect();
+    let gray =
+        image::GrayImage::from_raw(width, height, levels).expect("luma plane matches image size");
+
+    // 累积分布 -> 查找表；跳过首个非空灰阶，使最暗像素映射到 0
+    let histogram = ParallelProcessor::histogram(&gray);
+    let total: u64 = histogram.iter().map(|&c| c as u64).sum();
+    let cdf_min = histogram.iter().find(|&&c| c > 0).copied().unwrap_or(0) as u64;
+    if total <= cdf_min {
+        return;
+    }
+    let mut lut = [0u8; 256];
+    let mut cumulative = 0u64;
+    for (entry, &count) in lut.iter_mut().zip(histogram.iter()) {
+        cumulative += count as u64;
+        *entry = (cumulative.saturating_sub(cdf_min) as f64 * 255.0 / (total - cdf_min) as f64)
+            .round() as u8;
+    }
+
+    let color_channels = if channels.is_multiple_of(2) {
+        channels - 1
+    } else {
+        channels
+    };
+    data.par_chunks_mut(channels)
+        .zip(gray.as_raw().par_iter())
+        .for_each(|(px, &level)| {
+            let shift = (lut[level as usize] as f32 - level as f32) * scale;
+            for c in &mut px[..color_channels] {
+                *c = S::from_f32((*c).into() + shift);
+            }
+        });
+}
+
+/// File extension for a result written next to or in place of `source`.
+///
+/// Fall-backs keep the source's extension since their bytes are unchanged.
+fn output_extension(result: &CompressionResult, source: &Path) -> String {
+    let original_extension = source
+        .extension()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| result.format.clone());
+    match result.format.as_str() {
+        _ if result.fell_back => original_extension,
+        "jpeg" => "jpg".to_string(),
+        "apng" => "png".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// `name` as a relative, `/`-separated ZIP entry path.
+///
+/// Both `/` and `\` separate components; empty and `.` components, a
+/// leading root and a drive prefix such as `C:` are dropped. `..` is an
+/// error, as it would let extraction write outside the target directory.
+fn zip_entry_path(name: &str) -> Result<String> {
+    let mut parts = Vec::new();
+    for (index, part) in name.split(['/', '\\']).enumerate() {
+        let is_drive = index == 0
+            && part.len() == 2
+            && part.as_bytes()[0].is_ascii_alphabetic()
+            && part.ends_with(':');
+        match part {
+            "" | "." => {}
+            ".." => {
+                return Err(CompressionError::InvalidFormat(format!(
+                    "ZIP entry name '{name}' must not contain '..'"
+                )))
+            }
+            _ if is_drive => {}
+            _ => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return Err(CompressionError::InvalidFormat(format!(
+            "ZIP entry name '{name}' has no file name"
+        )));
+    }
+    Ok(parts.join("/"))
+}
+
+/// The input bytes wrapped as a result that was not re-encoded (`fell_back` set).
+///
+/// `ratio_vs_raw` compares the input to the raw size from `metadata`, and
+/// is 1.0 when the dimensions are unknown (0x0).
+fn unchanged_result(
+    data: &[u8],
+    metadata: ImageMetadata,
+    processing_time: u64,
+) -> CompressionResult {
+    let raw_size = metadata.raw_size();
+    CompressionResult {
+        data: data.to_vec(),
+        original_size: data.len(),
+        compressed_size: data.len(),
+        compression_ratio: 1.0,
+        ratio_vs_raw: if raw_size > 0 {
+            data.len() as f32 / raw_size as f32
+        } else {
+            1.0
+        },
+        output_width: metadata.width,
+        output_height: metadata.height,
+        format: metadata.format.clone(),
+        processing_time,
+        timings: Timings::default(),
+        metadata,
+        used_tiled_resize: false,
+        tile_count: 0,
+        upscaled: false,
+        fell_back: true,
+    }
+}
+
+/// Error once `options.per_image_timeout` has elapsed since `start_time`
+fn check_deadline(start_time: Instant, options: &CompressionOptions) -> Result<()> {
+    match options.per_image_timeout {
+        Some(timeout) if start_time.elapsed() > timeout => Err(CompressionError::Timeout(format!(
+            "image exceeded its {}ms processing budget",
+            timeout.as_millis()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Instant at which `options.per_image_timeout` runs out for an image
+/// started at `start_time`
+fn deadline(start_time: Instant, options: &CompressionOptions) -> Option<Instant> {
+    options
+        .per_image_timeout
+        .map(|timeout| start_time + timeout)
+}
+
+/// Error once `deadline` has passed; for checks inside long loops
+pub(crate) fn check_until(deadline: Option<Instant>) -> Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() > deadline => Err(CompressionError::Timeout(
+            "image exceeded its processing budget".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// MIME type for an output format name
+fn mime_type(format: &str) -> String {
+    match format {
+        "png" => "image/png".to_string(),
+        "jpeg" | "jpg" => "image/jpeg".to_string(),
+        "webp" => "image/webp".to_string(),
+        "avif" => "image/avif".to_string(),
+        "apng" => "image/apng".to_string(),
+        "gif" => "image/gif".to_string(),
+        "tiff" | "tif" => "image/tiff".to_string(),
+        other => format!("image/{other}"),
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -460,7 +2912,7 @@ mod tests {
     fn test_compression_engine_new() {
         let engine = CompressionEngine::new();
         // Test that engine can be created
-        assert!(std::ptr::addr_of!(engine) as *const _ != std::ptr::null());
+        assert!(!std::ptr::addr_of!(engine).is_null());
     }
 
     #[test]
@@ -468,8 +2920,7 @@ mod tests {
         let options = CompressionOptions {
             format: None,
             quality: None,
-            resize: None,
-            optimize: None,
+            ..Default::default()
         };
 
         assert!(options.format.is_none());
@@ -487,6 +2938,7 @@ mod tests {
             color_type: "rgba".to_string(),
             bit_depth: 8,
             has_transparency: false,
+            srgb_compatible: true,
         };
 
         let result = CompressionResult {
@@ -494,9 +2946,17 @@ mod tests {
             original_size: 1000,
             compressed_size: 500,
             compression_ratio: 0.5,
+            ratio_vs_raw: 0.0125,
+            output_width: 100,
+            output_height: 100,
             format: "webp".to_string(),
             processing_time: 100,
+            timings: Timings::default(),
             metadata,
+            used_tiled_resize: false,
+            tile_count: 0,
+            upscaled: false,
+            fell_back: false,
         };
 
         let cloned = result.clone();
@@ -505,14 +2965,38 @@ mod tests {
         assert_eq!(result.compressed_size, cloned.compressed_size);
     }
 
+    #[test]
+    fn test_conversion_options_follow_source() {
+        let engine = CompressionEngine::new();
+
+        let img =
+            image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 0]));
+        let mut png_data = Vec::new();
+        image::DynamicImage::ImageRgb8(img.clone())
+            .write_to(
+                &mut std::io::Cursor::new(&mut png_data),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let png_options = engine.conversion_options(&png_data, "webp").unwrap();
+        assert!(png_options.optimize.unwrap().lossless);
+
+        let mut jpeg_data = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg_data, 60)
+            .encode_image(&img)
+            .unwrap();
+        let jpeg_options = engine.conversion_options(&jpeg_data, "webp").unwrap();
+        assert!(!jpeg_options.optimize.unwrap().lossless);
+        assert!(jpeg_options.quality.unwrap() <= 65);
+    }
+
     #[test]
     fn test_compress_empty_data() {
         let engine = CompressionEngine::new();
         let options = CompressionOptions {
             format: Some("webp".to_string()),
             quality: Some(80),
-            resize: None,
-            optimize: None,
+            ..Default::default()
         };
 
         let result = engine.compress(&[], &options);
@@ -0,0 +1,378 @@
+//! Pluggable output encoders
+//!
+//! [`CompressionEngine`](crate::CompressionEngine) looks encoders up by
+//! format name. The built-in formats are registered at construction time;
+//! downstream crates can add formats (or replace a built-in) with
+//! [`CompressionEngine::register_encoder`](crate::CompressionEngine::register_encoder).
+
+use crate::{compression::OptimizeOptions, CompressionError, Result};
+use image::DynamicImage;
+use std::collections::HashMap;
+
+/// What an encoder can preserve in its output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatCapabilities {
+    /// Transparency survives encoding
+    pub supports_alpha: bool,
+    /// Multiple frames can be written
+    pub supports_animation: bool,
+    /// Pixels can be stored without loss
+    pub supports_lossless: bool,
+    /// Highest bits per channel the encoder writes
+    pub max_bit_depth: u8,
+}
+
+/// Encoder for one output format
+pub trait FormatEncoder: Send + Sync {
+    /// Format name matched against [`CompressionOptions::format`](crate::CompressionOptions::format)
+    fn name(&self) -> &str;
+
+    /// Encode the fully processed image
+    fn encode(&self, img: &DynamicImage, quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>>;
+
+    /// Encode with the raw `params` from
+    /// [`CompressionOptions::extra_params`](crate::CompressionOptions::extra_params).
+    ///
+    /// Encoders read the keys they recognise and ignore the rest; the
+    /// default ignores all of them.
+    fn encode_with_params(
+        &self,
+        img: &DynamicImage,
+        quality: u8,
+        opts: &OptimizeOptions,
+        _params: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        self.encode(img, quality, opts)
+    }
+
+    /// Quality used when the options leave it unset
+    fn default_quality(&self, _opts: &OptimizeOptions) -> u8 {
+        80
+    }
+
+    /// Features the output keeps; the default claims only opaque 8-bit stills
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: false,
+            supports_animation: false,
+            supports_lossless: false,
+            max_bit_depth: 8,
+        }
+    }
+}
+
+/// Encoders for every format enabled by the crate features
+pub(crate) fn builtin_encoders() -> Vec<Box<dyn FormatEncoder>> {
+    let mut encoders: Vec<Box<dyn FormatEncoder>> = Vec::new();
+    #[cfg(feature = "png")]
+    {
+        encoders.push(Box::new(PngFormat));
+        encoders.push(Box::new(ApngFormat));
+    }
+    #[cfg(feature = "jpeg")]
+    encoders.push(Box::new(JpegFormat));
+    #[cfg(feature = "webp")]
+    encoders.push(Box::new(WebPFormat));
+    #[cfg(feature = "tiff")]
+    encoders.push(Box::new(TiffFormat));
+    #[cfg(all(feature = "avif", not(target_arch = "wasm32")))]
+    encoders.push(Box::new(AvifFormat));
+    encoders
+}
+
+/// Map format aliases to the registered name
+pub(crate) fn canonical_name(format: &str) -> &str {
+    match format {
+        "jpg" => "jpeg",
+        "tif" => "tiff",
+        other => other,
+    }
+}
+
+/// Parse the value of a recognised extra parameter, if present
+#[cfg(any(feature = "webp", all(feature = "avif", not(target_arch = "wasm32"))))]
+fn extra_param<T: std::str::FromStr>(
+    params: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<T>> {
+    params
+        .get(key)
+        .map(|value| {
+            value.trim().parse().map_err(|_| {
+                CompressionError::InvalidFormat(format!(
+                    "Invalid value '{value}' for encoder parameter '{key}'"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Error for a format with no registered encoder
+pub(crate) fn unsupported_format(format: &str) -> CompressionError {
+    if format == "avif" && cfg!(target_arch = "wasm32") {
+        return CompressionError::UnsupportedFeature("avif unavailable on wasm32".to_string());
+    }
+    let feature = match format {
+        "png" | "apng" => Some(("PNG", "png")),
+        "jpeg" => Some(("JPEG", "jpeg")),
+        "webp" => Some(("WebP", "webp")),
+        "tiff" => Some(("TIFF", "tiff")),
+        "avif" => Some(("AVIF", "avif")),
+        _ => None,
+    };
+    CompressionError::UnsupportedFeature(match feature {
+        Some((label, feature)) => format!("{label} format requires the `{feature}` feature"),
+        None => format!("Format '{format}' not supported (feature not enabled)"),
+    })
+}
+
+#[cfg(feature = "png")]
+struct PngFormat;
+
+#[cfg(feature = "png")]
+impl FormatEncoder for PngFormat {
+    fn name(&self) -> &str {
+        "png"
+    }
+
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: true,
+            supports_animation: false,
+            supports_lossless: true,
+            max_bit_depth: 16,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage, _quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        // 纯 Rust PNG 编码路径：使用 image::codecs::png::PngEncoder
+        use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+        use image::ImageEncoder;
+
+        // 有损调色板量化（类似 pngquant）
+        if let Some(max_colors) = opts.max_colors {
+            return crate::formats::png::encode_quantized(img, max_colors, opts.dither_method);
+        }
+
+        let png_options = opts.png.clone().unwrap_or_default();
+        // 编码参数：在 wasm 环境避免引入任何 C 依赖
+        let png_compression = if png_options.optimization_level == 0 {
+            ::png::Compression::Fast
+        } else if opts.lossless || png_options.optimization_level >= 6 {
+            ::png::Compression::Best
+        } else {
+            ::png::Compression::Default
+        };
+
+        // 16 位源保持 16 位输出，不截断为 RGBA8，也不做位深缩减
+        if let Some(out) = crate::formats::png::encode_sixteen_bit(img, png_compression)? {
+            return Ok(out);
+        }
+
+        // 颜色足够少时保留 1/2/4 位深，避免将黑白扫描件提升为 RGBA8
+        if png_options.bit_depth_reduction {
+            if let Some(out) = crate::formats::png::encode_reduced_bit_depth(img)? {
+                return Ok(out);
+            }
+        }
+
+        // 逐行滤波搜索（最高级别时对整图穷举全部滤波器）；级别 0 始终走下方的快速路径
+        if png_options.optimization_level > 0
+            && (png_options.deflate_optimization || png_options.optimization_level >= 6)
+        {
+            return crate::formats::png::encode_filter_search(
+                img,
+                &png_options,
+                png_compression,
+                opts.deadline,
+            );
+        }
+
+        // 压缩级别与过滤器选择做一个简单映射
+        let (compression, filter) = if png_options.optimization_level == 0 {
+            (CompressionType::Fast, FilterType::Sub)
+        } else if opts.lossless {
+            (CompressionType::Best, FilterType::Paeth)
+        } else {
+            (CompressionType::Default, FilterType::Sub)
+        };
+
+        // 8 位灰度/RGB 保持原布局，其余规范化为 RGBA8（含透明）
+        let (color, data) = crate::formats::png::eight_bit_samples(img);
+        let (w, h) = (img.width(), img.height());
+
+        let mut out: Vec<u8> = Vec::with_capacity(data.len() / 2 + 1024);
+        {
+            let enc = PngEncoder::new_with_quality(&mut out, compression, filter);
+            enc.write_image(&data, w, h, color)
+                .map_err(|e| CompressionError::EncodingError(e.to_string()))?;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "png")]
+struct ApngFormat;
+
+#[cfg(feature = "png")]
+impl FormatEncoder for ApngFormat {
+    fn name(&self) -> &str {
+        "apng"
+    }
+
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: true,
+            supports_animation: true,
+            supports_lossless: true,
+            max_bit_depth: 8,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage, _quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        // 单帧输入（如原始像素或缩放变体）写为单帧 APNG
+        let frame = image::Frame::new(img.to_rgba8());
+        crate::formats::png::encode_apng(&[frame], ::png::Compression::Best, opts.deadline)
+    }
+}
+
+#[cfg(feature = "jpeg")]
+struct JpegFormat;
+
+#[cfg(feature = "jpeg")]
+impl FormatEncoder for JpegFormat {
+    fn name(&self) -> &str {
+        "jpeg"
+    }
+
+    fn encode(&self, img: &DynamicImage, quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        let mut jpeg_options = opts.jpeg.clone().unwrap_or_default();
+        jpeg_options.quality = quality;
+        jpeg_options.progressive |= opts.progressive;
+        jpeg_options.adaptive_quantization |= opts.adaptive_regions;
+        crate::formats::jpeg::encode(img, &jpeg_options)
+    }
+
+    fn default_quality(&self, opts: &OptimizeOptions) -> u8 {
+        opts.jpeg.clone().unwrap_or_default().quality
+    }
+}
+
+#[cfg(feature = "webp")]
+struct WebPFormat;
+
+#[cfg(feature = "webp")]
+impl FormatEncoder for WebPFormat {
+    fn name(&self) -> &str {
+        "webp"
+    }
+
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: true,
+            supports_animation: true,
+            supports_lossless: true,
+            max_bit_depth: 8,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage, quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        self.encode_with_params(img, quality, opts, &HashMap::new())
+    }
+
+    /// Recognised keys: `method` (0-6), `filter_strength` (0-100),
+    /// `filter_sharpness` (0-7) and `sns_strength` (0-100)
+    fn encode_with_params(
+        &self,
+        img: &DynamicImage,
+        quality: u8,
+        opts: &OptimizeOptions,
+        params: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let defaults = crate::formats::webp::WebPOptions::default();
+        let webp_options = crate::formats::webp::WebPOptions {
+            quality: quality as f32,
+            lossless: opts.lossless,
+            near_lossless: opts.near_lossless,
+            method: extra_param(params, "method")?.unwrap_or(defaults.method),
+            alpha_quality: opts.alpha_quality.unwrap_or(defaults.alpha_quality),
+            filter_strength: extra_param(params, "filter_strength")?,
+            filter_sharpness: extra_param(params, "filter_sharpness")?,
+            sns_strength: extra_param(params, "sns_strength")?,
+        };
+        crate::formats::webp::encode(img, &webp_options)
+    }
+}
+
+#[cfg(feature = "tiff")]
+struct TiffFormat;
+
+#[cfg(feature = "tiff")]
+impl FormatEncoder for TiffFormat {
+    fn name(&self) -> &str {
+        "tiff"
+    }
+
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: true,
+            supports_animation: false,
+            supports_lossless: true,
+            max_bit_depth: 8,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage, _quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        crate::formats::tiff::encode(img, &opts.tiff.clone().unwrap_or_default())
+    }
+}
+
+#[cfg(all(feature = "avif", not(target_arch = "wasm32")))]
+struct AvifFormat;
+
+#[cfg(all(feature = "avif", not(target_arch = "wasm32")))]
+impl FormatEncoder for AvifFormat {
+    fn name(&self) -> &str {
+        "avif"
+    }
+
+    fn capabilities(&self) -> FormatCapabilities {
+        FormatCapabilities {
+            supports_alpha: true,
+            supports_animation: false,
+            supports_lossless: false,
+            max_bit_depth: 10,
+        }
+    }
+
+    fn encode(&self, img: &DynamicImage, quality: u8, opts: &OptimizeOptions) -> Result<Vec<u8>> {
+        self.encode_with_params(img, quality, opts, &HashMap::new())
+    }
+
+    /// Recognised keys: `speed` (1-10) and `bit_depth` (8 or 10), which
+    /// override the typed [`AvifOptions`](crate::AvifOptions)
+    fn encode_with_params(
+        &self,
+        img: &DynamicImage,
+        quality: u8,
+        opts: &OptimizeOptions,
+        params: &HashMap<String, String>,
+    ) -> Result<Vec<u8>> {
+        let mut avif_options = opts.avif.clone().unwrap_or_default();
+        avif_options.quality = quality;
+        if let Some(alpha_quality) = opts.alpha_quality {
+            avif_options.alpha_quality = Some(alpha_quality);
+        }
+        if let Some(speed) = extra_param(params, "speed")? {
+            avif_options.speed = speed;
+        }
+        if let Some(bit_depth) = extra_param(params, "bit_depth")? {
+            avif_options.bit_depth = bit_depth;
+        }
+        crate::formats::avif::encode(img, &avif_options)
+    }
+
+    fn default_quality(&self, opts: &OptimizeOptions) -> u8 {
+        opts.avif.clone().unwrap_or_default().quality
+    }
+}
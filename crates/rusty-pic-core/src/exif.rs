@@ -0,0 +1,418 @@
+//! Minimal EXIF (APP1) support for JPEG output, plus EXIF/XMP carry-over into WebP
+//!
+//! Only what the engine needs: the orientation tag, reading/(re)writing
+//! the IFD1 thumbnail, and copying source EXIF/XMP into a WebP container.
+//! Segments are manipulated on the encoded bytes, so no re-encode is needed.
+
+use crate::{CompressionError, Result};
+use image::DynamicImage;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// VP8X feature flags
+const VP8X_ALPHA: u8 = 0x10;
+const VP8X_EXIF: u8 = 0x08;
+const VP8X_XMP: u8 = 0x04;
+
+/// Byte-order aware view of the TIFF structure inside an EXIF segment
+struct TiffView<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffView<'a> {
+    fn from_jpeg(jpeg: &'a [u8]) -> Option<Self> {
+        let (_, payload) = crate::analyzer::jpeg_segments(jpeg)
+            .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(EXIF_HEADER))?;
+        Self::new(&payload[EXIF_HEADER.len()..])
+    }
+
+    /// View over bare TIFF data (starting at the `II`/`MM` byte-order mark)
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16_at(&self, pos: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn ifd0(&self) -> Option<usize> {
+        self.u32_at(4).map(|offset| offset as usize)
+    }
+
+    /// Offset of the IFD following `ifd`, if any
+    fn next_ifd(&self, ifd: usize) -> Option<usize> {
+        let count = self.u16_at(ifd)? as usize;
+        let next = self.u32_at(ifd + 2 + count * 12)? as usize;
+        (next != 0).then_some(next)
+    }
+
+    /// Byte offset of the 12-byte entry for `tag` in `ifd`
+    fn find_entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = self.u16_at(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16_at(entry) == Some(tag))
+    }
+}
+
+/// Return the JPEG thumbnail embedded in the EXIF IFD1 of `jpeg`, if any.
+pub fn embedded_thumbnail(jpeg: &[u8]) -> Option<&[u8]> {
+    let tiff = TiffView::from_jpeg(jpeg)?;
+    // IFD0 之后的 next-IFD 偏移即 IFD1（缩略图目录）
+    let ifd1 = tiff.next_ifd(tiff.ifd0()?)?;
+
+    let offset = tiff.u32_at(tiff.find_entry(ifd1, TAG_THUMBNAIL_OFFSET)? + 8)? as usize;
+    let length = tiff.u32_at(tiff.find_entry(ifd1, TAG_THUMBNAIL_LENGTH)? + 8)? as usize;
+    tiff.data.get(offset..offset.checked_add(length)?)
+}
+
+/// Return the EXIF orientation (1-8) of `jpeg`, if present and valid.
+pub fn orientation(jpeg: &[u8]) -> Option<u16> {
+    let tiff = TiffView::from_jpeg(jpeg)?;
+    let entry = tiff.find_entry(tiff.ifd0()?, TAG_ORIENTATION)?;
+    tiff.u16_at(entry + 8)
+        .filter(|value| (1..=8).contains(value))
+}
+
+/// Return the source's EXIF as bare TIFF data (no `Exif\0\0` header).
+///
+/// Read from a JPEG APP1 segment, a PNG `eXIf` chunk or a WebP `EXIF` chunk.
+pub fn source_exif(data: &[u8]) -> Option<&[u8]> {
+    let exif = if data.starts_with(b"\x89PNG") {
+        crate::icc::png_chunks(data)
+            .find(|(kind, _)| kind == b"eXIf")?
+            .1
+    } else if data.starts_with(b"RIFF") {
+        webp_chunks(data).find(|(kind, _)| kind == b"EXIF")?.1
+    } else {
+        let (_, payload) = crate::analyzer::jpeg_segments(data)
+            .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(EXIF_HEADER))?;
+        &payload[EXIF_HEADER.len()..]
+    };
+    // 部分写入方在 WebP/PNG 中也保留了 Exif 头
+    let exif = exif.strip_prefix(EXIF_HEADER).unwrap_or(exif);
+    TiffView::new(exif).map(|_| exif)
+}
+
+/// Return the source's XMP packet.
+///
+/// Read from a JPEG APP1 segment, an uncompressed PNG `iTXt` chunk with the
+/// `XML:com.adobe.xmp` keyword or a WebP `XMP ` chunk.
+pub fn source_xmp(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(b"\x89PNG") {
+        // iTXt：关键字\0 压缩标志(1) 压缩方法(1) 语言\0 译名\0 文本
+        return crate::icc::png_chunks(data)
+            .filter(|(kind, _)| kind == b"iTXt")
+            .find_map(|(_, payload)| {
+                let rest = payload.strip_prefix(PNG_XMP_KEYWORD)?.strip_prefix(b"\0")?;
+                let (&compressed, rest) = rest.split_first()?;
+                if compressed != 0 {
+                    return None;
+                }
+                let mut fields = rest.get(1..)?.splitn(3, |&b| b == 0);
+                fields.next()?;
+                fields.next()?;
+                fields.next()
+            });
+    }
+    if data.starts_with(b"RIFF") {
+        return webp_chunks(data)
+            .find(|(kind, _)| kind == b"XMP ")
+            .map(|(_, payload)| payload);
+    }
+    crate::analyzer::jpeg_segments(data)
+        .find(|(marker, payload)| *marker == 0xE1 && payload.starts_with(JPEG_XMP_HEADER))
+        .map(|(_, payload)| &payload[JPEG_XMP_HEADER.len()..])
+}
+
+/// Copy of bare TIFF data with the orientation tag (if any) set to 1
+pub(crate) fn with_upright_orientation(exif: &[u8]) -> Vec<u8> {
+    let mut out = exif.to_vec();
+    let Some(tiff) = TiffView::new(exif) else {
+        return out;
+    };
+    if let Some(entry) = tiff
+        .ifd0()
+        .and_then(|ifd| tiff.find_entry(ifd, TAG_ORIENTATION))
+    {
+        let value = if tiff.little_endian {
+            1u16.to_le_bytes()
+        } else {
+            1u16.to_be_bytes()
+        };
+        if let Some(slot) = out.get_mut(entry + 8..entry + 10) {
+            slot.copy_from_slice(&value);
+        }
+    }
+    out
+}
+
+/// Iterate over the chunks of a WebP (RIFF) file as (FourCC, payload)
+pub fn webp_chunks(webp: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = if webp.len() >= 12 && &webp[..4] == b"RIFF" && &webp[8..12] == b"WEBP" {
+        12
+    } else {
+        webp.len()
+    };
+    std::iter::from_fn(move || {
+        let header = webp.get(pos..pos + 8)?;
+        let kind: [u8; 4] = header[..4].try_into().ok()?;
+        let len = u32::from_le_bytes(header[4..8].try_into().ok()?) as usize;
+        let payload = webp.get(pos + 8..pos + 8 + len)?;
+        // 奇数长度的块后跟 1 字节填充
+        pos += 8 + len + (len & 1);
+        Some((kind, payload))
+    })
+}
+
+/// Add `EXIF` and/or `XMP ` chunks to a WebP, converting it to the extended
+/// (VP8X) layout when needed.
+///
+/// `width`, `height` and `has_alpha` describe the encoded image and are only
+/// used when a VP8X header has to be created.
+pub fn embed_webp_metadata(
+    webp: &[u8],
+    exif: Option<&[u8]>,
+    xmp: Option<&[u8]>,
+    (width, height, has_alpha): (u32, u32, bool),
+) -> Result<Vec<u8>> {
+    if !(webp.len() >= 12 && &webp[..4] == b"RIFF" && &webp[8..12] == b"WEBP") {
+        return Err(CompressionError::InvalidFormat(
+            "WebP metadata embedding requires WebP data".to_string(),
+        ));
+    }
+    if exif.is_none() && xmp.is_none() {
+        return Ok(webp.to_vec());
+    }
+
+    let mut flags = 0;
+    if exif.is_some() {
+        flags |= VP8X_EXIF;
+    }
+    if xmp.is_some() {
+        flags |= VP8X_XMP;
+    }
+    let mut body: Vec<u8> = Vec::with_capacity(webp.len() + 64);
+    let push_chunk = |body: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]| {
+        body.extend_from_slice(kind);
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            body.push(0);
+        }
+    };
+
+    let mut chunks = webp_chunks(webp).peekable();
+    match chunks.peek() {
+        Some((kind, payload)) if kind == b"VP8X" && payload.len() >= 10 => {
+            let mut header = payload.to_vec();
+            header[0] |= flags;
+            push_chunk(&mut body, b"VP8X", &header);
+            chunks.next();
+        }
+        _ => {
+            // 简单格式：补一个 VP8X 头，画布宽高以减一的 24 位小端存储
+            if has_alpha {
+                flags |= VP8X_ALPHA;
+            }
+            let mut header = vec![flags, 0, 0, 0];
+            header.extend_from_slice(&(width.max(1) - 1).to_le_bytes()[..3]);
+            header.extend_from_slice(&(height.max(1) - 1).to_le_bytes()[..3]);
+            push_chunk(&mut body, b"VP8X", &header);
+        }
+    }
+    // 已有的 EXIF/XMP 块由源数据替换
+    for (kind, payload) in chunks.filter(|(kind, _)| kind != b"EXIF" && kind != b"XMP ") {
+        push_chunk(&mut body, &kind, payload);
+    }
+    if let Some(exif) = exif {
+        push_chunk(&mut body, b"EXIF", exif);
+    }
+    if let Some(xmp) = xmp {
+        push_chunk(&mut body, b"XMP ", xmp);
+    }
+
+    let riff_len = u32::try_from(body.len() + 4).map_err(|_| {
+        CompressionError::EncodingError("WebP output exceeds the 4GB RIFF limit".to_string())
+    })?;
+    let mut out = Vec::with_capacity(body.len() + 12);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&riff_len.to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Rotate/flip `img` so an image tagged with `orientation` displays upright
+pub fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        // 5/7 为转置与反转置：旋转后再水平翻转
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Replace any EXIF segment in `jpeg` with one carrying only `thumbnail`.
+///
+/// The new APP1 segment goes right after SOI (and a JFIF APP0, if present).
+pub fn embed_thumbnail(jpeg: &[u8], thumbnail: &[u8]) -> Result<Vec<u8>> {
+    let segment = thumbnail_segment(thumbnail)?;
+    let stripped = strip_exif(jpeg)?;
+
+    // 保持 JFIF APP0 紧跟 SOI
+    let mut insert_at = 2;
+    if let Some(&[0xFF, 0xE0, high, low]) = stripped.get(2..6) {
+        let len = u16::from_be_bytes([high, low]) as usize;
+        insert_at = (4 + len).min(stripped.len());
+    }
+
+    let mut out = Vec::with_capacity(stripped.len() + segment.len());
+    out.extend_from_slice(&stripped[..insert_at]);
+    out.extend_from_slice(&segment);
+    out.extend_from_slice(&stripped[insert_at..]);
+    Ok(out)
+}
+
+/// Remove all EXIF APP1 segments (and with them any stale thumbnail).
+pub fn strip_exif(jpeg: &[u8]) -> Result<Vec<u8>> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return Err(CompressionError::InvalidFormat(
+            "EXIF editing requires JPEG data".to_string(),
+        ));
+    }
+
+    let mut out = Vec::with_capacity(jpeg.len());
+    out.extend_from_slice(&jpeg[..2]);
+    let mut pos = 2;
+    // 只遍历 SOS 之前的带长度段，其余数据原样拷贝
+    while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF && !matches!(jpeg[pos + 1], 0xDA | 0xD9) {
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        // 长度字段包含自身的 2 字节，更小的值说明数据已损坏
+        if len < 2 {
+            return Err(CompressionError::InvalidFormat(format!(
+                "JPEG segment at offset {pos} has invalid length {len}"
+            )));
+        }
+        let end = (pos + 2 + len).min(jpeg.len());
+        let is_exif = jpeg[pos + 1] == 0xE1 && jpeg[pos + 4..end].starts_with(EXIF_HEADER);
+        if !is_exif {
+            out.extend_from_slice(&jpeg[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&jpeg[pos..]);
+    Ok(out)
+}
+
+/// Build an APP1 segment: empty IFD0 followed by an IFD1 pointing at the thumbnail
+fn thumbnail_segment(thumbnail: &[u8]) -> Result<Vec<u8>> {
+    // TIFF 头(8) + IFD0(2 + 4) + IFD1(2 + 3*12 + 4)
+    const IFD1_OFFSET: u32 = 14;
+    const DATA_OFFSET: u32 = IFD1_OFFSET + 2 + 3 * 12 + 4;
+
+    let mut tiff = Vec::with_capacity(DATA_OFFSET as usize + thumbnail.len());
+    tiff.extend_from_slice(b"II*\0");
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&0u16.to_le_bytes());
+    tiff.extend_from_slice(&IFD1_OFFSET.to_le_bytes());
+
+    tiff.extend_from_slice(&3u16.to_le_bytes());
+    let mut entry = |tag: u16, kind: u16, value: u32| {
+        tiff.extend_from_slice(&tag.to_le_bytes());
+        tiff.extend_from_slice(&kind.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&value.to_le_bytes());
+    };
+    // 3 = SHORT, 4 = LONG；Compression = 6 表示 JPEG 缩略图
+    entry(TAG_COMPRESSION, 3, 6);
+    entry(TAG_THUMBNAIL_OFFSET, 4, DATA_OFFSET);
+    entry(TAG_THUMBNAIL_LENGTH, 4, thumbnail.len() as u32);
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(thumbnail);
+
+    let segment_len = 2 + EXIF_HEADER.len() + tiff.len();
+    let segment_len = u16::try_from(segment_len).map_err(|_| {
+        CompressionError::EncodingError("EXIF thumbnail exceeds 64KB segment limit".to_string())
+    })?;
+
+    let mut segment = Vec::with_capacity(segment_len as usize + 2);
+    segment.extend_from_slice(&[0xFF, 0xE1]);
+    segment.extend_from_slice(&segment_len.to_be_bytes());
+    segment.extend_from_slice(EXIF_HEADER);
+    segment.extend_from_slice(&tiff);
+    Ok(segment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embed_and_read_thumbnail() {
+        let img = image::RgbImage::from_pixel(16, 16, image::Rgb([10, 20, 30]));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode_image(&img)
+            .unwrap();
+        assert!(embedded_thumbnail(&jpeg).is_none());
+
+        let thumbnail = [0xFF, 0xD8, 1, 2, 3, 0xFF, 0xD9];
+        let with_thumb = embed_thumbnail(&jpeg, &thumbnail).unwrap();
+        assert_eq!(embedded_thumbnail(&with_thumb), Some(&thumbnail[..]));
+
+        // 再次嵌入会替换而不是叠加
+        let replaced = embed_thumbnail(&with_thumb, &thumbnail[..4]).unwrap();
+        assert_eq!(embedded_thumbnail(&replaced), Some(&thumbnail[..4]));
+        assert_eq!(strip_exif(&replaced).unwrap(), jpeg);
+        assert!(image::load_from_memory(&replaced).is_ok());
+    }
+
+    #[test]
+    fn test_malformed_segment_lengths_are_errors() {
+        for len in [0u8, 1] {
+            let jpeg = [0xFF, 0xD8, 0xFF, 0xE1, 0x00, len, 0xFF, 0xD9];
+            assert!(strip_exif(&jpeg).is_err());
+            assert!(embed_thumbnail(&jpeg, &[0xFF, 0xD8, 0xFF, 0xD9]).is_err());
+        }
+
+        // APP0 长度超出数据末尾时不越界
+        let truncated = [0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x40, 0x4A, 0x46];
+        let out = embed_thumbnail(&truncated, &[0xFF, 0xD8, 0xFF, 0xD9]).unwrap();
+        assert!(out.starts_with(&truncated));
+    }
+}
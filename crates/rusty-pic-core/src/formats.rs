@@ -2,27 +2,21 @@
 //!
 //! This file provides optional submodules for non-PNG formats and an inline
 //! pure-Rust PNG encoder using the `image` crate. We define PNG inline to
-//! avoid extra files and any C dependencies for wasm32 builds.
-
-// Optional formats behind feature gates (modules are defined elsewhere)
-// JPEG support will be added in future versions
-// #[cfg(feature = "jpeg")]
-// pub mod jpeg;
-// AVIF support will be added in future versions
-// #[cfg(feature = "avif")]
-// pub mod avif;
-// WebP support will be added in future versions
-// #[cfg(feature = "webp")]
-// pub mod webp;
+//! avoid extra files and any C dependencies for wasm32 builds. The WebP
+//! encoder is also defined inline but only compiled with the `webp` feature,
+//! since it links libwebp. JPEG, TIFF and AVIF options are always available;
+//! their encoders need the `jpeg`, `tiff` and `avif` features respectively.
+//! The AVIF encoder is never built for wasm32, even with `avif` enabled.
 
 // Inline PNG module implementation
 pub mod png {
     #[derive(Clone, Debug)]
     pub struct PngOptions {
-        /// 兼容旧 API 的占位字段，当前实现走 image 纯 Rust 编码器
+        /// 优化级别；0 使用最快的 deflate 设置，6 及以上时使用最高 deflate 压缩并对整图穷举全部行滤波器，取最小输出
         pub optimization_level: u8,
         pub palette_optimization: bool,
         pub transparency_optimization: bool,
+        /// 按行选择绝对差之和最小的滤波器（默认开启）；优化级别 0 时忽略
         pub deflate_optimization: bool,
         pub strip_metadata: bool,
         pub interlace: bool,
@@ -45,6 +39,7 @@ pub mod png {
         }
     }
 
+    use crate::smart::quantize::{median_cut_weighted, nearest_index};
     use crate::{CompressionError, Result};
     use image::codecs::png::{CompressionType, FilterType, PngEncoder};
     use image::ImageEncoder;
@@ -64,4 +59,1292 @@ pub mod png {
             .map_err(|e| CompressionError::EncodingError(e.to_string()))?;
         Ok(out)
     }
+
+    /// 8-bit grey, grey+alpha and RGB samples as-is; anything else as RGBA8
+    pub(crate) fn eight_bit_samples(
+        img: &image::DynamicImage,
+    ) -> (image::ColorType, std::borrow::Cow<'_, [u8]>) {
+        use image::DynamicImage;
+        use std::borrow::Cow;
+        match img {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_) => (img.color(), Cow::Borrowed(img.as_bytes())),
+            _ => (
+                image::ColorType::Rgba8,
+                Cow::Owned(img.to_rgba8().into_raw()),
+            ),
+        }
+    }
+
+    /// Encode at 8 bits with a searched choice of PNG row filters.
+    ///
+    /// Grey, grey+alpha and RGB input keep their layout; other colour types
+    /// are written as RGBA.
+    ///
+    /// Each scanline takes the filter with the smallest sum of absolute
+    /// differences. At `optimization_level` 6 and above every fixed filter is
+    /// also tried for the whole image (like optipng's brute mode) and the
+    /// smallest file wins; each pass first checks `deadline`.
+    pub fn encode_filter_search(
+        img: &image::DynamicImage,
+        opts: &PngOptions,
+        compression: ::png::Compression,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Vec<u8>> {
+        use ::png::{AdaptiveFilterType, FilterType};
+
+        let (color, samples) = eight_bit_samples(img);
+        let (w, h) = (img.width(), img.height());
+        let map_err = |e: ::png::EncodingError| CompressionError::EncodingError(e.to_string());
+        let png_color = match color {
+            image::ColorType::L8 => ::png::ColorType::Grayscale,
+            image::ColorType::La8 => ::png::ColorType::GrayscaleAlpha,
+            image::ColorType::Rgb8 => ::png::ColorType::Rgb,
+            _ => ::png::ColorType::Rgba,
+        };
+
+        let encode = |filter: FilterType, adaptive: AdaptiveFilterType| -> Result<Vec<u8>> {
+            let mut out = Vec::with_capacity(samples.len() / 2 + 1024);
+            {
+                let mut encoder = ::png::Encoder::new(&mut out, w, h);
+                encoder.set_color(png_color);
+                encoder.set_depth(::png::BitDepth::Eight);
+                encoder.set_compression(compression);
+                encoder.set_filter(filter);
+                encoder.set_adaptive_filter(adaptive);
+                let mut writer = encoder.write_header().map_err(map_err)?;
+                writer.write_image_data(&samples).map_err(map_err)?;
+                writer.finish().map_err(map_err)?;
+            }
+            Ok(out)
+        };
+
+        let mut best = encode(FilterType::Paeth, AdaptiveFilterType::Adaptive)?;
+        if opts.optimization_level >= 6 {
+            for filter in [
+                FilterType::NoFilter,
+                FilterType::Sub,
+                FilterType::Up,
+                FilterType::Avg,
+                FilterType::Paeth,
+            ] {
+                crate::compression::check_until(deadline)?;
+                let candidate = encode(filter, AdaptiveFilterType::NonAdaptive)?;
+                if candidate.len() < best.len() {
+                    best = candidate;
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// Encode 16-bit sources (`L16`, `La16`, `Rgb16`, `Rgba16`) at 16 bits per sample.
+    ///
+    /// Returns `None` for any other colour type, which the caller encodes at
+    /// 8 bits as usual.
+    pub fn encode_sixteen_bit(
+        img: &image::DynamicImage,
+        compression: ::png::Compression,
+    ) -> Result<Option<Vec<u8>>> {
+        use image::DynamicImage;
+
+        let (color, samples): (::png::ColorType, &[u16]) = match img {
+            DynamicImage::ImageLuma16(buf) => (::png::ColorType::Grayscale, buf.as_raw()),
+            DynamicImage::ImageLumaA16(buf) => (::png::ColorType::GrayscaleAlpha, buf.as_raw()),
+            DynamicImage::ImageRgb16(buf) => (::png::ColorType::Rgb, buf.as_raw()),
+            DynamicImage::ImageRgba16(buf) => (::png::ColorType::Rgba, buf.as_raw()),
+            _ => return Ok(None),
+        };
+        // PNG 的 16 位采样为大端序
+        let bytes: Vec<u8> = samples.iter().flat_map(|v| v.to_be_bytes()).collect();
+
+        let map_err = |e: ::png::EncodingError| CompressionError::EncodingError(e.to_string());
+        let mut out = Vec::with_capacity(bytes.len() / 2 + 1024);
+        {
+            let mut encoder = ::png::Encoder::new(&mut out, img.width(), img.height());
+            encoder.set_color(color);
+            encoder.set_depth(::png::BitDepth::Sixteen);
+            encoder.set_compression(compression);
+            encoder.set_adaptive_filter(::png::AdaptiveFilterType::Adaptive);
+            let mut writer = encoder.write_header().map_err(map_err)?;
+            writer.write_image_data(&bytes).map_err(map_err)?;
+            writer.finish().map_err(map_err)?;
+        }
+        Ok(Some(out))
+    }
+
+    /// Encode RGBA frames of equal size as an animated PNG that loops forever.
+    ///
+    /// Frame delays are kept at millisecond precision. `deadline` is checked
+    /// before each frame.
+    pub fn encode_apng(
+        frames: &[image::Frame],
+        compression: ::png::Compression,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Vec<u8>> {
+        let first = frames.first().ok_or_else(|| {
+            CompressionError::EncodingError("APNG needs at least one frame".to_string())
+        })?;
+        let (w, h) = first.buffer().dimensions();
+        let map_err = |e: ::png::EncodingError| CompressionError::EncodingError(e.to_string());
+
+        let mut out = Vec::new();
+        {
+            let mut encoder = ::png::Encoder::new(&mut out, w, h);
+            encoder.set_color(::png::ColorType::Rgba);
+            encoder.set_depth(::png::BitDepth::Eight);
+            encoder.set_compression(compression);
+            encoder.set_adaptive_filter(::png::AdaptiveFilterType::Adaptive);
+            encoder
+                .set_animated(frames.len() as u32, 0)
+                .map_err(map_err)?;
+            let mut writer = encoder.write_header().map_err(map_err)?;
+
+            for frame in frames {
+                crate::compression::check_until(deadline)?;
+                if frame.buffer().dimensions() != (w, h) {
+                    return Err(CompressionError::EncodingError(
+                        "APNG frames must share the canvas size".to_string(),
+                    ));
+                }
+                let (num, den) = frame.delay().numer_denom_ms();
+                let delay_ms = (num as f64 / den.max(1) as f64)
+                    .round()
+                    .min(u16::MAX as f64);
+                writer
+                    .set_frame_delay(delay_ms as u16, 1000)
+                    .map_err(map_err)?;
+                writer
+                    .write_image_data(frame.buffer().as_raw())
+                    .map_err(map_err)?;
+            }
+            writer.finish().map_err(map_err)?;
+        }
+        Ok(out)
+    }
+
+    /// Encode at 1/2/4 bits per pixel when the image allows it without loss.
+    ///
+    /// Grayscale images whose levels all sit on the N-bit grid (e.g. black and
+    /// white scans) become N-bit grayscale; anything else with at most 16
+    /// distinct RGBA colours becomes an N-bit indexed PNG. Returns `None` when
+    /// the image needs 8 bits or more.
+    pub fn encode_reduced_bit_depth(img: &image::DynamicImage) -> Result<Option<Vec<u8>>> {
+        let rgba = img.to_rgba8();
+        let (w, h) = (rgba.width(), rgba.height());
+
+        // 收集调色板，超过 16 色即放弃
+        let mut palette: Vec<[u8; 4]> = Vec::with_capacity(16);
+        let mut indices = Vec::with_capacity((w * h) as usize);
+        for pixel in rgba.pixels() {
+            let index = match palette.iter().position(|c| *c == pixel.0) {
+                Some(i) => i,
+                None if palette.len() < 16 => {
+                    palette.push(pixel.0);
+                    palette.len() - 1
+                }
+                None => return Ok(None),
+            };
+            indices.push(index as u8);
+        }
+
+        let depth_for = |count: usize| match count {
+            0..=2 => 1u8,
+            3..=4 => 2,
+            _ => 4,
+        };
+
+        // 不透明灰度且灰阶恰好落在 N 位网格上时，直接写灰度（无需 PLTE）
+        let is_gray = palette
+            .iter()
+            .all(|c| c[0] == c[1] && c[1] == c[2] && c[3] == 255);
+        if is_gray {
+            for depth in [1u8, 2, 4] {
+                let step = 255 / ((1u16 << depth) - 1) as u8;
+                if palette.iter().all(|c| c[0] % step == 0) {
+                    let samples: Vec<u8> = rgba.pixels().map(|p| p[0] / step).collect();
+                    return write_packed(w, h, depth, ::png::ColorType::Grayscale, &samples, None)
+                        .map(Some);
+                }
+            }
+        }
+
+        let depth = depth_for(palette.len());
+        write_packed(
+            w,
+            h,
+            depth,
+            ::png::ColorType::Indexed,
+            &indices,
+            Some(&palette),
+        )
+        .map(Some)
+    }
+
+    /// How quantization error is hidden when mapping to a reduced palette
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum DitherMethod {
+        /// Map every pixel to its nearest palette entry (flat bands)
+        None,
+        /// Diffuse each pixel's error to its right and lower neighbours
+        #[default]
+        FloydSteinberg,
+        /// Add a 4x4 Bayer threshold pattern before mapping
+        Ordered,
+    }
+
+    /// Quantize to at most `max_colors` (2-256) and write an indexed PNG.
+    ///
+    /// The palette comes from median cut over the image's RGBA colours; images
+    /// that already fit are kept exact. Alpha is quantized with the colour but
+    /// never dithered.
+    pub fn encode_quantized(
+        img: &image::DynamicImage,
+        max_colors: usize,
+        dither: DitherMethod,
+    ) -> Result<Vec<u8>> {
+        let rgba = img.to_rgba8();
+        let (w, h) = (rgba.width(), rgba.height());
+        let max_colors = max_colors.clamp(2, 256);
+
+        let mut histogram: std::collections::HashMap<[u8; 4], u32> =
+            std::collections::HashMap::new();
+        for pixel in rgba.pixels() {
+            *histogram.entry(pixel.0).or_insert(0) += 1;
+        }
+        let exact = histogram.len() <= max_colors;
+        // HashMap 遍历顺序每次运行都不同，排序后保证调色板与输出字节可复现
+        let mut colors: Vec<([u8; 4], u32)> = histogram.into_iter().collect();
+        colors.sort_unstable();
+        let palette = median_cut_weighted(colors, max_colors);
+        let indices = if exact {
+            map_pixels(&rgba, &palette, DitherMethod::None)
+        } else {
+            map_pixels(&rgba, &palette, dither)
+        };
+
+        let depth = match palette.len() {
+            0..=2 => 1u8,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        write_packed(
+            w,
+            h,
+            depth,
+            ::png::ColorType::Indexed,
+            &indices,
+            Some(&palette),
+        )
+    }
+
+    /// Palette index of each pixel, dithered per `dither`
+    pub(crate) fn map_pixels(
+        rgba: &image::RgbaImage,
+        palette: &[[u8; 4]],
+        dither: DitherMethod,
+    ) -> Vec<u8> {
+        const BAYER_4X4: [[u8; 4]; 4] =
+            [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+        let mut cache: std::collections::HashMap<[u8; 4], u8> = std::collections::HashMap::new();
+        let mut nearest = |px: [u8; 4]| {
+            *cache
+                .entry(px)
+                .or_insert_with(|| nearest_index(px, palette) as u8)
+        };
+
+        let (w, h) = (rgba.width() as usize, rgba.height() as usize);
+        let mut indices = Vec::with_capacity(w * h);
+        match dither {
+            DitherMethod::None => {
+                indices.extend(rgba.pixels().map(|p| nearest(p.0)));
+            }
+            DitherMethod::Ordered => {
+                // 阈值幅度约为调色板在每个通道上的间距
+                let spread = 255.0 / (palette.len() as f32).cbrt();
+                for (x, y, p) in rgba.enumerate_pixels() {
+                    let t = (BAYER_4X4[y as usize % 4][x as usize % 4] as f32 + 0.5) / 16.0 - 0.5;
+                    let mut px = p.0;
+                    for v in &mut px[..3] {
+                        *v = (*v as f32 + t * spread).round().clamp(0.0, 255.0) as u8;
+                    }
+                    indices.push(nearest(px));
+                }
+            }
+            DitherMethod::FloydSteinberg => {
+                // 当前行与下一行的 RGB 累积误差（两端各留一列哨兵）
+                let mut current = vec![[0f32; 3]; w + 2];
+                let mut next = vec![[0f32; 3]; w + 2];
+                for y in 0..h {
+                    for x in 0..w {
+                        let p = rgba.get_pixel(x as u32, y as u32).0;
+                        let mut wanted = [0f32; 3];
+                        let mut px = p;
+                        for c in 0..3 {
+                            wanted[c] = (p[c] as f32 + current[x + 1][c]).clamp(0.0, 255.0);
+                            px[c] = wanted[c].round() as u8;
+                        }
+                        let index = nearest(px);
+                        indices.push(index);
+
+                        let chosen = palette[index as usize];
+                        for c in 0..3 {
+                            let err = wanted[c] - chosen[c] as f32;
+                            current[x + 2][c] += err * 7.0 / 16.0;
+                            next[x][c] += err * 3.0 / 16.0;
+                            next[x + 1][c] += err * 5.0 / 16.0;
+                            next[x + 2][c] += err * 1.0 / 16.0;
+                        }
+                    }
+                    std::mem::swap(&mut current, &mut next);
+                    next.iter_mut().for_each(|e| *e = [0.0; 3]);
+                }
+            }
+        }
+        indices
+    }
+
+    /// Pack N-bit (1/2/4/8) samples MSB-first per row and write them as a PNG
+    fn write_packed(
+        w: u32,
+        h: u32,
+        depth: u8,
+        color: ::png::ColorType,
+        samples: &[u8],
+        palette: Option<&[[u8; 4]]>,
+    ) -> Result<Vec<u8>> {
+        let per_byte = (8 / depth) as usize;
+        let row_bytes = (w as usize).div_ceil(per_byte);
+        let mut packed = vec![0u8; row_bytes * h as usize];
+        for (row, src) in packed
+            .chunks_exact_mut(row_bytes)
+            .zip(samples.chunks(w as usize))
+        {
+            for (x, &v) in src.iter().enumerate() {
+                let shift = 8 - depth as usize * (x % per_byte + 1);
+                row[x / per_byte] |= v << shift;
+            }
+        }
+
+        let bit_depth = match depth {
+            1 => ::png::BitDepth::One,
+            2 => ::png::BitDepth::Two,
+            4 => ::png::BitDepth::Four,
+            _ => ::png::BitDepth::Eight,
+        };
+
+        let map_err = |e: ::png::EncodingError| CompressionError::EncodingError(e.to_string());
+        let mut out = Vec::new();
+        {
+            let mut encoder = ::png::Encoder::new(&mut out, w, h);
+            encoder.set_color(color);
+            encoder.set_depth(bit_depth);
+            encoder.set_compression(::png::Compression::Best);
+            if let Some(palette) = palette {
+                encoder.set_palette(
+                    palette
+                        .iter()
+                        .flat_map(|c| [c[0], c[1], c[2]])
+                        .collect::<Vec<_>>(),
+                );
+                if palette.iter().any(|c| c[3] != 255) {
+                    encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+                }
+            }
+            let mut writer = encoder.write_header().map_err(map_err)?;
+            writer.write_image_data(&packed).map_err(map_err)?;
+            writer.finish().map_err(map_err)?;
+        }
+        Ok(out)
+    }
+}
+
+// Inline GIF module implementation (pure-Rust `gif` crate)
+#[cfg(feature = "gif")]
+pub mod gif {
+    use crate::formats::png::{map_pixels, DitherMethod};
+    use crate::smart::quantize::median_cut_weighted;
+    use crate::{CompressionError, Result};
+
+    /// Alpha below this is written as the transparent index
+    const ALPHA_THRESHOLD: u8 = 128;
+
+    /// Encode `frames` as one GIF whose frames all use a single global palette.
+    ///
+    /// The palette is built by median cut over the colours of every frame, so
+    /// sprites and sequences that share colours map to identical indices. When
+    /// any pixel is transparent one entry is reserved for transparency. The
+    /// global table holds at most `max_colors` (2-256) entries and no frame
+    /// carries a local table.
+    pub fn encode_shared_palette(
+        frames: &[image::RgbaImage],
+        max_colors: usize,
+    ) -> Result<Vec<u8>> {
+        let map_err = |e: ::gif::EncodingError| CompressionError::EncodingError(e.to_string());
+        if frames.is_empty() {
+            return Err(CompressionError::InvalidFormat(
+                "GIF output needs at least one image".to_string(),
+            ));
+        }
+        let width = frames.iter().map(|f| f.width()).max().unwrap_or(0);
+        let height = frames.iter().map(|f| f.height()).max().unwrap_or(0);
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(CompressionError::UnsupportedFeature(format!(
+                "GIF dimensions are limited to 65535, got {width}x{height}"
+            )));
+        };
+        let max_colors = max_colors.clamp(2, 256);
+
+        // 所有帧共用一个直方图；透明像素不参与调色板，只占一个保留索引
+        let mut histogram: std::collections::HashMap<[u8; 4], u32> =
+            std::collections::HashMap::new();
+        let mut has_transparency = false;
+        for pixel in frames.iter().flat_map(|f| f.pixels()) {
+            if pixel[3] < ALPHA_THRESHOLD {
+                has_transparency = true;
+            } else {
+                let [r, g, b, _] = pixel.0;
+                *histogram.entry([r, g, b, 255]).or_insert(0) += 1;
+            }
+        }
+        let opaque_slots = max_colors - usize::from(has_transparency);
+        let exact = histogram.len() <= opaque_slots;
+        let mut colors: Vec<([u8; 4], u32)> = histogram.into_iter().collect();
+        colors.sort_unstable();
+        let mut palette = median_cut_weighted(colors, opaque_slots.max(1));
+        let transparent = has_transparency.then(|| {
+            palette.push([0, 0, 0, 0]);
+            (palette.len() - 1) as u8
+        });
+        // GIF 颜色表长度必须是 2 的幂，不足部分由编码器补零
+        let rgb_palette: Vec<u8> = palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+        let dither = if exact {
+            DitherMethod::None
+        } else {
+            DitherMethod::FloydSteinberg
+        };
+
+        let mut out = Vec::new();
+        {
+            let mut encoder =
+                ::gif::Encoder::new(&mut out, width, height, &rgb_palette).map_err(map_err)?;
+            if frames.len() > 1 {
+                encoder
+                    .set_repeat(::gif::Repeat::Infinite)
+                    .map_err(map_err)?;
+            }
+            for rgba in frames {
+                // 映射时只在不透明调色板中查找，透明像素随后改写为保留索引
+                let mut indices = map_pixels(
+                    rgba,
+                    &palette[..palette.len() - usize::from(has_transparency)],
+                    dither,
+                );
+                if let Some(index) = transparent {
+                    for (slot, pixel) in indices.iter_mut().zip(rgba.pixels()) {
+                        if pixel[3] < ALPHA_THRESHOLD {
+                            *slot = index;
+                        }
+                    }
+                }
+                let frame = ::gif::Frame {
+                    width: rgba.width() as u16,
+                    height: rgba.height() as u16,
+                    delay: 10,
+                    dispose: ::gif::DisposalMethod::Background,
+                    transparent,
+                    buffer: std::borrow::Cow::Owned(indices),
+                    ..::gif::Frame::default()
+                };
+                encoder.write_frame(&frame).map_err(map_err)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+// Inline ICO container writer (PNG-compressed entries)
+pub mod ico {
+    use crate::{CompressionError, Result};
+
+    /// Largest edge an ICO directory entry can describe
+    pub const MAX_ICON_SIZE: u32 = 256;
+
+    /// Pack `icons` into one ICO file, each entry stored as a 32-bit RGBA PNG.
+    ///
+    /// PNG entries are understood by every browser and by Windows Vista and
+    /// later. Entries keep the given order.
+    pub fn encode(icons: &[image::RgbaImage]) -> Result<Vec<u8>> {
+        if icons.is_empty() || icons.len() > u16::MAX as usize {
+            return Err(CompressionError::InvalidFormat(format!(
+                "ICO needs 1-65535 images, got {}",
+                icons.len()
+            )));
+        }
+        let payloads = icons
+            .iter()
+            .map(|icon| {
+                let (width, height) = icon.dimensions();
+                if width == 0 || height == 0 || width > MAX_ICON_SIZE || height > MAX_ICON_SIZE {
+                    return Err(CompressionError::InvalidFormat(format!(
+                        "ICO images must be 1-{MAX_ICON_SIZE}px per side, got {width}x{height}"
+                    )));
+                }
+                crate::formats::png::encode_optimized(
+                    &image::DynamicImage::ImageRgba8(icon.clone()),
+                    &crate::formats::png::PngOptions::default(),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // ICONDIR（6 字节）+ 每项 16 字节的 ICONDIRENTRY，之后依次为各 PNG 数据
+        let mut out = Vec::with_capacity(6 + 16 * icons.len());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&(icons.len() as u16).to_le_bytes());
+        let mut offset = 6 + 16 * icons.len();
+        for (icon, payload) in icons.iter().zip(&payloads) {
+            // 尺寸字段为单字节，0 表示 256
+            out.push(icon.width() as u8);
+            out.push(icon.height() as u8);
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&1u16.to_le_bytes());
+            out.extend_from_slice(&32u16.to_le_bytes());
+            out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += payload.len();
+        }
+        for payload in payloads {
+            out.extend_from_slice(&payload);
+        }
+        Ok(out)
+    }
+}
+
+/// Minimal ZIP archive writer for already-compressed payloads
+pub mod zip {
+    use crate::{CompressionError, Result};
+
+    /// Builds a ZIP archive entry by entry using the stored method (no
+    /// deflate), since image payloads are already compressed.
+    ///
+    /// Each entry is written out as soon as it is added; only the central
+    /// directory records are kept until [`ZipWriter::finish`]. ZIP64 is not
+    /// supported, so entries and the archive must stay below 4 GiB.
+    #[derive(Debug, Default)]
+    pub struct ZipWriter {
+        out: Vec<u8>,
+        central_directory: Vec<u8>,
+        entries: u16,
+    }
+
+    // 1980-01-01 00:00（DOS 时间戳的最早值），保证输出可复现
+    const DOS_DATE: u16 = (1 << 5) | 1;
+    const DOS_TIME: u16 = 0;
+    // 通用标志位 11：文件名为 UTF-8
+    const UTF8_NAMES: u16 = 1 << 11;
+
+    impl ZipWriter {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Append one stored entry named `name` (use `/` as separator)
+        pub fn add_stored(&mut self, name: &str, data: &[u8]) -> Result<()> {
+            let too_large = |what: &str| {
+                CompressionError::InvalidFormat(format!("ZIP {what} exceeds the non-ZIP64 limits"))
+            };
+            if self.entries == u16::MAX {
+                return Err(too_large("entry count"));
+            }
+            let size = u32::try_from(data.len()).map_err(|_| too_large("entry size"))?;
+            let offset = u32::try_from(self.out.len()).map_err(|_| too_large("archive size"))?;
+            let name_len = u16::try_from(name.len()).map_err(|_| too_large("entry name"))?;
+            let crc = crate::icc::crc32(data.iter());
+
+            // 本地文件头与中央目录共用的字段：版本、标志、方法(0=stored)、时间、CRC、大小、文件名长度
+            let mut common = Vec::with_capacity(26);
+            common.extend_from_slice(&20u16.to_le_bytes());
+            common.extend_from_slice(&UTF8_NAMES.to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+            common.extend_from_slice(&DOS_TIME.to_le_bytes());
+            common.extend_from_slice(&DOS_DATE.to_le_bytes());
+            common.extend_from_slice(&crc.to_le_bytes());
+            common.extend_from_slice(&size.to_le_bytes());
+            common.extend_from_slice(&size.to_le_bytes());
+            common.extend_from_slice(&name_len.to_le_bytes());
+            common.extend_from_slice(&0u16.to_le_bytes());
+
+            self.out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+            self.out.extend_from_slice(&common);
+            self.out.extend_from_slice(name.as_bytes());
+            self.out.extend_from_slice(data);
+
+            let cd = &mut self.central_directory;
+            cd.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            cd.extend_from_slice(&20u16.to_le_bytes());
+            cd.extend_from_slice(&common);
+            // 注释长度、磁盘号、内部/外部属性
+            cd.extend_from_slice(&[0; 10]);
+            cd.extend_from_slice(&offset.to_le_bytes());
+            cd.extend_from_slice(name.as_bytes());
+
+            self.entries += 1;
+            Ok(())
+        }
+
+        /// Write the central directory and return the finished archive
+        pub fn finish(mut self) -> Result<Vec<u8>> {
+            let too_large =
+                || CompressionError::InvalidFormat("ZIP archive exceeds 4 GiB".to_string());
+            let cd_offset = u32::try_from(self.out.len()).map_err(|_| too_large())?;
+            let cd_size = u32::try_from(self.central_directory.len()).map_err(|_| too_large())?;
+            self.out.extend_from_slice(&self.central_directory);
+
+            // 中央目录结束记录（EOCD）
+            self.out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+            self.out.extend_from_slice(&[0; 4]);
+            self.out.extend_from_slice(&self.entries.to_le_bytes());
+            self.out.extend_from_slice(&self.entries.to_le_bytes());
+            self.out.extend_from_slice(&cd_size.to_le_bytes());
+            self.out.extend_from_slice(&cd_offset.to_le_bytes());
+            self.out.extend_from_slice(&0u16.to_le_bytes());
+            Ok(self.out)
+        }
+    }
+}
+
+// Inline WebP module implementation (libwebp via the `webp` crate)
+#[cfg(feature = "webp")]
+pub mod webp {
+    use crate::{CompressionError, Result};
+    use image::DynamicImage;
+
+    #[derive(Clone, Debug)]
+    pub struct WebPOptions {
+        /// 有损质量（0-100）
+        pub quality: f32,
+        pub lossless: bool,
+        /// near-lossless 预处理级别（0-100，100 等同关闭，数值越小预处理越强）。
+        /// 设置后编码器切换到无损模式，这是 libwebp 的要求。
+        pub near_lossless: Option<u8>,
+        /// 压缩速度/质量权衡（0=最快，6=最慢）
+        pub method: u8,
+        pub alpha_quality: u8,
+        /// 环路滤波强度（0-100，0 为关闭）；`None` 使用 libwebp 默认值
+        pub filter_strength: Option<u8>,
+        /// 滤波锐度（0-7）
+        pub filter_sharpness: Option<u8>,
+        /// 空间噪声整形强度（0-100）
+        pub sns_strength: Option<u8>,
+    }
+
+    impl Default for WebPOptions {
+        fn default() -> Self {
+            Self {
+                quality: 80.0,
+                lossless: false,
+                near_lossless: None,
+                method: 4,
+                alpha_quality: 100,
+                filter_strength: None,
+                filter_sharpness: None,
+                sns_strength: None,
+            }
+        }
+    }
+
+    /// Encode an image to WebP with the given options
+    pub fn encode(img: &DynamicImage, opts: &WebPOptions) -> Result<Vec<u8>> {
+        let mut config = webp::WebPConfig::new().map_err(|_| {
+            CompressionError::EncodingError("Failed to initialise WebP config".to_string())
+        })?;
+
+        let lossless = opts.lossless || opts.near_lossless.is_some();
+        config.lossless = lossless as i32;
+        config.quality = opts.quality.clamp(0.0, 100.0);
+        config.method = opts.method.min(6) as i32;
+        config.alpha_quality = opts.alpha_quality.min(100) as i32;
+        if let Some(level) = opts.near_lossless {
+            config.near_lossless = level.min(100) as i32;
+        }
+        if let Some(strength) = opts.filter_strength {
+            config.filter_strength = strength.min(100) as i32;
+        }
+        if let Some(sharpness) = opts.filter_sharpness {
+            config.filter_sharpness = sharpness.min(7) as i32;
+        }
+        if let Some(strength) = opts.sns_strength {
+            config.sns_strength = strength.min(100) as i32;
+        }
+
+        // libwebp 仅接受 RGB/RGBA 排列；其余颜色类型先规范化
+        let memory = if img.color().has_alpha() {
+            let rgba = img.to_rgba8();
+            webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height())
+                .encode_advanced(&config)
+        } else {
+            let rgb = img.to_rgb8();
+            webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+                .encode_advanced(&config)
+        }
+        .map_err(|e| CompressionError::EncodingError(format!("WebP encoding failed: {e:?}")))?;
+
+        Ok(memory.to_vec())
+    }
+
+    /// Transcode a PNG to lossless WebP without going through `DynamicImage`.
+    ///
+    /// Samples are expanded to 8 bits but keep the source's channel layout:
+    /// RGB (and opaque palette) PNGs are encoded without an alpha channel,
+    /// RGBA, grey+alpha and palette-with-tRNS PNGs with one. Plain greyscale is
+    /// widened to RGB, since libwebp has no greyscale input.
+    #[cfg(feature = "png")]
+    pub fn transcode_png_lossless(png_bytes: &[u8]) -> Result<Vec<u8>> {
+        let map_err = |e: ::png::DecodingError| CompressionError::InvalidFormat(e.to_string());
+
+        let mut decoder = ::png::Decoder::new(png_bytes);
+        decoder
+            .set_transformations(::png::Transformations::EXPAND | ::png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(map_err)?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(map_err)?;
+        buf.truncate(info.buffer_size());
+        let (w, h) = (info.width, info.height);
+
+        // EXPAND 之后调色板已展开为 RGB/RGBA，灰度保持 1 或 2 通道
+        let (pixels, has_alpha) = match info.color_type {
+            ::png::ColorType::Rgb => (buf, false),
+            ::png::ColorType::Rgba => (buf, true),
+            ::png::ColorType::Grayscale => (buf.iter().flat_map(|&v| [v, v, v]).collect(), false),
+            ::png::ColorType::GrayscaleAlpha => (
+                buf.chunks_exact(2)
+                    .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                    .collect(),
+                true,
+            ),
+            ::png::ColorType::Indexed => {
+                return Err(CompressionError::InvalidFormat(
+                    "PNG palette was not expanded".to_string(),
+                ))
+            }
+        };
+
+        let mut config = webp::WebPConfig::new().map_err(|_| {
+            CompressionError::EncodingError("Failed to initialise WebP config".to_string())
+        })?;
+        config.lossless = 1;
+        config.quality = 100.0;
+        config.method = 6;
+
+        let encoder = if has_alpha {
+            webp::Encoder::from_rgba(&pixels, w, h)
+        } else {
+            webp::Encoder::from_rgb(&pixels, w, h)
+        };
+        let memory = encoder
+            .encode_advanced(&config)
+            .map_err(|e| CompressionError::EncodingError(format!("WebP encoding failed: {e:?}")))?;
+        Ok(memory.to_vec())
+    }
+}
+
+pub mod jpeg {
+    #[derive(Clone, Debug)]
+    pub struct JpegOptions {
+        pub quality: u8,
+        pub progressive: bool,
+        /// Insert DRI/RSTn markers every N MCUs to limit corruption spread
+        pub restart_interval: Option<u16>,
+        /// Spend bits by region: smooth flat 8x8 blocks so they quantize to
+        /// little more than DC, raise quality for the detailed ones, and use
+        /// per-image tables from [`derive_quant_tables`]
+        pub adaptive_quantization: bool,
+        /// Force 4:2:0 (`Some(true)`) or 4:4:4 (`Some(false)`) chroma
+        /// sampling; `None` keeps the encoder default (4:4:4 from quality 90)
+        pub subsample_chroma: Option<bool>,
+    }
+
+    impl Default for JpegOptions {
+        fn default() -> Self {
+            Self {
+                quality: 85,
+                progressive: false,
+                restart_interval: None,
+                adaptive_quantization: false,
+                subsample_chroma: None,
+            }
+        }
+    }
+
+    /// Encode an image to baseline or progressive JPEG
+    #[cfg(feature = "jpeg")]
+    pub fn encode(img: &image::DynamicImage, opts: &JpegOptions) -> crate::Result<Vec<u8>> {
+        use crate::CompressionError;
+        use image::DynamicImage;
+        use jpeg_encoder::{ColorType, Encoder, QuantizationTableType, SamplingFactor};
+
+        let (w, h) = (img.width(), img.height());
+        let (w, h) = match (u16::try_from(w), u16::try_from(h)) {
+            (Ok(w), Ok(h)) => (w, h),
+            _ => {
+                return Err(CompressionError::EncodingError(format!(
+                    "JPEG dimensions {w}x{h} exceed 65535"
+                )))
+            }
+        };
+
+        // 灰度（含近似灰度）源只写亮度分量，省去两个色度平面
+        let grayscale = is_grayscale(img);
+
+        // 自适应模式：平坦块预平滑，细节块占比越高整体质量提升越多
+        let adaptive;
+        let (img, quality) = if opts.adaptive_quantization {
+            let (filtered, detail_share) = adaptive_prefilter(&img.to_rgb8());
+            adaptive = DynamicImage::ImageRgb8(filtered);
+            let boost = (detail_share * ADAPTIVE_QUALITY_BOOST).round() as u8;
+            (&adaptive, opts.quality.saturating_add(boost))
+        } else {
+            (img, opts.quality)
+        };
+
+        let mut out = Vec::new();
+        let mut encoder = Encoder::new(&mut out, quality.clamp(1, 100));
+        encoder.set_progressive(opts.progressive);
+        if opts.adaptive_quantization {
+            let (luma, chroma) = derive_quant_tables(img, quality);
+            encoder.set_quantization_tables(
+                QuantizationTableType::Custom(Box::new(luma)),
+                QuantizationTableType::Custom(Box::new(chroma)),
+            );
+            if opts.quality < 90 {
+                // 质量提升不应改变色度采样（编码器在 90 及以上切换为 4:4:4）
+                encoder.set_sampling_factor(SamplingFactor::F_2_2);
+            }
+        }
+        match opts.subsample_chroma {
+            Some(true) => encoder.set_sampling_factor(SamplingFactor::F_2_2),
+            Some(false) => encoder.set_sampling_factor(SamplingFactor::F_1_1),
+            None => {}
+        }
+        if let Some(interval) = opts.restart_interval {
+            encoder.set_restart_interval(interval);
+        }
+
+        // JPEG 无 alpha：灰度写单通道，其余统一转 RGB
+        let result = match img {
+            DynamicImage::ImageLuma8(gray) => encoder.encode(gray.as_raw(), w, h, ColorType::Luma),
+            _ if grayscale => encoder.encode(img.to_luma8().as_raw(), w, h, ColorType::Luma),
+            _ => encoder.encode(img.to_rgb8().as_raw(), w, h, ColorType::Rgb),
+        };
+        result
+            .map_err(|e| CompressionError::EncodingError(format!("JPEG encoding failed: {e}")))?;
+
+        Ok(out)
+    }
+
+    /// Annex K luminance table in natural (row-major) order
+    const STD_LUMA_TABLE: [u16; 64] = [
+        16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+        56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81,
+        104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+    ];
+    /// Annex K chrominance table in natural (row-major) order
+    const STD_CHROMA_TABLE: [u16; 64] = [
+        17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99,
+        99, 47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+        99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    ];
+    /// Blocks sampled per plane when measuring DCT energy
+    const QUANT_SAMPLE_BLOCKS: usize = 1024;
+
+    /// Annex K luma/chroma tables scaled to `quality` with the IJG convention.
+    ///
+    /// Entries are in natural (row-major) order.
+    pub fn standard_quant_tables(quality: u8) -> ([u16; 64], [u16; 64]) {
+        let quality = quality.clamp(1, 100) as u32;
+        let scale = if quality < 50 {
+            5000 / quality
+        } else {
+            200 - quality * 2
+        };
+        let scaled =
+            |table: &[u16; 64]| table.map(|v| ((v as u32 * scale + 50) / 100).clamp(1, 255) as u16);
+        (scaled(&STD_LUMA_TABLE), scaled(&STD_CHROMA_TABLE))
+    }
+
+    /// Derive luma/chroma quantization tables from the image's DCT energy.
+    ///
+    /// Starts from [`standard_quant_tables`] and, per AC coefficient, compares
+    /// the measured energy with what the standard step size assumes:
+    /// coefficients carrying more energy than expected get finer steps, the
+    /// rest coarser ones (0.5x-2x, normalised so the geometric mean of the
+    /// adjustments is 1). DC steps are kept. Entries are in natural order.
+    pub fn derive_quant_tables(img: &image::DynamicImage, quality: u8) -> ([u16; 64], [u16; 64]) {
+        let (luma_std, chroma_std) = standard_quant_tables(quality);
+        let rgb = img.to_rgb8();
+        let (width, height) = rgb.dimensions();
+
+        // 全范围 BT.601 的 Y/Cb/Cr 平面（减去 128 的电平偏移）
+        let planes = [
+            |p: &image::Rgb<u8>| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32,
+            |p: &image::Rgb<u8>| {
+                -0.168_736 * p[0] as f32 - 0.331_264 * p[1] as f32 + 0.5 * p[2] as f32 + 128.0
+            },
+            |p: &image::Rgb<u8>| {
+                0.5 * p[0] as f32 - 0.418_688 * p[1] as f32 - 0.081_312 * p[2] as f32 + 128.0
+            },
+        ];
+
+        let blocks: Vec<(u32, u32)> = (0..height / 8)
+            .flat_map(|by| (0..width / 8).map(move |bx| (bx * 8, by * 8)))
+            .collect();
+        if blocks.is_empty() {
+            return (luma_std, chroma_std);
+        }
+        let step = blocks.len().div_ceil(QUANT_SAMPLE_BLOCKS);
+
+        let mut energy = [[0f64; 64]; 3];
+        let mut sampled = 0f64;
+        for &(x0, y0) in blocks.iter().step_by(step) {
+            for (plane, to_sample) in planes.iter().enumerate() {
+                let mut block = [0f32; 64];
+                for (i, v) in block.iter_mut().enumerate() {
+                    let p = rgb.get_pixel(x0 + i as u32 % 8, y0 + i as u32 / 8);
+                    *v = to_sample(p) - 128.0;
+                }
+                for (e, c) in energy[plane].iter_mut().zip(dct_8x8(&block)) {
+                    *e += (c as f64) * (c as f64);
+                }
+            }
+            sampled += 1.0;
+        }
+
+        let adjust = |std: &[u16; 64], energy: &[f64; 64]| {
+            // 相对能量：实测 RMS 乘以标准步长；对数域去均值后按 -0.3 次幂缩放
+            let log_rel: Vec<f64> = (1..64)
+                .map(|k| ((energy[k] / sampled).sqrt() * std[k] as f64 + 1.0).ln())
+                .collect();
+            let mean = log_rel.iter().sum::<f64>() / log_rel.len() as f64;
+            let mut table = *std;
+            for (k, l) in (1..64).zip(log_rel) {
+                let factor = (-0.3 * (l - mean)).exp().clamp(0.5, 2.0);
+                table[k] = (std[k] as f64 * factor).round().clamp(1.0, 255.0) as u16;
+            }
+            table
+        };
+
+        let mut chroma_energy = [0f64; 64];
+        for (k, e) in chroma_energy.iter_mut().enumerate() {
+            *e = (energy[1][k] + energy[2][k]) / 2.0;
+        }
+        (
+            adjust(&luma_std, &energy[0]),
+            adjust(&chroma_std, &chroma_energy),
+        )
+    }
+
+    /// Orthonormal 2-D DCT-II of an 8x8 block in natural order
+    fn dct_8x8(block: &[f32; 64]) -> [f32; 64] {
+        let basis = |u: usize, x: usize| {
+            let scale = if u == 0 { (1.0f32 / 8.0).sqrt() } else { 0.5 };
+            scale * (((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.0).cos()
+        };
+        let mut out = [0f32; 64];
+        for v in 0..8 {
+            for u in 0..8 {
+                let mut sum = 0f32;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        sum += block[y * 8 + x] * basis(u, x) * basis(v, y);
+                    }
+                }
+                out[v * 8 + u] = sum;
+            }
+        }
+        out
+    }
+
+    /// Luma variance below which an 8x8 block counts as flat
+    #[cfg(feature = "jpeg")]
+    const FLAT_BLOCK_VARIANCE: f32 = 30.0;
+    /// Luma variance above which an 8x8 block counts as detailed
+    #[cfg(feature = "jpeg")]
+    const DETAIL_BLOCK_VARIANCE: f32 = 400.0;
+    /// Quality added when every block is detailed
+    #[cfg(feature = "jpeg")]
+    const ADAPTIVE_QUALITY_BOOST: f32 = 12.0;
+    /// Largest channel spread at which an RGB pixel still counts as grey
+    #[cfg(feature = "jpeg")]
+    const NEAR_GRAY_TOLERANCE: u8 = 2;
+
+    /// Grey source, or colour source whose pixels are all (nearly) neutral
+    #[cfg(feature = "jpeg")]
+    fn is_grayscale(img: &image::DynamicImage) -> bool {
+        use image::DynamicImage;
+        match img {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_) => true,
+            _ => img.to_rgb8().pixels().all(|p| {
+                let [r, g, b] = p.0;
+                r.max(g).max(b) - r.min(g).min(b) <= NEAR_GRAY_TOLERANCE
+            }),
+        }
+    }
+
+    /// Smooth flat 8x8 blocks and report the share of detailed blocks
+    #[cfg(feature = "jpeg")]
+    fn adaptive_prefilter(rgb: &image::RgbImage) -> (image::RgbImage, f32) {
+        let (width, height) = rgb.dimensions();
+        let blurred =
+            crate::performance::SimdProcessor::box_blur_rgb(rgb.as_raw(), width, height, 1);
+        let mut out = rgb.clone();
+        let (mut blocks, mut detailed) = (0u32, 0u32);
+
+        for by in (0..height).step_by(8) {
+            for bx in (0..width).step_by(8) {
+                let (x1, y1) = ((bx + 8).min(width), (by + 8).min(height));
+                let (mut sum, mut sum_sq, mut n) = (0f32, 0f32, 0f32);
+                for y in by..y1 {
+                    for x in bx..x1 {
+                        let p = rgb.get_pixel(x, y);
+                        let luma = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+                        sum += luma;
+                        sum_sq += luma * luma;
+                        n += 1.0;
+                    }
+                }
+                let mean = sum / n;
+                let variance = sum_sq / n - mean * mean;
+
+                blocks += 1;
+                if variance > DETAIL_BLOCK_VARIANCE {
+                    detailed += 1;
+                } else if variance < FLAT_BLOCK_VARIANCE {
+                    // 平坦块用 3x3 盒式模糊结果替换，去掉只会耗费码率的细噪声
+                    for y in by..y1 {
+                        for x in bx..x1 {
+                            let i = (y as usize * width as usize + x as usize) * 3;
+                            out.put_pixel(
+                                x,
+                                y,
+                                image::Rgb([blurred[i], blurred[i + 1], blurred[i + 2]]),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        (out, detailed as f32 / blocks.max(1) as f32)
+    }
+}
+
+pub mod tiff {
+    /// Lossless compression scheme for TIFF output
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum TiffCompression {
+        None,
+        #[default]
+        Lzw,
+        Deflate,
+        PackBits,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    pub struct TiffOptions {
+        pub compression: TiffCompression,
+    }
+
+    /// Encode an image to TIFF with the selected compression
+    #[cfg(feature = "tiff")]
+    pub fn encode(img: &image::DynamicImage, opts: &TiffOptions) -> crate::Result<Vec<u8>> {
+        use crate::CompressionError;
+        use ::tiff::encoder::{colortype, compression, TiffEncoder};
+        use image::DynamicImage;
+
+        // 按颜色类型与压缩方式分发到 tiff 的泛型编码接口
+        macro_rules! write_with {
+            ($encoder:expr, $color:ty, $w:expr, $h:expr, $data:expr) => {
+                match opts.compression {
+                    TiffCompression::None => $encoder.write_image_with_compression::<$color, _>(
+                        $w,
+                        $h,
+                        compression::Uncompressed,
+                        $data,
+                    ),
+                    TiffCompression::Lzw => $encoder
+                        .write_image_with_compression::<$color, _>($w, $h, compression::Lzw, $data),
+                    TiffCompression::Deflate => $encoder.write_image_with_compression::<$color, _>(
+                        $w,
+                        $h,
+                        compression::Deflate::default(),
+                        $data,
+                    ),
+                    TiffCompression::PackBits => $encoder
+                        .write_image_with_compression::<$color, _>(
+                            $w,
+                            $h,
+                            compression::Packbits,
+                            $data,
+                        ),
+                }
+            };
+        }
+
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut cursor)
+            .map_err(|e| CompressionError::EncodingError(format!("TIFF encoding failed: {e}")))?;
+
+        let (w, h) = (img.width(), img.height());
+        match img {
+            DynamicImage::ImageLuma8(gray) => {
+                write_with!(encoder, colortype::Gray8, w, h, gray.as_raw())
+            }
+            _ if img.color().has_alpha() => {
+                let rgba = img.to_rgba8();
+                write_with!(encoder, colortype::RGBA8, w, h, rgba.as_raw())
+            }
+            _ => {
+                let rgb = img.to_rgb8();
+                write_with!(encoder, colortype::RGB8, w, h, rgb.as_raw())
+            }
+        }
+        .map_err(|e| CompressionError::EncodingError(format!("TIFF encoding failed: {e}")))?;
+
+        Ok(cursor.into_inner())
+    }
+}
+
+pub mod avif {
+    /// Internal colour representation of the AV1 payload
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum AvifColorSpace {
+        /// YCbCr 4:4:4, the usual choice for photographic content
+        #[default]
+        YCbCr,
+        /// Untransformed RGB; larger files, only for content that needs it
+        Rgb,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct AvifOptions {
+        /// 有损质量（1-100）
+        pub quality: u8,
+        /// rav1e 速度预设（1=最慢最好，10=最快）
+        pub speed: u8,
+        /// Encoded bit depth: 8 or 10. 16-bit sources keep their extra
+        /// precision at 10 bits instead of being truncated to 8 first.
+        pub bit_depth: u8,
+        pub color_space: AvifColorSpace,
+        /// Alpha plane quality (1-100); `None` derives it from `quality`
+        pub alpha_quality: Option<u8>,
+    }
+
+    impl Default for AvifOptions {
+        fn default() -> Self {
+            Self {
+                quality: 80,
+                speed: 6,
+                bit_depth: 8,
+                color_space: AvifColorSpace::YCbCr,
+                alpha_quality: None,
+            }
+        }
+    }
+
+    /// Encode an image to AVIF with the pure-Rust rav1e encoder
+    #[cfg(all(feature = "avif", not(target_arch = "wasm32")))]
+    pub fn encode(img: &image::DynamicImage, opts: &AvifOptions) -> crate::Result<Vec<u8>> {
+        use crate::CompressionError;
+        use ravif::{BitDepth, ColorModel, Encoder, Img, MatrixCoefficients, RGBA8};
+
+        let depth = match opts.bit_depth {
+            8 => BitDepth::Eight,
+            10 => BitDepth::Ten,
+            12 => {
+                return Err(CompressionError::UnsupportedFeature(
+                    "12-bit AVIF is not supported by the rav1e encoder; use 8 or 10".to_string(),
+                ))
+            }
+            other => {
+                return Err(CompressionError::InvalidFormat(format!(
+                    "AVIF bit depth must be 8 or 10, got {other}"
+                )))
+            }
+        };
+
+        let mut encoder = Encoder::new()
+            .with_quality(opts.quality.clamp(1, 100) as f32)
+            .with_speed(opts.speed.clamp(1, 10))
+            .with_bit_depth(depth)
+            .with_internal_color_model(match opts.color_space {
+                AvifColorSpace::YCbCr => ColorModel::YCbCr,
+                AvifColorSpace::Rgb => ColorModel::RGB,
+            });
+        if let Some(alpha_quality) = opts.alpha_quality {
+            encoder = encoder.with_alpha_quality(alpha_quality.clamp(1, 100) as f32);
+        }
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let map_err =
+            |e: ravif::Error| CompressionError::EncodingError(format!("AVIF encoding failed: {e}"));
+
+        let encoded = if depth == BitDepth::Eight {
+            let pixels: Vec<RGBA8> = img
+                .to_rgba8()
+                .pixels()
+                .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+                .collect();
+            encoder
+                .encode_rgba(Img::new(&pixels[..], width, height))
+                .map_err(map_err)?
+        } else {
+            // 10 位路径直接从 16 位数据取高 10 位，8 位源会被等比扩展
+            let rgba = img.to_rgba16();
+            let to_10 = |v: u16| v >> 6;
+            let planes = rgba.pixels().map(|p| {
+                let (r, g, b) = (to_10(p[0]), to_10(p[1]), to_10(p[2]));
+                match opts.color_space {
+                    // AV1 的 identity 矩阵按 GBR 顺序存储
+                    AvifColorSpace::Rgb => [g, b, r],
+                    AvifColorSpace::YCbCr => ycbcr_10_bit(r, g, b),
+                }
+            });
+            let alpha = img
+                .color()
+                .has_alpha()
+                .then(|| rgba.pixels().map(|p| to_10(p[3])).collect::<Vec<_>>());
+            let matrix = match opts.color_space {
+                AvifColorSpace::Rgb => MatrixCoefficients::Identity,
+                AvifColorSpace::YCbCr => MatrixCoefficients::BT601,
+            };
+            encoder
+                .encode_raw_planes_10_bit(
+                    width,
+                    height,
+                    planes,
+                    alpha,
+                    rav1e::prelude::PixelRange::Full,
+                    matrix,
+                )
+                .map_err(map_err)?
+        };
+
+        Ok(encoded.avif_file)
+    }
+
+    /// Full-range BT.601 RGB -> YCbCr at 10 bits
+    #[cfg(all(feature = "avif", not(target_arch = "wasm32")))]
+    fn ycbcr_10_bit(r: u16, g: u16, b: u16) -> [u16; 3] {
+        let (r, g, b) = (r as f32, g as f32, b as f32);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = (b - y) * (0.5 / 0.886) + 512.0;
+        let cr = (r - y) * (0.5 / 0.701) + 512.0;
+        [y, cb, cr].map(|v| v.round().clamp(0.0, 1023.0) as u16)
+    }
 }
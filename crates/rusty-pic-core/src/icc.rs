@@ -0,0 +1,154 @@
+//! Minimal colour-profile support for encoded output
+//!
+//! Outputs never carry the source's ICC profile; for sRGB content a 13-byte
+//! PNG `sRGB` chunk says the same thing as a multi-KB profile.
+//! Chunks are spliced into the encoded bytes, so no re-encode is needed.
+
+use crate::{CompressionError, Result};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+/// Return the ICC profile embedded in a PNG (`iCCP`) or JPEG (APP2), if any.
+///
+/// Multi-segment JPEG profiles are reassembled in sequence order.
+pub fn embedded_profile(data: &[u8]) -> Option<Vec<u8>> {
+    #[cfg(feature = "png")]
+    if data.starts_with(PNG_SIGNATURE) {
+        let reader = ::png::Decoder::new(std::io::Cursor::new(data))
+            .read_info()
+            .ok()?;
+        return reader.info().icc_profile.as_ref().map(|p| p.to_vec());
+    }
+
+    // APP2 载荷：标识串 + 序号(1 字节) + 总段数(1 字节) + 配置文件片段
+    let mut parts: Vec<(u8, &[u8])> = crate::analyzer::jpeg_segments(data)
+        .filter(|(marker, payload)| *marker == 0xE2 && payload.starts_with(JPEG_ICC_HEADER))
+        .filter_map(|(_, payload)| {
+            let rest = &payload[JPEG_ICC_HEADER.len()..];
+            Some((*rest.first()?, rest.get(2..)?))
+        })
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+    parts.sort_by_key(|(seq, _)| *seq);
+    Some(
+        parts
+            .into_iter()
+            .flat_map(|(_, part)| part.to_vec())
+            .collect(),
+    )
+}
+
+/// Whether `data` declares sRGB: a PNG `sRGB` chunk, or an embedded
+/// profile whose description names sRGB (e.g. "sRGB IEC61966-2.1").
+///
+/// Images without any colour information are treated as sRGB too, which is
+/// how browsers display them.
+pub fn is_srgb_compatible(data: &[u8]) -> bool {
+    if data.starts_with(PNG_SIGNATURE) && png_chunks(data).any(|(kind, _)| kind == *b"sRGB") {
+        return true;
+    }
+    match embedded_profile(data) {
+        Some(profile) => is_srgb_profile(&profile),
+        None => true,
+    }
+}
+
+/// Heuristic check for an sRGB ICC profile by its description text.
+///
+/// v2 profiles store the description as ASCII (`desc`), v4 as UTF-16BE
+/// (`mluc`), so both encodings are searched.
+pub fn is_srgb_profile(profile: &[u8]) -> bool {
+    const ASCII: &[u8] = b"sRGB";
+    const UTF16: &[u8] = &[0, b's', 0, b'R', 0, b'G', 0, b'B'];
+    profile.windows(ASCII.len()).any(|w| w == ASCII)
+        || profile.windows(UTF16.len()).any(|w| w == UTF16)
+}
+
+/// Iterate over PNG chunks as (type, payload), stopping at malformed data
+pub fn png_chunks(png: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = if png.starts_with(PNG_SIGNATURE) {
+        PNG_SIGNATURE.len()
+    } else {
+        png.len()
+    };
+
+    std::iter::from_fn(move || {
+        // 长度(4) + 类型(4) + 数据 + CRC(4)
+        let len = u32::from_be_bytes(png.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind: [u8; 4] = png.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let payload = png.get(pos + 8..(pos + 8).checked_add(len)?)?;
+        pos += 12 + len;
+        Some((kind, payload))
+    })
+}
+
+/// Insert an `sRGB` chunk (perceptual intent) right after IHDR.
+///
+/// Any `iCCP`, `gAMA` or `cHRM` chunk is dropped, since the PNG spec lets
+/// `sRGB` override them and a decoder seeing both may pick either.
+pub fn tag_srgb_png(png: &[u8]) -> Result<Vec<u8>> {
+    let mut chunks = png_chunks(png).peekable();
+    if chunks.peek().map(|(kind, _)| kind) != Some(b"IHDR") {
+        return Err(CompressionError::InvalidFormat(
+            "sRGB tagging requires PNG data".to_string(),
+        ));
+    }
+
+    let mut out = Vec::with_capacity(png.len() + 13);
+    out.extend_from_slice(PNG_SIGNATURE);
+    for (kind, payload) in chunks {
+        if matches!(&kind, b"sRGB" | b"iCCP" | b"gAMA" | b"cHRM") {
+            continue;
+        }
+        write_chunk(&mut out, kind, payload);
+        if &kind == b"IHDR" {
+            // 0 = 感知渲染意图
+            write_chunk(&mut out, *b"sRGB", &[0]);
+        }
+    }
+    Ok(out)
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: [u8; 4], payload: &[u8]) {
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(&kind);
+    out.extend_from_slice(payload);
+    let crc = crc32(kind.iter().chain(payload));
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 (IEEE, reflected) as used by PNG chunk trailers and ZIP entries
+pub(crate) fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_matches_png_iend() {
+        // IEND 块的 CRC 是固定值 AE 42 60 82
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+    }
+
+    #[test]
+    fn test_is_srgb_profile_matches_both_encodings() {
+        assert!(is_srgb_profile(b"....desc....sRGB IEC61966-2.1...."));
+        assert!(is_srgb_profile(&[
+            b'm', b'l', b'u', b'c', 0, b's', 0, b'R', 0, b'G', 0, b'B'
+        ]));
+        assert!(!is_srgb_profile(b"....desc....Display P3...."));
+    }
+}
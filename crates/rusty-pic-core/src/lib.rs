@@ -5,30 +5,38 @@
 
 pub mod analyzer;
 pub mod compression;
+pub mod encoder;
+pub mod exif;
+pub mod icc;
+pub mod logger;
+pub mod metrics;
 pub mod performance;
 pub mod smart;
 // Use file-based formats.rs only; ensure no directory module conflict
 #[path = "formats.rs"]
 pub mod formats;
 
-pub use analyzer::{ImageAnalysis, ImageAnalyzer, ImageMetadata};
-pub use compression::{CompressionEngine, CompressionOptions, CompressionResult};
-// AVIF support will be added in future versions
-// #[cfg(feature = "avif")]
-// pub use formats::avif::{AvifColorSpace, AvifOptions, AvifSubsample};
-// JPEG support will be added in future versions
-// #[cfg(feature = "jpeg")]
-// pub use formats::jpeg::{JpegColorSpace, JpegOptions};
-pub use formats::png::PngOptions;
-// WebP support will be added in future versions
-// #[cfg(feature = "webp")]
-// pub use formats::webp::WebPOptions;
+pub use analyzer::{
+    BackgroundDetect, ChannelStat, ChannelStats, ImageAnalysis, ImageAnalyzer, ImageMetadata,
+};
+pub use compression::{
+    BatchOptions, CompressionEngine, CompressionOptions, CompressionResult, ErrorPolicy,
+    FormatComparison, NormalizeOptions, Profile, Timings,
+};
+pub use encoder::{FormatCapabilities, FormatEncoder};
+pub use formats::avif::{AvifColorSpace, AvifOptions};
+pub use formats::jpeg::JpegOptions;
+pub use formats::png::{DitherMethod, PngOptions};
+pub use formats::tiff::{TiffCompression, TiffOptions};
+#[cfg(feature = "webp")]
+pub use formats::webp::WebPOptions;
+pub use logger::LogCallback;
 pub use performance::{
     MemoryPool, OptimizedImageBuffer, ParallelProcessor, SimdProcessor, ZeroCopyTransfer,
 };
 pub use smart::{
-    AdvancedImageAnalysis, ColorAnalysis, FrequencyAnalysis, SmartCompressionConstraints,
-    SmartCompressionEngine,
+    AdvancedImageAnalysis, ColorAnalysis, FrequencyAnalysis, ImageClass, JointSizeFit,
+    ProgressCallback, SizeModel, SmartCompressionConstraints, SmartCompressionEngine,
 };
 
 /// Core error types for the compression engine
@@ -54,6 +62,12 @@ pub enum CompressionError {
 
     #[error("Analysis error: {0}")]
     AnalysisError(String),
+
+    #[error("Operation cancelled: {0}")]
+    Cancelled(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
 }
 
 pub type Result<T> = std::result::Result<T, CompressionError>;
@@ -72,14 +86,14 @@ mod tests {
     fn test_analyzer_creation() {
         let analyzer = ImageAnalyzer::new();
         // Test that analyzer can be created without panicking
-        assert!(std::ptr::addr_of!(analyzer) as *const _ != std::ptr::null());
+        assert!(!std::ptr::addr_of!(analyzer).is_null());
     }
 
     #[test]
     fn test_compression_engine_creation() {
         let engine = CompressionEngine::new();
         // Test that engine can be created without panicking
-        assert!(std::ptr::addr_of!(engine) as *const _ != std::ptr::null());
+        assert!(!std::ptr::addr_of!(engine).is_null());
     }
 }
 /// Example usage of the smart compression functionality
@@ -115,6 +129,7 @@ mod smart_compression_examples {
                 width: Some(800),
                 height: Some(600),
                 fit: "contain".to_string(),
+                ..Default::default()
             }),
         };
 
@@ -0,0 +1,104 @@
+//! Diagnostic message routing
+//!
+//! Engines send their progress/diagnostic messages through a [`Logger`],
+//! which forwards them to the `log` facade (with the `logging` feature) and,
+//! if one is installed, to a user callback. The callback works without the
+//! `logging` feature so WASM hosts can route messages to `console.log`.
+
+use std::sync::Arc;
+
+/// User-supplied sink for diagnostic messages
+pub type LogCallback = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Severity of a diagnostic message
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Level {
+    Debug,
+    Info,
+    Warn,
+}
+
+#[cfg(feature = "logging")]
+impl From<Level> for log::Level {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Debug => log::Level::Debug,
+            Level::Info => log::Level::Info,
+            Level::Warn => log::Level::Warn,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Logger {
+    callback: Option<LogCallback>,
+}
+
+impl Logger {
+    pub(crate) fn new() -> Self {
+        Self { callback: None }
+    }
+
+    pub(crate) fn set_callback(&mut self, callback: LogCallback) {
+        self.callback = Some(callback);
+    }
+
+    /// Whether any sink would receive a `level` message from `target`
+    /// (avoids formatting otherwise)
+    pub(crate) fn is_active(&self, level: Level, target: &str) -> bool {
+        #[cfg(feature = "logging")]
+        if log::log_enabled!(target: target, level.into()) {
+            return true;
+        }
+        #[cfg(not(feature = "logging"))]
+        let _ = (level, target);
+        self.callback.is_some()
+    }
+
+    pub(crate) fn emit(&self, level: Level, target: &str, message: &str) {
+        #[cfg(feature = "logging")]
+        log::log!(target: target, level.into(), "{message}");
+        #[cfg(not(feature = "logging"))]
+        let _ = (level, target);
+
+        if let Some(callback) = &self.callback {
+            callback(message);
+        }
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Format and emit a message only when some sink is listening
+macro_rules! log_event {
+    ($logger:expr, $level:ident, $($arg:tt)+) => {
+        if $logger.is_active($crate::logger::Level::$level, module_path!()) {
+            $logger.emit(
+                $crate::logger::Level::$level,
+                module_path!(),
+                &format!($($arg)+),
+            );
+        }
+    };
+}
+
+pub(crate) use log_event;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inactive_without_sinks() {
+        // 未安装 log 后端时最大级别为 Off，只有回调能激活
+        let mut logger = Logger::new();
+        assert!(!logger.is_active(Level::Warn, module_path!()));
+
+        logger.set_callback(Arc::new(|_| {}));
+        assert!(logger.is_active(Level::Debug, module_path!()));
+    }
+}
@@ -0,0 +1,105 @@
+//! Image quality metrics
+//!
+//! Objective quality measures used to validate lossy encodes (e.g. comparing a
+//! decoded candidate against the source image).
+
+use crate::{CompressionError, Result};
+use image::{DynamicImage, GenericImageView, GrayImage};
+use rayon::prelude::*;
+
+/// SSIM 窗口尺寸（像素）
+const SSIM_WINDOW: u32 = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// Compute the mean structural similarity (SSIM) between two images.
+///
+/// Both images are compared on their luma channel using non-overlapping 8x8
+/// windows. Returns a value in `[-1, 1]` where `1.0` means identical.
+pub fn ssim(a: &DynamicImage, b: &DynamicImage) -> Result<f32> {
+    if a.dimensions() != b.dimensions() {
+        return Err(CompressionError::AnalysisError(format!(
+            "SSIM requires equal dimensions: {:?} vs {:?}",
+            a.dimensions(),
+            b.dimensions()
+        )));
+    }
+
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return Err(CompressionError::AnalysisError(
+            "SSIM requires a non-empty image".to_string(),
+        ));
+    }
+
+    let luma_a = a.to_luma8();
+    let luma_b = b.to_luma8();
+
+    // 小于窗口尺寸的图像整体作为一个窗口
+    let win_w = SSIM_WINDOW.min(width);
+    let win_h = SSIM_WINDOW.min(height);
+    let windows_x = width / win_w;
+    let windows_y = height / win_h;
+
+    let total: f64 = (0..windows_y)
+        .into_par_iter()
+        .map(|wy| {
+            (0..windows_x)
+                .map(|wx| window_ssim(&luma_a, &luma_b, wx * win_w, wy * win_h, win_w, win_h))
+                .sum::<f64>()
+        })
+        .sum();
+
+    Ok((total / (windows_x as f64 * windows_y as f64)) as f32)
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32, w: u32, h: u32) -> f64 {
+    let n = (w * h) as f64;
+    let mut sum_a = 0.0f64;
+    let mut sum_b = 0.0f64;
+    let mut sum_aa = 0.0f64;
+    let mut sum_bb = 0.0f64;
+    let mut sum_ab = 0.0f64;
+
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let pa = a.get_pixel(x, y)[0] as f64;
+            let pb = b.get_pixel(x, y)[0] as f64;
+            sum_a += pa;
+            sum_b += pb;
+            sum_aa += pa * pa;
+            sum_bb += pb * pb;
+            sum_ab += pa * pb;
+        }
+    }
+
+    let mean_a = sum_a / n;
+    let mean_b = sum_b / n;
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let cov = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * cov + SSIM_C2))
+        / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssim_identical_images() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb([(x * 8) as u8, (y * 8) as u8, 128])
+        }));
+        let score = ssim(&img, &img).unwrap();
+        assert!((score - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_ssim_dimension_mismatch() {
+        let a = DynamicImage::new_rgb8(16, 16);
+        let b = DynamicImage::new_rgb8(8, 8);
+        assert!(ssim(&a, &b).is_err());
+    }
+}
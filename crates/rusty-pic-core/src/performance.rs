@@ -13,18 +13,40 @@ use std::sync::Arc;
 /// SIMD-accelerated pixel processing operations
 pub struct SimdProcessor;
 
+/// Smallest block handed to a worker by the colour conversions
+const MIN_BLOCK_PIXELS: usize = 1024;
+/// Blocks per rayon thread the colour conversions aim for, so uneven
+/// blocks still balance without fragmenting small images
+const BLOCKS_PER_THREAD: usize = 3;
+
 impl SimdProcessor {
+    /// Pixels per parallel block for an image of `pixels` pixels.
+    ///
+    /// Aims for about [`BLOCKS_PER_THREAD`] blocks per rayon thread, but never
+    /// less than [`MIN_BLOCK_PIXELS`] so small images are not over-fragmented.
+    pub fn adaptive_block_pixels(pixels: usize) -> usize {
+        let blocks = rayon::current_num_threads().max(1) * BLOCKS_PER_THREAD;
+        pixels.div_ceil(blocks).max(MIN_BLOCK_PIXELS)
+    }
+
     /// "SIMD"-accelerated（当前实现为并行分块 + 标量核心，避免错误SIMD用法）
     pub fn rgb_to_yuv_simd(rgb_data: &[u8]) -> Vec<u8> {
+        let block_pixels = Self::adaptive_block_pixels(rgb_data.len() / 3);
+        Self::rgb_to_yuv_with_block(rgb_data, block_pixels)
+    }
+
+    /// [`Self::rgb_to_yuv_simd`] with an explicit block size; the output does
+    /// not depend on `block_pixels`
+    pub fn rgb_to_yuv_with_block(rgb_data: &[u8], block_pixels: usize) -> Vec<u8> {
         assert!(
-            rgb_data.len() % 3 == 0,
+            rgb_data.len().is_multiple_of(3),
             "RGB data length must be multiple of 3"
         );
 
         // 为避免在并行闭包中可变借用同一 Vec，采用“输出分片”策略：
         // 先分割输出到多个独立小 Vec，最后串行拼接。
         let pixels = rgb_data.len() / 3;
-        let block_pixels = 4096usize;
+        let block_pixels = block_pixels.max(1);
         let blocks: Vec<(usize, usize)> = (0..pixels)
             .step_by(block_pixels)
             .map(|start| {
@@ -67,13 +89,20 @@ impl SimdProcessor {
 
     /// 并行分块 + 标量核心（安全且可扩展为真实SIMD）
     pub fn yuv_to_rgb_simd(yuv_data: &[u8]) -> Vec<u8> {
+        let block_pixels = Self::adaptive_block_pixels(yuv_data.len() / 3);
+        Self::yuv_to_rgb_with_block(yuv_data, block_pixels)
+    }
+
+    /// [`Self::yuv_to_rgb_simd`] with an explicit block size; the output does
+    /// not depend on `block_pixels`
+    pub fn yuv_to_rgb_with_block(yuv_data: &[u8], block_pixels: usize) -> Vec<u8> {
         assert!(
-            yuv_data.len() % 3 == 0,
+            yuv_data.len().is_multiple_of(3),
             "YUV data length must be multiple of 3"
         );
 
         let pixels = yuv_data.len() / 3;
-        let block_pixels = 4096usize;
+        let block_pixels = block_pixels.max(1);
         let blocks: Vec<(usize, usize)> = (0..pixels)
             .step_by(block_pixels)
             .map(|start| {
@@ -113,6 +142,146 @@ impl SimdProcessor {
         rgb_data
     }
 
+    /// Drop the alpha channel of packed RGBA8 data, processing pixel blocks in parallel
+    pub fn rgba_to_rgb(rgba: &[u8]) -> Vec<u8> {
+        assert!(
+            rgba.len().is_multiple_of(4),
+            "RGBA data length must be multiple of 4"
+        );
+
+        // 每块 4096 像素，输入/输出块一一对应，无需拼接
+        let block_pixels = 4096usize;
+        let mut rgb = vec![0u8; rgba.len() / 4 * 3];
+        rgb.par_chunks_mut(block_pixels * 3)
+            .zip(rgba.par_chunks(block_pixels * 4))
+            .for_each(|(dst, src)| {
+                for (out, px) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                    out.copy_from_slice(&px[..3]);
+                }
+            });
+        rgb
+    }
+
+    /// Expand packed RGB8 data to RGBA8 with fully opaque alpha
+    pub fn rgb_to_rgba(rgb: &[u8]) -> Vec<u8> {
+        assert!(
+            rgb.len().is_multiple_of(3),
+            "RGB data length must be multiple of 3"
+        );
+
+        let block_pixels = 4096usize;
+        let mut rgba = vec![0u8; rgb.len() / 3 * 4];
+        rgba.par_chunks_mut(block_pixels * 4)
+            .zip(rgb.par_chunks(block_pixels * 3))
+            .for_each(|(dst, src)| {
+                for (out, px) in dst.chunks_exact_mut(4).zip(src.chunks_exact(3)) {
+                    out[..3].copy_from_slice(px);
+                    out[3] = 255;
+                }
+            });
+        rgba
+    }
+
+    /// Box blur packed RGB8 data with a separable sliding-window sum
+    ///
+    /// Each pass keeps a running window sum, so the cost per pixel is constant
+    /// regardless of `radius`. Rows are processed in parallel; the vertical
+    /// pass runs as a horizontal pass over the transposed image. Edges are
+    /// clamped (replicated).
+    pub fn box_blur_rgb(data: &[u8], width: u32, height: u32, radius: u32) -> Vec<u8> {
+        let (w, h) = (width as usize, height as usize);
+        assert_eq!(
+            data.len(),
+            w * h * 3,
+            "RGB data length must match dimensions"
+        );
+        if radius == 0 || w == 0 || h == 0 {
+            return data.to_vec();
+        }
+
+        Self::box_blur(data, w, h, 3, radius as usize)
+    }
+
+    /// Sharpen with an unsharp mask: `original + amount * (original - blurred)`.
+    ///
+    /// `data` is packed 8-bit samples with 1-4 `channels`; the blur is a box
+    /// blur of `radius` (rounded, at least 1). For 2- and 4-channel data the
+    /// last channel is alpha and is left untouched.
+    pub fn unsharp_mask(
+        data: &[u8],
+        width: u32,
+        height: u32,
+        channels: u8,
+        amount: f32,
+        radius: f32,
+    ) -> Vec<u8> {
+        let (w, h, ch) = (width as usize, height as usize, channels as usize);
+        assert!((1..=4).contains(&ch), "channels must be 1-4");
+        assert_eq!(data.len(), w * h * ch, "data length must match dimensions");
+        if w == 0 || h == 0 || amount == 0.0 {
+            return data.to_vec();
+        }
+
+        let radius = (radius.round() as usize).max(1);
+        let blurred = Self::box_blur(data, w, h, ch, radius);
+        let color_channels = if ch % 2 == 0 { ch - 1 } else { ch };
+
+        let mut out = data.to_vec();
+        out.par_chunks_mut(ch)
+            .zip(blurred.par_chunks(ch))
+            .for_each(|(px, blur)| {
+                for c in 0..color_channels {
+                    let v = px[c] as f32 + amount * (px[c] as f32 - blur[c] as f32);
+                    px[c] = v.round().clamp(0.0, 255.0) as u8;
+                }
+            });
+        out
+    }
+
+    /// Separable box blur over packed 8-bit samples with `ch` channels
+    fn box_blur(data: &[u8], w: usize, h: usize, ch: usize, radius: usize) -> Vec<u8> {
+        let horizontal = Self::blur_rows(data, w, h, ch, radius);
+        let transposed = Self::transpose(&horizontal, w, h, ch);
+        let vertical = Self::blur_rows(&transposed, h, w, ch, radius);
+        Self::transpose(&vertical, h, w, ch)
+    }
+
+    /// One horizontal sliding-window pass over every row
+    fn blur_rows(data: &[u8], w: usize, h: usize, ch: usize, radius: usize) -> Vec<u8> {
+        let window = (2 * radius + 1) as u32;
+        let mut out = vec![0u8; w * h * ch];
+
+        out.par_chunks_mut(w * ch)
+            .zip(data.par_chunks(w * ch))
+            .for_each(|(dst, src)| {
+                let at =
+                    |x: isize, c: usize| src[(x.clamp(0, w as isize - 1) as usize) * ch + c] as u32;
+                for c in 0..ch {
+                    let mut sum: u32 = (-(radius as isize)..=radius as isize)
+                        .map(|x| at(x, c))
+                        .sum();
+                    for x in 0..w {
+                        dst[x * ch + c] = ((sum + window / 2) / window) as u8;
+                        let xi = x as isize;
+                        sum = sum + at(xi + radius as isize + 1, c) - at(xi - radius as isize, c);
+                    }
+                }
+            });
+        out
+    }
+
+    /// Transpose a packed image (w x h -> h x w)
+    fn transpose(data: &[u8], w: usize, h: usize, ch: usize) -> Vec<u8> {
+        let mut out = vec![0u8; w * h * ch];
+        out.par_chunks_mut(h * ch).enumerate().for_each(|(x, dst)| {
+            for y in 0..h {
+                let si = (y * w + x) * ch;
+                dst[y * ch..y * ch + ch].copy_from_slice(&data[si..si + ch]);
+            }
+        });
+        out
+    }
+
     /// SIMD-accelerated color quantization
     /// 注意：wide 的 u8x32/f32x8 需要按块安全转换；这里采用按通道标量+并行块的折中方案，避免不正确的向量构造。
     pub fn quantize_colors_simd(pixels: &mut [u8], levels: u8) {
@@ -501,8 +670,27 @@ impl ParallelProcessor {
         let cpu_count = rayon::current_num_threads();
         let chunk_size = std::cmp::max(1, images.len() / (cpu_count * 2));
 
+        Self::process_batch_chunked(images, processor, chunk_size)
+    }
+
+    /// Process multiple images in parallel using an explicit chunk size
+    ///
+    /// `process_batch` picks roughly two chunks per thread, which balances well
+    /// when images are of similar size. When sizes are very uneven (a few large
+    /// images among many small ones), a `chunk_size` of 1 lets idle threads pick
+    /// up work one image at a time instead of waiting behind a chunk holding the
+    /// large ones. Results keep the input order; a `chunk_size` of 0 is treated as 1.
+    pub fn process_batch_chunked<F, T>(
+        images: Vec<DynamicImage>,
+        processor: F,
+        chunk_size: usize,
+    ) -> Vec<Result<T>>
+    where
+        F: Fn(&DynamicImage) -> Result<T> + Send + Sync,
+        T: Send,
+    {
         images
-            .par_chunks(chunk_size)
+            .par_chunks(chunk_size.max(1))
             .flat_map(|chunk| chunk.par_iter().map(&processor).collect::<Vec<_>>())
             .collect()
     }
@@ -523,6 +711,73 @@ impl ParallelProcessor {
             .collect()
     }
 
+    /// Compute a 256-bin luma histogram, splitting rows across threads
+    ///
+    /// Each worker accumulates into its own histogram; the partial histograms
+    /// are merged at the end, so no atomics are needed on the hot path.
+    pub fn histogram(img: &ImageBuffer<image::Luma<u8>, Vec<u8>>) -> [u32; 256] {
+        let row_len = (img.width() as usize).max(1);
+
+        img.as_raw()
+            .par_chunks(row_len)
+            .fold(
+                || [0u32; 256],
+                |mut hist, row| {
+                    for &value in row {
+                        hist[value as usize] += 1;
+                    }
+                    hist
+                },
+            )
+            .reduce(|| [0u32; 256], merge_histograms)
+    }
+
+    /// Compute the 256-bin histogram of 8-point Local Binary Pattern codes
+    ///
+    /// Only interior pixels are coded, as border pixels lack a full
+    /// neighbourhood. Rows are coded in parallel straight into per-thread
+    /// histograms, so no buffer of codes is built.
+    pub fn lbp_histogram(img: &ImageBuffer<image::Luma<u8>, Vec<u8>>) -> [u32; 256] {
+        // (dx, dy)；第 i 个邻点不小于中心时置第 i 位
+        const OFFSETS: [(isize, isize); 8] = [
+            (-1, -1),
+            (-1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+            (1, 0),
+            (1, -1),
+            (0, -1),
+        ];
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        if width < 3 || height < 3 {
+            return [0u32; 256];
+        }
+        let raw = img.as_raw();
+
+        (1..height - 1)
+            .into_par_iter()
+            .fold(
+                || [0u32; 256],
+                |mut hist, y| {
+                    for x in 1..width - 1 {
+                        let center = raw[y * width + x];
+                        let mut code = 0u8;
+                        for (i, (dx, dy)) in OFFSETS.iter().enumerate() {
+                            let nx = x.wrapping_add_signed(*dx);
+                            let ny = y.wrapping_add_signed(*dy);
+                            if raw[ny * width + nx] >= center {
+                                code |= 1 << i;
+                            }
+                        }
+                        hist[code as usize] += 1;
+                    }
+                    hist
+                },
+            )
+            .reduce(|| [0u32; 256], merge_histograms)
+    }
+
     /// Parallel color space conversion
     pub fn parallel_color_conversion<F>(
         images: Vec<DynamicImage>,
@@ -535,6 +790,14 @@ impl ParallelProcessor {
     }
 }
 
+/// Add the bins of two partial histograms
+fn merge_histograms(mut a: [u32; 256], b: [u32; 256]) -> [u32; 256] {
+    for (bin, count) in a.iter_mut().zip(b.iter()) {
+        *bin += count;
+    }
+    a
+}
+
 /// Memory pool for efficient buffer reuse
 pub struct MemoryPool {
     buffers: crossbeam_channel::Receiver<Vec<u8>>,
@@ -568,6 +831,9 @@ impl MemoryPool {
     }
 
     /// Return a buffer to the pool
+    ///
+    /// Buffers whose length differs from the pool's buffer size are dropped;
+    /// use [`MemoryPool::return_or_realloc`] if callers may have resized them.
     pub fn return_buffer(&self, mut buffer: Vec<u8>) {
         if buffer.len() == self.buffer_size {
             buffer.clear();
@@ -576,6 +842,26 @@ impl MemoryPool {
         }
         // If buffer size doesn't match, just drop it
     }
+
+    /// Return a buffer to the pool, restoring it to the pool's buffer size
+    ///
+    /// Buffers with enough capacity are truncated/zero-extended in place;
+    /// smaller ones are replaced with a fresh allocation, so the pool never
+    /// loses a slot because a caller resized a buffer.
+    pub fn return_or_realloc(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() >= self.buffer_size {
+            buffer.clear();
+            buffer.resize(self.buffer_size, 0);
+        } else {
+            buffer = vec![0u8; self.buffer_size];
+        }
+        let _ = self.buffer_sender.try_send(buffer);
+    }
+
+    /// Number of buffers currently idle in the pool
+    pub fn available(&self) -> usize {
+        self.buffers.len()
+    }
 }
 
 /// Zero-copy data transfer utilities
@@ -594,7 +880,7 @@ impl ZeroCopyTransfer {
 
     /// Create a view into image data without copying
     pub fn create_view<T: Pod>(data: &[u8]) -> Result<&[T]> {
-        if data.len() % std::mem::size_of::<T>() != 0 {
+        if !data.len().is_multiple_of(std::mem::size_of::<T>()) {
             return Err(CompressionError::MemoryError(
                 "Data length not aligned for target type".to_string(),
             ));
@@ -605,7 +891,7 @@ impl ZeroCopyTransfer {
 
     /// Create a mutable view into image data without copying
     pub fn create_view_mut<T: Pod>(data: &mut [u8]) -> Result<&mut [T]> {
-        if data.len() % std::mem::size_of::<T>() != 0 {
+        if !data.len().is_multiple_of(std::mem::size_of::<T>()) {
             return Err(CompressionError::MemoryError(
                 "Data length not aligned for target type".to_string(),
             ));
@@ -632,6 +918,87 @@ mod tests {
         assert_ne!(yuv_data, rgb_data);
     }
 
+    #[test]
+    fn test_color_conversion_independent_of_block_size() {
+        // 非整块长度，覆盖末尾不足一块的情况
+        let rgb: Vec<u8> = (0..10_007 * 3).map(|i| (i * 37 % 256) as u8).collect();
+        let yuv = SimdProcessor::rgb_to_yuv_simd(&rgb);
+        let back = SimdProcessor::yuv_to_rgb_simd(&yuv);
+
+        for block_pixels in [1, 7, 1024, 4096, 1 << 20] {
+            assert_eq!(
+                SimdProcessor::rgb_to_yuv_with_block(&rgb, block_pixels),
+                yuv
+            );
+            assert_eq!(
+                SimdProcessor::yuv_to_rgb_with_block(&yuv, block_pixels),
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn test_adaptive_block_pixels_bounds() {
+        assert_eq!(SimdProcessor::adaptive_block_pixels(10), MIN_BLOCK_PIXELS);
+        let pixels = 4096 * 4096;
+        let block = SimdProcessor::adaptive_block_pixels(pixels);
+        let blocks = pixels.div_ceil(block);
+        assert!(blocks <= rayon::current_num_threads() * BLOCKS_PER_THREAD);
+    }
+
+    #[test]
+    fn test_rgba_rgb_round_trip() {
+        let rgba = vec![10, 20, 30, 0, 40, 50, 60, 128, 70, 80, 90, 255];
+        let rgb = SimdProcessor::rgba_to_rgb(&rgba);
+        assert_eq!(rgb, vec![10, 20, 30, 40, 50, 60, 70, 80, 90]);
+
+        let back = SimdProcessor::rgb_to_rgba(&rgb);
+        assert_eq!(
+            back,
+            vec![10, 20, 30, 255, 40, 50, 60, 255, 70, 80, 90, 255]
+        );
+    }
+
+    #[test]
+    fn test_box_blur_vertical_edge() {
+        // 左半黑、右半白（边缘位于 x=8）
+        let (w, h) = (16u32, 4u32);
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let v = if i % w < 8 { 0 } else { 255 };
+                [v, v, v]
+            })
+            .collect();
+
+        let blurred = SimdProcessor::box_blur_rgb(&data, w, h, 2);
+        let row: Vec<u8> = (0..w as usize).map(|x| blurred[x * 3]).collect();
+        assert_eq!(
+            row,
+            vec![0, 0, 0, 0, 0, 0, 51, 102, 153, 204, 255, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_unsharp_mask_steepens_edge_and_keeps_alpha() {
+        // 灰 -> 亮的软边缘，RGBA
+        let (w, h) = (12u32, 2u32);
+        let ramp = [60u8, 60, 60, 60, 90, 130, 170, 200, 200, 200, 200, 200];
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let v = ramp[(i % w) as usize];
+                [v, v, v, 128]
+            })
+            .collect();
+
+        let sharpened = SimdProcessor::unsharp_mask(&data, w, h, 4, 1.0, 1.0);
+        let row: Vec<u8> = (0..w as usize).map(|x| sharpened[x * 4]).collect();
+        // 边缘两侧出现下冲/上冲，平坦区域保持不变
+        assert!(row[3] < 60 && row[7] > 200);
+        assert_eq!(row[0], 60);
+        assert_eq!(row[11], 200);
+        assert!(sharpened.chunks(4).all(|px| px[3] == 128));
+    }
+
     #[test]
     fn test_simd_color_quantization() {
         let mut pixels = vec![0, 64, 128, 192, 255];
@@ -665,6 +1032,23 @@ mod tests {
         assert_eq!(buffer3.len(), 1024);
     }
 
+    #[test]
+    fn test_memory_pool_return_or_realloc() {
+        let pool = MemoryPool::new(1024, 1);
+        let mut buffer = pool.get_buffer();
+        assert_eq!(pool.available(), 0);
+
+        // 调用方缩小了缓冲区：return_buffer 会丢弃，return_or_realloc 会恢复
+        buffer.truncate(10);
+        buffer.shrink_to_fit();
+        pool.return_buffer(buffer.clone());
+        assert_eq!(pool.available(), 0);
+
+        pool.return_or_realloc(buffer);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.get_buffer().len(), 1024);
+    }
+
     #[test]
     fn test_parallel_processing() {
         let images = vec![
@@ -679,6 +1063,85 @@ mod tests {
         assert!(results.iter().all(|r| r.is_ok()));
     }
 
+    #[test]
+    fn test_process_batch_chunked_matches_default() {
+        let images: Vec<DynamicImage> = (1..=9)
+            .map(|i| DynamicImage::new_rgb8(i * 7, i * 3))
+            .collect();
+
+        let expected: Vec<_> =
+            ParallelProcessor::process_batch(images.clone(), |img| Ok(img.dimensions()))
+                .into_iter()
+                .map(|r| r.unwrap())
+                .collect();
+
+        for chunk_size in [0, 1, 4, 100] {
+            let results: Vec<_> = ParallelProcessor::process_batch_chunked(
+                images.clone(),
+                |img| Ok(img.dimensions()),
+                chunk_size,
+            )
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+            assert_eq!(results, expected);
+        }
+    }
+
+    #[test]
+    fn test_parallel_histogram_matches_serial() {
+        let img = ImageBuffer::from_fn(257, 131, |x, y| {
+            image::Luma([((x * 7 + y * 13) % 256) as u8])
+        });
+        let hist = ParallelProcessor::histogram(&img);
+
+        let mut reference = [0u32; 256];
+        for pixel in img.pixels() {
+            reference[pixel[0] as usize] += 1;
+        }
+
+        assert_eq!(hist.iter().sum::<u32>(), 257 * 131);
+        assert_eq!(hist, reference);
+    }
+
+    #[test]
+    fn test_lbp_histogram_matches_serial() {
+        let img = ImageBuffer::from_fn(67, 41, |x, y| {
+            image::Luma([((x * 31 + y * 17 + x * y) % 256) as u8])
+        });
+        let hist = ParallelProcessor::lbp_histogram(&img);
+
+        let mut reference = [0u32; 256];
+        for y in 1..40 {
+            for x in 1..66 {
+                let center = img.get_pixel(x, y)[0];
+                let neighbors = [
+                    (x - 1, y - 1),
+                    (x - 1, y),
+                    (x - 1, y + 1),
+                    (x, y + 1),
+                    (x + 1, y + 1),
+                    (x + 1, y),
+                    (x + 1, y - 1),
+                    (x, y - 1),
+                ];
+                let code = neighbors
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &(nx, ny))| img.get_pixel(nx, ny)[0] >= center)
+                    .fold(0u8, |code, (i, _)| code | 1 << i);
+                reference[code as usize] += 1;
+            }
+        }
+
+        assert_eq!(hist.iter().sum::<u32>(), 65 * 39);
+        assert_eq!(hist, reference);
+        assert_eq!(
+            ParallelProcessor::lbp_histogram(&ImageBuffer::new(2, 9)),
+            [0u32; 256]
+        );
+    }
+
     #[test]
     fn test_zero_copy_transfer() {
         let img = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(10, 10);
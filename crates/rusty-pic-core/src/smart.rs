@@ -1,18 +1,36 @@
 //! Smart compression algorithms with advanced image analysis and iterative optimization
 
 use crate::{
+    logger::{log_event, Logger},
+    performance::ParallelProcessor,
     CompressionEngine, CompressionError, CompressionOptions, CompressionResult, ImageAnalyzer,
     Result,
 };
 use image::{DynamicImage, GenericImageView};
+use rayon::prelude::*;
 use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Chroma edge density below which 4:2:0 subsampling loses no visible detail
+const LOW_CHROMA_COMPLEXITY: f32 = 0.02;
+
+/// Lossless PNG bits per pixel below which line art and graphics stay lossless
+pub const DEFAULT_LOSSLESS_BPP_THRESHOLD: f32 = 2.0;
+
+/// Called after each trial encode of a target-size run with the 1-based
+/// iteration, the quality tried and the encoded size in bytes
+pub type ProgressCallback = Box<dyn Fn(u8, u8, usize)>;
 
 /// Smart compression engine with advanced analysis and optimization
 pub struct SmartCompressionEngine {
     analyzer: ImageAnalyzer,
     compression_engine: CompressionEngine,
-    #[cfg(feature = "logging")]
-    logger_enabled: bool,
+    logger: Logger,
+    /// Quality/size observations shared by iterative runs on this engine
+    size_model: Mutex<SizeModel>,
+    /// Line art and graphics whose lossless PNG stays below this many bits
+    /// per pixel are kept on PNG instead of a lossy format
+    lossless_bpp_threshold: f32,
 }
 
 impl SmartCompressionEngine {
@@ -20,24 +38,68 @@ impl SmartCompressionEngine {
         Self {
             analyzer: ImageAnalyzer::new(),
             compression_engine: CompressionEngine::new(),
-            #[cfg(feature = "logging")]
-            logger_enabled: true,
+            logger: Logger::new(),
+            size_model: Mutex::new(SizeModel::default()),
+            lossless_bpp_threshold: DEFAULT_LOSSLESS_BPP_THRESHOLD,
+        }
+    }
+
+    /// Create an engine that keeps line art and graphics on PNG while their
+    /// lossless encoding stays below `bpp` bits per pixel (0 disables the rule)
+    pub fn with_lossless_bpp_threshold(bpp: f32) -> Self {
+        Self {
+            lossless_bpp_threshold: bpp,
+            ..Self::new()
         }
     }
 
+    /// Snapshot of the quality/size model learned by target-size runs
+    pub fn size_model(&self) -> SizeModel {
+        self.size_model
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Send diagnostic messages to `callback`, independent of the `logging` feature.
+    ///
+    /// Covers messages from the smart engine and the engines it drives.
+    pub fn set_log_callback(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        let callback: crate::LogCallback = std::sync::Arc::new(callback);
+        self.analyzer.set_log_callback(callback.clone());
+        self.compression_engine
+            .set_shared_log_callback(callback.clone());
+        self.logger.set_callback(callback);
+    }
+
     /// Perform smart compression with target size constraints
     pub fn smart_compress(
         &self,
         data: &[u8],
         constraints: &SmartCompressionConstraints,
     ) -> Result<CompressionResult> {
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::info!("Starting smart compression with constraints: {constraints:?}");
-        }
+        self.smart_compress_with_progress(data, constraints, None)
+    }
+
+    /// [`smart_compress`](Self::smart_compress) that reports each trial encode
+    /// of a `target_size` run to `progress`.
+    ///
+    /// The callback only sees `&self` state, so the engine can still be shared
+    /// across threads while it runs.
+    pub fn smart_compress_with_progress(
+        &self,
+        data: &[u8],
+        constraints: &SmartCompressionConstraints,
+        progress: Option<ProgressCallback>,
+    ) -> Result<CompressionResult> {
+        log_event!(
+            self.logger,
+            Info,
+            "Starting smart compression with constraints: {constraints:?}"
+        );
 
         // Load and analyze the image
-        let img = image::load_from_memory(data)?;
+        let img = crate::analyzer::decode_image(data)?;
         let analysis = self.analyzer.analyze(data)?;
 
         // Perform advanced complexity analysis
@@ -49,7 +111,13 @@ impl SmartCompressionEngine {
 
         // If target size is specified, use iterative compression
         if let Some(target_size) = &constraints.target_size {
-            self.iterative_compress_to_size(data, &optimal_format, target_size, constraints)
+            self.iterative_compress_to_size(
+                data,
+                &optimal_format,
+                target_size,
+                constraints,
+                progress.as_deref(),
+            )
         } else {
             // Use standard compression with optimal settings
             let options =
@@ -60,7 +128,12 @@ impl SmartCompressionEngine {
 
     /// Analyze image complexity using advanced algorithms
     fn analyze_image_complexity(&self, img: &DynamicImage) -> Result<AdvancedImageAnalysis> {
-        let (_width, _height) = img.dimensions();
+        let (width, height) = img.dimensions();
+        if width == 0 || height == 0 {
+            return Err(CompressionError::AnalysisError(format!(
+                "Cannot analyze a zero-area image ({width}x{height})"
+            )));
+        }
 
         // Convert to grayscale for analysis
         let gray_img = img.to_luma8();
@@ -68,6 +141,13 @@ impl SmartCompressionEngine {
         // Edge detection using Sobel operator
         let edge_density = self.calculate_edge_density(&gray_img)?;
 
+        // 亮度与色度分别做边缘检测，判断细节位于哪个通道
+        let (luma_plane, cb_plane, cr_plane) = ycbcr_planes(img);
+        let luma_complexity = self.calculate_edge_density(&luma_plane)?;
+        let chroma_complexity = self
+            .calculate_edge_density(&cb_plane)?
+            .max(self.calculate_edge_density(&cr_plane)?);
+
         // Texture analysis using Local Binary Patterns
         let texture_complexity = self.calculate_texture_complexity(&gray_img)?;
 
@@ -87,6 +167,8 @@ impl SmartCompressionEngine {
 
         Ok(AdvancedImageAnalysis {
             edge_density,
+            luma_complexity,
+            chroma_complexity,
             texture_complexity,
             color_analysis,
             frequency_analysis,
@@ -155,44 +237,12 @@ impl SmartCompressionEngine {
             return Ok(0.0);
         }
 
-        let mut lbp_histogram = HashMap::new();
-        let mut total_patterns = 0u32;
-
-        // Simplified 8-point LBP
-        let offsets = [
-            (-1, -1),
-            (-1, 0),
-            (-1, 1),
-            (0, 1),
-            (1, 1),
-            (1, 0),
-            (1, -1),
-            (0, -1),
-        ];
-
-        for y in 1..height - 1 {
-            for x in 1..width - 1 {
-                let center_val = gray_img.get_pixel(x, y)[0];
-                let mut lbp_code = 0u8;
-
-                for (i, (dx, dy)) in offsets.iter().enumerate() {
-                    let neighbor_x = (x as i32 + dx) as u32;
-                    let neighbor_y = (y as i32 + dy) as u32;
-                    let neighbor_val = gray_img.get_pixel(neighbor_x, neighbor_y)[0];
-
-                    if neighbor_val >= center_val {
-                        lbp_code |= 1 << i;
-                    }
-                }
-
-                *lbp_histogram.entry(lbp_code).or_insert(0) += 1;
-                total_patterns += 1;
-            }
-        }
+        let lbp_histogram = ParallelProcessor::lbp_histogram(gray_img);
+        let total_patterns = (width - 2) * (height - 2);
 
         // Calculate texture complexity as histogram entropy
         let mut entropy = 0.0f32;
-        for &count in lbp_histogram.values() {
+        for &count in lbp_histogram.iter() {
             if count > 0 {
                 let probability = count as f32 / total_patterns as f32;
                 entropy -= probability * probability.log2();
@@ -207,22 +257,23 @@ impl SmartCompressionEngine {
     fn analyze_color_distribution(&self, img: &DynamicImage) -> Result<ColorAnalysis> {
         let rgba_img = img.to_rgba8();
         let (width, height) = rgba_img.dimensions();
-        let total_pixels = (width * height) as f32;
+        if width == 0 || height == 0 {
+            return Ok(ColorAnalysis {
+                unique_colors: 0,
+                color_diversity: 0.0,
+                color_variance: 0.0,
+                dominant_colors: Vec::new(),
+            });
+        }
+        let total_pixels = width as f32 * height as f32;
 
         let mut color_histogram = HashMap::new();
-        let mut r_sum = 0u64;
-        let mut g_sum = 0u64;
-        let mut b_sum = 0u64;
         let mut unique_colors = 0u32;
 
         // Collect color statistics
         for pixel in rgba_img.pixels() {
             let color = (pixel[0], pixel[1], pixel[2]);
 
-            r_sum += pixel[0] as u64;
-            g_sum += pixel[1] as u64;
-            b_sum += pixel[2] as u64;
-
             if !color_histogram.contains_key(&color) {
                 unique_colors += 1;
             }
@@ -230,9 +281,8 @@ impl SmartCompressionEngine {
         }
 
         // Calculate color variance
-        let r_mean = r_sum as f32 / total_pixels;
-        let g_mean = g_sum as f32 / total_pixels;
-        let b_mean = b_sum as f32 / total_pixels;
+        let stats = crate::analyzer::channel_stats_of(img);
+        let (r_mean, g_mean, b_mean) = (stats.red.mean, stats.green.mean, stats.blue.mean);
 
         let mut r_variance = 0.0f32;
         let mut g_variance = 0.0f32;
@@ -267,7 +317,8 @@ impl SmartCompressionEngine {
         color_histogram: &HashMap<(u8, u8, u8), u32>,
     ) -> Vec<(u8, u8, u8)> {
         let mut colors: Vec<_> = color_histogram.iter().collect();
-        colors.sort_by(|a, b| b.1.cmp(a.1));
+        // 次级按颜色排序：HashMap 遍历顺序每次不同，计数相同时需确定的先后
+        colors.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
 
         colors
             .into_iter()
@@ -283,6 +334,14 @@ impl SmartCompressionEngine {
     ) -> Result<FrequencyAnalysis> {
         let (width, height) = gray_img.dimensions();
 
+        if width < 3 || height < 3 {
+            return Ok(FrequencyAnalysis {
+                high_frequency_ratio: 0.0,
+                low_frequency_ratio: 1.0,
+                total_energy: 0.0,
+            });
+        }
+
         // Simplified frequency analysis using gradient magnitudes
         let mut _low_freq_energy = 0.0f32;
         let mut high_freq_energy = 0.0f32;
@@ -351,7 +410,10 @@ impl SmartCompressionEngine {
     /// Calculate perceptual quality score
     fn calculate_perceptual_quality_score(&self, img: &DynamicImage) -> Result<f32> {
         let (width, height) = img.dimensions();
-        let pixel_count = width * height;
+        if width == 0 || height == 0 {
+            return Ok(0.0);
+        }
+        let pixel_count = width as u64 * height as u64;
 
         // Base quality score based on resolution
         let resolution_score = if pixel_count > 2_000_000 {
@@ -396,6 +458,27 @@ impl SmartCompressionEngine {
             }
         }
 
+        // 线稿/图形类且无损 PNG 已足够小时，不因像素数等偏好切换到有损格式
+        let class = advanced_analysis.image_class();
+        if class != ImageClass::Photo && self.lossless_bpp_threshold > 0.0 {
+            let png = match crate::formats::png::encode_reduced_bit_depth(img)? {
+                Some(png) => png,
+                None => crate::formats::png::encode_optimized(
+                    img,
+                    &crate::formats::png::PngOptions::default(),
+                )?,
+            };
+            let bpp = png.len() as f32 * 8.0 / pixel_count.max(1) as f32;
+            if bpp < self.lossless_bpp_threshold {
+                log_event!(
+                    self.logger,
+                    Info,
+                    "Selected format 'png' for {class:?} content: lossless PNG is {bpp:.2} bpp"
+                );
+                return Ok("png".to_string());
+            }
+        }
+
         // Advanced format selection logic
         let format = if has_alpha {
             if advanced_analysis.overall_complexity > 0.7 && pixel_count > 1_000_000 {
@@ -424,17 +507,16 @@ impl SmartCompressionEngine {
             }
         };
 
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::info!(
-                "Selected format '{}' based on: complexity={:.3}, edges={:.3}, texture={:.3}, colors={}",
-                format,
-                advanced_analysis.overall_complexity,
-                advanced_analysis.edge_density,
-                advanced_analysis.texture_complexity,
-                advanced_analysis.color_analysis.unique_colors
-            );
-        }
+        log_event!(
+            self.logger,
+            Info,
+            "Selected format '{}' based on: complexity={:.3}, edges={:.3}, texture={:.3}, colors={}",
+            format,
+            advanced_analysis.overall_complexity,
+            advanced_analysis.edge_density,
+            advanced_analysis.texture_complexity,
+            advanced_analysis.color_analysis.unique_colors
+        );
 
         Ok(format)
     }
@@ -477,6 +559,14 @@ impl SmartCompressionEngine {
     ) -> Result<CompressionOptions> {
         let quality = self.calculate_optimal_quality(format, analysis, constraints)?;
 
+        // 色度细节很少时，即使高质量也可以安全地使用 4:2:0 采样
+        let jpeg = (analysis.chroma_complexity < LOW_CHROMA_COMPLEXITY).then(|| {
+            crate::formats::jpeg::JpegOptions {
+                subsample_chroma: Some(true),
+                ..Default::default()
+            }
+        });
+
         Ok(CompressionOptions {
             format: Some(format.to_string()),
             quality: Some(quality),
@@ -485,7 +575,10 @@ impl SmartCompressionEngine {
                 colors: analysis.color_analysis.unique_colors < 65536,
                 progressive: analysis.overall_complexity > 0.5,
                 lossless: constraints.min_quality.unwrap_or(0) >= 95,
+                jpeg,
+                ..Default::default()
             }),
+            ..Default::default()
         })
     }
 
@@ -542,6 +635,44 @@ impl SmartCompressionEngine {
         Ok(final_quality.clamp(1, 100))
     }
 
+    /// Compress to a bits-per-pixel budget measured on the output dimensions.
+    ///
+    /// The budget becomes `target_bpp * width * height / 8` bytes, with width
+    /// and height taken after `constraints.resize`, and is then fitted like a
+    /// target size, so sources of any resolution get the same treatment.
+    pub fn compress_to_bpp(
+        &self,
+        data: &[u8],
+        target_bpp: f32,
+        constraints: &SmartCompressionConstraints,
+    ) -> Result<CompressionResult> {
+        if !(target_bpp.is_finite() && target_bpp > 0.0) {
+            return Err(CompressionError::InvalidFormat(format!(
+                "Target bits per pixel must be positive, got {target_bpp}"
+            )));
+        }
+
+        let img = crate::analyzer::decode_image(data)?;
+        let analysis = self.analyzer.analyze(data)?;
+        let advanced_analysis = self.analyze_image_complexity(&img)?;
+        let format =
+            self.select_optimal_format(&img, &analysis, &advanced_analysis, constraints)?;
+
+        let (width, height) = self.compression_engine.output_dimensions(
+            img.dimensions(),
+            &constraints.resize,
+            true,
+        )?;
+        let target_bytes = (target_bpp as f64 * width as f64 * height as f64 / 8.0) as usize;
+        log_event!(
+            self.logger,
+            Info,
+            "{target_bpp} bpp at {width}x{height} gives a {target_bytes} byte budget"
+        );
+
+        self.compress_to_bytes(data, &format, target_bytes.max(1), constraints, None)
+    }
+
     /// Iteratively compress to target file size
     fn iterative_compress_to_size(
         &self,
@@ -549,20 +680,55 @@ impl SmartCompressionEngine {
         format: &str,
         target_size: &str,
         constraints: &SmartCompressionConstraints,
+        progress: Option<&dyn Fn(u8, u8, usize)>,
     ) -> Result<CompressionResult> {
         let target_bytes = self.parse_target_size(target_size)?;
+        self.compress_to_bytes(data, format, target_bytes, constraints, progress)
+    }
 
-        #[cfg(feature = "logging")]
-        if self.logger_enabled {
-            log::info!("Iterative compression target: {target_bytes} bytes");
-        }
+    /// Lower quality step by step until the output fits in `target_bytes`
+    fn compress_to_bytes(
+        &self,
+        data: &[u8],
+        format: &str,
+        target_bytes: usize,
+        constraints: &SmartCompressionConstraints,
+        progress: Option<&dyn Fn(u8, u8, usize)>,
+    ) -> Result<CompressionResult> {
+        log_event!(
+            self.logger,
+            Info,
+            "Iterative compression target: {target_bytes} bytes"
+        );
 
-        let img = image::load_from_memory(data)?;
+        let img = crate::analyzer::decode_image(data)?;
         let advanced_analysis = self.analyze_image_complexity(&img)?;
 
-        // Start with high quality and iterate down
-        let mut current_quality = constraints.min_quality.unwrap_or(95).min(95);
+        // 先前运行学到的质量-码率关系给出起点，否则从高质量开始逐步降低
         let min_quality = constraints.min_quality.unwrap_or(30);
+        // 模型按输出尺寸记录码率，目标码率同样按缩放后的尺寸换算
+        let (width, height) = self.compression_engine.output_dimensions(
+            img.dimensions(),
+            &constraints.resize,
+            true,
+        )?;
+        let target_bpp = target_bytes as f32 * 8.0 / width.max(1) as f32 / height.max(1) as f32;
+        let predicted = self
+            .size_model
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .predict_quality(format, target_bpp);
+        let mut current_quality = match predicted {
+            Some(quality) => {
+                log_event!(
+                    self.logger,
+                    Debug,
+                    "Size model predicts quality {quality} for {target_bpp:.3} bpp"
+                );
+                quality.clamp(min_quality.min(95), 95)
+            }
+            None => constraints.min_quality.unwrap_or(95).min(95),
+        };
         let mut best_result: Option<CompressionResult> = None;
         let mut iterations = 0;
         const MAX_ITERATIONS: u8 = 10;
@@ -576,20 +742,35 @@ impl SmartCompressionEngine {
                     colors: advanced_analysis.color_analysis.unique_colors < 65536,
                     progressive: advanced_analysis.overall_complexity > 0.5,
                     lossless: current_quality >= 95,
+                    ..Default::default()
                 }),
+                ..Default::default()
             };
 
             match self.compression_engine.compress(data, &options) {
                 Ok(result) => {
-                    #[cfg(feature = "logging")]
-                    if self.logger_enabled {
-                        log::debug!(
-                            "Iteration {}: quality={}, size={} bytes (target: {})",
-                            iterations + 1,
-                            current_quality,
-                            result.compressed_size,
-                            target_bytes
-                        );
+                    log_event!(
+                        self.logger,
+                        Debug,
+                        "Iteration {}: quality={}, size={} bytes (target: {})",
+                        iterations + 1,
+                        current_quality,
+                        result.compressed_size,
+                        target_bytes
+                    );
+
+                    let pixels = result.output_width as f32 * result.output_height as f32;
+                    let mut model = self.size_model.lock().unwrap_or_else(|e| e.into_inner());
+                    model.record(
+                        format,
+                        current_quality,
+                        result.compressed_size as f32 * 8.0 / pixels.max(1.0),
+                    );
+                    model.last_iterations = iterations as u32 + 1;
+                    drop(model);
+
+                    if let Some(progress) = progress {
+                        progress(iterations + 1, current_quality, result.compressed_size);
                     }
 
                     if result.compressed_size <= target_bytes {
@@ -600,12 +781,11 @@ impl SmartCompressionEngine {
                     best_result = Some(result);
                 }
                 Err(e) => {
-                    #[cfg(feature = "logging")]
-                    if self.logger_enabled {
-                        log::warn!("Compression failed at quality {current_quality}: {e}");
-                    }
-                    #[cfg(not(feature = "logging"))]
-                    let _ = e; // Suppress unused variable warning when logging is disabled
+                    log_event!(
+                        self.logger,
+                        Warn,
+                        "Compression failed at quality {current_quality}: {e}"
+                    );
                 }
             }
 
@@ -622,6 +802,193 @@ impl SmartCompressionEngine {
         })
     }
 
+    /// Fit under `target_bytes` by searching scale factors and qualities together.
+    ///
+    /// A fixed grid of 5 scales x 6 qualities (qualities below
+    /// `constraints.min_quality` skipped) is encoded in parallel; each output
+    /// is scaled back to the source size and scored with SSIM, and the
+    /// highest-scoring pair within the target wins. `max_width`/`max_height`
+    /// cap the largest scale. Errors if no pair fits.
+    pub fn fit_to_size_joint(
+        &self,
+        data: &[u8],
+        target_bytes: usize,
+        constraints: &SmartCompressionConstraints,
+    ) -> Result<JointSizeFit> {
+        const SCALES: [f32; 5] = [1.0, 0.85, 0.7, 0.55, 0.4];
+        const QUALITIES: [u8; 6] = [90, 80, 70, 60, 50, 40];
+
+        let img = crate::analyzer::decode_image(data)?;
+        let analysis = self.analyzer.analyze(data)?;
+        let advanced_analysis = self.analyze_image_complexity(&img)?;
+        let format =
+            self.select_optimal_format(&img, &analysis, &advanced_analysis, constraints)?;
+
+        let (width, height) = img.dimensions();
+        let max_scale = [
+            constraints.max_width.map(|w| w as f32 / width as f32),
+            constraints.max_height.map(|h| h as f32 / height as f32),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(1.0f32, f32::min);
+
+        let min_quality = constraints.min_quality.unwrap_or(0);
+        let mut qualities: Vec<u8> = QUALITIES
+            .into_iter()
+            .filter(|&q| q >= min_quality)
+            .collect();
+        if qualities.is_empty() {
+            qualities.push(min_quality.min(100));
+        }
+
+        // 每个缩放比例只缩放一次，再并行尝试各质量档位
+        let candidates: Vec<Result<JointSizeFit>> = SCALES
+            .par_iter()
+            .flat_map(|&scale| {
+                let scale = scale * max_scale;
+                let scaled_width = ((width as f32 * scale).round() as u32).max(1);
+                let scaled_height = ((height as f32 * scale).round() as u32).max(1);
+                let scaled = img.resize_exact(
+                    scaled_width,
+                    scaled_height,
+                    image::imageops::FilterType::Lanczos3,
+                );
+                let (pixels, channels) = if scaled.color().has_alpha() {
+                    (scaled.to_rgba8().into_raw(), 4)
+                } else {
+                    (scaled.to_rgb8().into_raw(), 3)
+                };
+                let format = &format;
+                let img = &img;
+                qualities
+                    .par_iter()
+                    .map(move |&quality| {
+                        let options = CompressionOptions {
+                            format: Some(format.clone()),
+                            quality: Some(quality),
+                            ..Default::default()
+                        };
+                        let result = self.compression_engine.compress_raw(
+                            &pixels,
+                            scaled_width,
+                            scaled_height,
+                            channels,
+                            &options,
+                        )?;
+
+                        let decoded = image::load_from_memory(&result.data)?;
+                        let restored = if decoded.dimensions() == (width, height) {
+                            decoded
+                        } else {
+                            decoded.resize_exact(
+                                width,
+                                height,
+                                image::imageops::FilterType::Triangle,
+                            )
+                        };
+                        let ssim = crate::metrics::ssim(img, &restored)?;
+
+                        log_event!(
+                            self.logger,
+                            Debug,
+                            "Joint search: scale={scale:.2}, quality={quality}, size={} bytes, ssim={ssim:.4}",
+                            result.compressed_size
+                        );
+                        Ok(JointSizeFit {
+                            result,
+                            scale,
+                            quality,
+                            ssim,
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let best = candidates
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .filter(|fit| fit.result.compressed_size <= target_bytes)
+            .max_by(|a, b| a.ssim.total_cmp(&b.ssim))
+            .ok_or_else(|| {
+                CompressionError::EncodingError(format!(
+                    "No scale/quality combination fits in {target_bytes} bytes"
+                ))
+            })?;
+
+        log_event!(
+            self.logger,
+            Info,
+            "Joint fit chose scale={:.2}, quality={} ({} bytes, ssim={:.4})",
+            best.scale,
+            best.quality,
+            best.result.compressed_size,
+            best.ssim
+        );
+        Ok(best)
+    }
+
+    /// Find the smallest encode whose SSIM against the source stays at or above `min_ssim`.
+    ///
+    /// Quality is binary-searched over 1-100; every candidate is decoded and
+    /// measured with [`crate::metrics::ssim`]. Errors if even quality 100 does
+    /// not reach the threshold.
+    pub fn compress_to_ssim(
+        &self,
+        data: &[u8],
+        format: &str,
+        min_ssim: f32,
+    ) -> Result<CompressionResult> {
+        let original = crate::analyzer::decode_image(data)?;
+
+        let mut low = 1u8;
+        let mut high = 100u8;
+        let mut best: Option<CompressionResult> = None;
+
+        while low <= high {
+            let quality = low + (high - low) / 2;
+            let options = CompressionOptions {
+                format: Some(format.to_string()),
+                quality: Some(quality),
+                ..Default::default()
+            };
+
+            let result = self.compression_engine.compress(data, &options)?;
+            let decoded = image::load_from_memory(&result.data)?;
+            let score = crate::metrics::ssim(&original, &decoded)?;
+
+            log_event!(
+                self.logger,
+                Debug,
+                "SSIM search: quality={quality}, size={} bytes, ssim={score:.4}",
+                result.compressed_size
+            );
+
+            if score >= min_ssim {
+                if best
+                    .as_ref()
+                    .is_none_or(|b| result.compressed_size <= b.compressed_size)
+                {
+                    best = Some(result);
+                }
+                if quality == 1 {
+                    break;
+                }
+                high = quality - 1;
+            } else {
+                low = quality + 1;
+            }
+        }
+
+        best.ok_or_else(|| {
+            CompressionError::EncodingError(format!(
+                "Could not reach SSIM {min_ssim} in format '{format}'"
+            ))
+        })
+    }
+
     /// Parse target size string (e.g., "100kb", "1mb")
     pub fn parse_target_size(&self, target_size: &str) -> Result<usize> {
         let target_lower = target_size.to_lowercase();
@@ -653,6 +1020,23 @@ impl Default for SmartCompressionEngine {
     }
 }
 
+/// Split an image into BT.601 Y, Cb and Cr planes (chroma centred on 128)
+fn ycbcr_planes(img: &DynamicImage) -> (image::GrayImage, image::GrayImage, image::GrayImage) {
+    let rgb = img.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let mut y_plane = Vec::with_capacity(rgb.as_raw().len() / 3);
+    let mut cb_plane = Vec::with_capacity(y_plane.capacity());
+    let mut cr_plane = Vec::with_capacity(y_plane.capacity());
+    for px in rgb.pixels() {
+        let [r, g, b] = px.0.map(|c| c as f32);
+        y_plane.push((0.299 * r + 0.587 * g + 0.114 * b).round() as u8);
+        cb_plane.push((128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b).round() as u8);
+        cr_plane.push((128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b).round() as u8);
+    }
+    let plane = |data| image::GrayImage::from_raw(width, height, data).expect("plane size");
+    (plane(y_plane), plane(cb_plane), plane(cr_plane))
+}
+
 /// Constraints for smart compression
 #[derive(Debug, Clone)]
 pub struct SmartCompressionConstraints {
@@ -664,10 +1048,86 @@ pub struct SmartCompressionConstraints {
     pub resize: Option<crate::compression::ResizeOptions>,
 }
 
+/// Quality-to-size relationship learned from target-size runs
+///
+/// Every encode made while searching for a target size records its quality
+/// and bits per pixel. Later runs on similar images start from the quality
+/// the model predicts for their target instead of from the top, which cuts
+/// the number of encodes for catalog-style batches.
+#[derive(Debug, Clone, Default)]
+pub struct SizeModel {
+    /// `(quality, bits per pixel)` per format, sorted by quality
+    samples: HashMap<String, Vec<(u8, f32)>>,
+    last_iterations: u32,
+}
+
+impl SizeModel {
+    /// Most samples kept per format; the oldest are dropped first
+    const MAX_SAMPLES: usize = 64;
+
+    /// Record one encode of `format` at `quality`
+    pub fn record(&mut self, format: &str, quality: u8, bits_per_pixel: f32) {
+        let samples = self.samples.entry(format.to_string()).or_default();
+        if samples.len() >= Self::MAX_SAMPLES {
+            samples.remove(0);
+        }
+        samples.push((quality, bits_per_pixel));
+    }
+
+    /// Highest quality expected to stay within `bits_per_pixel`.
+    ///
+    /// Interpolates between the observed qualities around the target;
+    /// `None` until the format has been observed.
+    pub fn predict_quality(&self, format: &str, bits_per_pixel: f32) -> Option<u8> {
+        let mut samples = self.samples.get(format)?.clone();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by_key(|&(quality, _)| quality);
+
+        // 最高的不超标样本与其上方最近的超标样本之间线性插值
+        let below = samples
+            .iter()
+            .rev()
+            .find(|&&(_, bpp)| bpp <= bits_per_pixel);
+        let above = samples
+            .iter()
+            .find(|&&(q, bpp)| bpp > bits_per_pixel && below.is_none_or(|&(low, _)| q > low));
+        match (below, above) {
+            (Some(&(q0, b0)), Some(&(q1, b1))) if b1 > b0 => {
+                let t = (bits_per_pixel - b0) / (b1 - b0);
+                Some(q0 + ((q1 - q0) as f32 * t) as u8)
+            }
+            (Some(&(q0, _)), _) => Some(q0),
+            // 所有样本都超标：从观测到的最低质量继续向下搜索
+            (None, Some(&(q1, _))) => Some(q1),
+            (None, None) => None,
+        }
+    }
+
+    /// Encodes the most recent target-size run needed
+    pub fn last_iterations(&self) -> u32 {
+        self.last_iterations
+    }
+}
+
+/// Outcome of [`SmartCompressionEngine::fit_to_size_joint`]
+#[derive(Debug, Clone)]
+pub struct JointSizeFit {
+    pub result: CompressionResult,
+    /// Scale factor applied to both dimensions (1.0 = source size)
+    pub scale: f32,
+    pub quality: u8,
+    /// SSIM of the output, scaled back to the source size, against the source
+    pub ssim: f32,
+}
+
 /// Advanced image analysis results
 #[derive(Debug, Clone)]
 pub struct AdvancedImageAnalysis {
     pub edge_density: f32,       // 0-1, density of edges
+    pub luma_complexity: f32,    // 0-1, edge density of the Y (BT.601) plane
+    pub chroma_complexity: f32,  // 0-1, edge density of the busier of Cb/Cr
     pub texture_complexity: f32, // 0-1, texture complexity using LBP
     pub color_analysis: ColorAnalysis,
     pub frequency_analysis: FrequencyAnalysis,
@@ -675,6 +1135,30 @@ pub struct AdvancedImageAnalysis {
     pub perceptual_quality_score: f32, // 0-1, perceptual quality requirements
 }
 
+/// Rough content class of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageClass {
+    /// Continuous-tone content with many colours
+    Photo,
+    /// Few flat colours, e.g. logos, charts and UI screenshots
+    Graphic,
+    /// Few colours without chroma detail, e.g. drawings and scanned text
+    LineArt,
+}
+
+impl AdvancedImageAnalysis {
+    /// Classify the image from its colour count and chroma detail
+    pub fn image_class(&self) -> ImageClass {
+        if self.color_analysis.unique_colors > 256 {
+            ImageClass::Photo
+        } else if self.chroma_complexity < LOW_CHROMA_COMPLEXITY {
+            ImageClass::LineArt
+        } else {
+            ImageClass::Graphic
+        }
+    }
+}
+
 /// Color distribution analysis
 #[derive(Debug, Clone)]
 pub struct ColorAnalysis {
@@ -692,6 +1176,110 @@ pub struct FrequencyAnalysis {
     pub total_energy: f32,         // Total frequency energy
 }
 
+/// Median-cut colour quantization shared by the palette encoders
+pub mod quantize {
+    /// Build a palette of at most `max_colors` entries by median cut.
+    ///
+    /// The colour box with the widest channel range is split at its
+    /// pixel-weighted median until there are `max_colors` boxes or no box
+    /// holds more than one colour; each box contributes its weighted mean.
+    /// When the input has no more unique colours than `max_colors`, the
+    /// palette is exactly those colours.
+    pub fn median_cut(pixels: &[[u8; 3]], max_colors: usize) -> Vec<[u8; 3]> {
+        if pixels.is_empty() || max_colors == 0 {
+            return Vec::new();
+        }
+        let mut histogram: std::collections::HashMap<[u8; 3], u32> =
+            std::collections::HashMap::new();
+        for &pixel in pixels {
+            *histogram.entry(pixel).or_insert(0) += 1;
+        }
+        // HashMap 遍历顺序不固定，排序后保证结果可复现
+        let mut colors: Vec<([u8; 3], u32)> = histogram.into_iter().collect();
+        colors.sort_unstable();
+        median_cut_weighted(colors, max_colors)
+    }
+
+    /// Index of the palette entry nearest to `pixel` (squared RGB distance).
+    ///
+    /// Ties go to the lower index; an empty palette yields 0.
+    pub fn map_to_palette(pixel: [u8; 3], palette: &[[u8; 3]]) -> usize {
+        nearest_index(pixel, palette)
+    }
+
+    /// [`median_cut`] over a pre-built, sorted `(colour, pixel count)` histogram
+    pub(crate) fn median_cut_weighted<const N: usize>(
+        colors: Vec<([u8; N], u32)>,
+        max_colors: usize,
+    ) -> Vec<[u8; N]> {
+        let channel_range = |colors: &[([u8; N], u32)], c: usize| {
+            let (lo, hi) = colors.iter().fold((255u8, 0u8), |(lo, hi), (px, _)| {
+                (lo.min(px[c]), hi.max(px[c]))
+            });
+            hi.saturating_sub(lo)
+        };
+
+        let mut boxes = vec![colors];
+        while boxes.len() < max_colors {
+            // 选择跨度最大的可分割盒子，沿该通道在加权中位数处切开
+            let Some((index, channel, _)) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.len() > 1)
+                .flat_map(|(i, b)| (0..N).map(move |c| (i, c, channel_range(b, c))))
+                .max_by_key(|&(_, _, range)| range)
+            else {
+                break;
+            };
+
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_unstable_by_key(|&(px, _)| (px[channel], px));
+            let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+            let mut seen = 0u64;
+            let mut split = colors.len() - 1;
+            for (i, &(_, n)) in colors.iter().enumerate() {
+                seen += n as u64;
+                if seen * 2 >= total {
+                    split = i + 1;
+                    break;
+                }
+            }
+            let split = split.clamp(1, colors.len() - 1);
+            let upper = colors.split_off(split);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        boxes
+            .iter()
+            .map(|colors| {
+                let total: u64 = colors.iter().map(|&(_, n)| n as u64).sum();
+                let mut sum = [0u64; N];
+                for &(px, n) in colors {
+                    for c in 0..N {
+                        sum[c] += px[c] as u64 * n as u64;
+                    }
+                }
+                sum.map(|s| ((s + total / 2) / total.max(1)) as u8)
+            })
+            .collect()
+    }
+
+    /// [`map_to_palette`] for any channel count
+    pub(crate) fn nearest_index<const N: usize>(pixel: [u8; N], palette: &[[u8; N]]) -> usize {
+        palette
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| {
+                c.iter()
+                    .zip(pixel.iter())
+                    .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+                    .sum::<i32>()
+            })
+            .map_or(0, |(i, _)| i)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -699,7 +1287,7 @@ mod tests {
     #[test]
     fn test_smart_compression_engine_new() {
         let engine = SmartCompressionEngine::new();
-        assert!(std::ptr::addr_of!(engine) as *const _ != std::ptr::null());
+        assert!(!std::ptr::addr_of!(engine).is_null());
     }
 
     #[test]
@@ -744,6 +1332,8 @@ mod tests {
 
         let analysis = AdvancedImageAnalysis {
             edge_density: 0.4,
+            luma_complexity: 0.4,
+            chroma_complexity: 0.1,
             texture_complexity: 0.6,
             color_analysis,
             frequency_analysis,
@@ -756,4 +1346,216 @@ mod tests {
         assert_eq!(analysis.texture_complexity, cloned.texture_complexity);
         assert_eq!(analysis.overall_complexity, cloned.overall_complexity);
     }
+
+    #[test]
+    fn test_degenerate_images_yield_finite_metrics() {
+        let engine = SmartCompressionEngine::new();
+
+        for (width, height) in [(1, 1), (2, 7), (1, 300)] {
+            let img = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                width,
+                height,
+                image::Rgb([200, 10, 60]),
+            ));
+            let analysis = engine.analyze_image_complexity(&img).unwrap();
+            let metrics = [
+                analysis.edge_density,
+                analysis.texture_complexity,
+                analysis.color_analysis.color_diversity,
+                analysis.color_analysis.color_variance,
+                analysis.frequency_analysis.high_frequency_ratio,
+                analysis.frequency_analysis.low_frequency_ratio,
+                analysis.overall_complexity,
+                analysis.perceptual_quality_score,
+            ];
+            assert!(metrics.iter().all(|m| m.is_finite()), "{width}x{height}");
+            assert_eq!(analysis.overall_complexity, 0.0);
+        }
+
+        // 零面积图像在分析入口直接报错
+        let empty = DynamicImage::ImageRgb8(image::RgbImage::new(0, 0));
+        assert!(matches!(
+            engine.analyze_image_complexity(&empty),
+            Err(CompressionError::AnalysisError(_))
+        ));
+    }
+
+    #[test]
+    fn test_luma_detail_has_no_chroma_complexity() {
+        // 灰度棋盘：细节全部位于亮度通道
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            let v = if (x / 2 + y / 2) % 2 == 0 { 30 } else { 220 };
+            image::Rgb([v, v, v])
+        }));
+
+        let analysis = SmartCompressionEngine::new()
+            .analyze_image_complexity(&img)
+            .unwrap();
+        assert!(analysis.luma_complexity > 0.5);
+        assert!(analysis.chroma_complexity < 0.01);
+    }
+
+    /// 128x128 gradient with xorshift noise, PNG-encoded
+    #[cfg(feature = "jpeg")]
+    fn photo(seed: u32) -> Vec<u8> {
+        let mut seed = seed;
+        let img = image::RgbImage::from_fn(128, 128, |x, y| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let noise = (seed % 24) as i32 - 12;
+            image::Rgb([
+                (x as i32 * 2 + noise).clamp(0, 255) as u8,
+                (y as i32 * 2 + noise).clamp(0, 255) as u8,
+                ((x + y) as i32 + noise).clamp(0, 255) as u8,
+            ])
+        });
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(img)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_size_model_seeds_later_runs() {
+        let constraints = SmartCompressionConstraints {
+            target_size: None,
+            max_width: None,
+            max_height: None,
+            min_quality: None,
+            preferred_formats: None,
+            resize: None,
+        };
+
+        // 目标取第一张图在质量 50 时的体积，迫使首次运行多次迭代
+        let engine = SmartCompressionEngine::new();
+        let first = photo(0x9e37_79b9);
+        let target = engine
+            .compression_engine
+            .compress(
+                &first,
+                &CompressionOptions {
+                    format: Some("jpeg".to_string()),
+                    quality: Some(50),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .compressed_size;
+        let target = format!("{target}");
+
+        assert!(engine.size_model().predict_quality("jpeg", 1.0).is_none());
+        engine
+            .iterative_compress_to_size(&first, "jpeg", &target, &constraints, None)
+            .unwrap();
+        let cold = engine.size_model().last_iterations();
+
+        engine
+            .iterative_compress_to_size(&photo(0x1234_5678), "jpeg", &target, &constraints, None)
+            .unwrap();
+        let warm = engine.size_model().last_iterations();
+        assert!(warm < cold, "warm run took {warm} iterations, cold {cold}");
+    }
+
+    #[cfg(feature = "jpeg")]
+    #[test]
+    fn test_size_model_prediction_with_resize() {
+        let constraints = SmartCompressionConstraints {
+            target_size: None,
+            max_width: None,
+            max_height: None,
+            min_quality: None,
+            preferred_formats: None,
+            resize: Some(crate::compression::ResizeOptions {
+                width: Some(64),
+                height: None,
+                ..Default::default()
+            }),
+        };
+
+        // 先用无法达到的目标跑一遍，让模型记录从 95 到最低质量的全部样本
+        let engine = SmartCompressionEngine::new();
+        let source = photo(0x9e37_79b9);
+        engine
+            .compress_to_bytes(&source, "jpeg", 1, &constraints, None)
+            .unwrap();
+
+        // 模型按输出尺寸记录码率；目标码率若按源尺寸换算会偏低四倍，
+        // 预测出远低于所需的质量
+        let target = engine
+            .compression_engine
+            .compress(
+                &source,
+                &CompressionOptions {
+                    format: Some("jpeg".to_string()),
+                    quality: Some(80),
+                    resize: constraints.resize.clone(),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .compressed_size;
+        let result = engine
+            .compress_to_bytes(&source, "jpeg", target, &constraints, None)
+            .unwrap();
+        assert!(
+            result.compressed_size <= target && result.compressed_size * 10 >= target * 9,
+            "produced {} bytes for a {target} byte target",
+            result.compressed_size
+        );
+    }
+
+    #[test]
+    fn test_median_cut_finds_cluster_centers() {
+        // 两个等大的簇，分别围绕 (40, 60, 200) 与 (220, 180, 30)
+        let mut pixels = Vec::new();
+        for center in [[40u8, 60, 200], [220, 180, 30]] {
+            for offset in [-6i16, -3, 0, 3, 6] {
+                pixels.push(center.map(|c| (c as i16 + offset) as u8));
+            }
+        }
+
+        let mut palette = quantize::median_cut(&pixels, 2);
+        palette.sort_unstable();
+        assert_eq!(palette, vec![[40, 60, 200], [220, 180, 30]]);
+        assert_eq!(quantize::map_to_palette([35, 70, 190], &palette), 0);
+        assert_eq!(quantize::map_to_palette([230, 170, 40], &palette), 1);
+    }
+
+    #[test]
+    fn test_median_cut_keeps_existing_colors_when_under_budget() {
+        let pixels = [[1, 2, 3], [200, 0, 0], [1, 2, 3], [0, 0, 255], [200, 0, 0]];
+        let mut palette = quantize::median_cut(&pixels, 16);
+        palette.sort_unstable();
+        assert_eq!(palette, vec![[0, 0, 255], [1, 2, 3], [200, 0, 0]]);
+        assert!(quantize::median_cut(&[], 16).is_empty());
+    }
+
+    #[test]
+    fn test_dominant_colors_break_ties_deterministically() {
+        // 左右两半像素数完全相同
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, _| {
+            if x < 8 {
+                image::Rgb([200, 40, 40])
+            } else {
+                image::Rgb([40, 40, 200])
+            }
+        }));
+
+        let engine = SmartCompressionEngine::new();
+        let first = engine
+            .analyze_color_distribution(&img)
+            .unwrap()
+            .dominant_colors;
+        assert_eq!(first, vec![(40, 40, 200), (200, 40, 40)]);
+        for _ in 0..20 {
+            let again = engine
+                .analyze_color_distribution(&img)
+                .unwrap()
+                .dominant_colors;
+            assert_eq!(again, first);
+        }
+    }
 }
@@ -0,0 +1,68 @@
+//! Fixtures shared by the integration test binaries
+
+use image::DynamicImage;
+
+/// Build a photo-like test image: smooth gradients with deterministic noise
+pub fn create_photo_png(width: u32, height: u32) -> Vec<u8> {
+    let mut seed = 0x2545_f491u32;
+    let img = image::RgbImage::from_fn(width, height, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let noise = (seed % 24) as i32 - 12;
+        let r = (x * 255 / width) as i32 + noise;
+        let g = (y * 255 / height) as i32 + noise;
+        let b = ((x + y) * 255 / (width + height)) as i32 - noise;
+        image::Rgb([
+            r.clamp(0, 255) as u8,
+            g.clamp(0, 255) as u8,
+            b.clamp(0, 255) as u8,
+        ])
+    });
+
+    encode_png(&DynamicImage::ImageRgb8(img))
+}
+
+/// Encode an image as PNG in memory
+pub fn encode_png(img: &DynamicImage) -> Vec<u8> {
+    let mut png_data = Vec::new();
+    img.write_to(
+        &mut std::io::Cursor::new(&mut png_data),
+        image::ImageFormat::Png,
+    )
+    .expect("Failed to encode test image");
+    png_data
+}
+
+/// Little-endian TIFF block holding a single EXIF orientation tag
+pub fn orientation_exif(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"II*\0".to_vec();
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&0x0112u16.to_le_bytes());
+    tiff.extend_from_slice(&3u16.to_le_bytes());
+    tiff.extend_from_slice(&1u32.to_le_bytes());
+    tiff.extend_from_slice(&orientation.to_le_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff
+}
+
+/// Encode an image as quality-90 JPEG with an APP1 segment carrying
+/// [`orientation_exif`] right after SOI
+pub fn jpeg_with_orientation(img: &DynamicImage, orientation: u16) -> Vec<u8> {
+    let mut jpeg = Vec::new();
+    img.write_to(
+        &mut std::io::Cursor::new(&mut jpeg),
+        image::ImageOutputFormat::Jpeg(90),
+    )
+    .expect("Failed to encode test image");
+
+    let tiff = orientation_exif(orientation);
+    let mut app1 = vec![0xFF, 0xE1];
+    app1.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(&tiff);
+    jpeg.splice(2..2, app1);
+    jpeg
+}
@@ -1,5 +1,9 @@
 //! Integration tests for rusty-pic-core
 
+// pub 使各测试二进制未用到的共享夹具不触发 dead_code
+pub mod common;
+
+use common::{create_photo_png, encode_png};
 use image::GenericImageView;
 use rusty_pic_core::{CompressionEngine, CompressionOptions, ImageAnalyzer};
 
@@ -10,13 +14,7 @@ fn test_analyze_and_compress_simple_image() {
     let dynamic_img = image::DynamicImage::ImageRgb8(img);
 
     // Encode to PNG for testing
-    let mut png_data = Vec::new();
-    dynamic_img
-        .write_to(
-            &mut std::io::Cursor::new(&mut png_data),
-            image::ImageFormat::Png,
-        )
-        .expect("Failed to encode test image");
+    let png_data = encode_png(&dynamic_img);
 
     // Test analysis
     let analyzer = ImageAnalyzer::new();
@@ -35,8 +33,7 @@ fn test_analyze_and_compress_simple_image() {
     let options = CompressionOptions {
         format: Some("png".to_string()),
         quality: Some(80),
-        resize: None,
-        optimize: None,
+        ..Default::default()
     };
 
     let result = engine
@@ -62,13 +59,7 @@ fn test_resize_functionality() {
     });
     let dynamic_img = image::DynamicImage::ImageRgb8(img);
 
-    let mut png_data = Vec::new();
-    dynamic_img
-        .write_to(
-            &mut std::io::Cursor::new(&mut png_data),
-            image::ImageFormat::Png,
-        )
-        .expect("Failed to encode test image");
+    let png_data = encode_png(&dynamic_img);
 
     let engine = CompressionEngine::new();
     let options = CompressionOptions {
@@ -78,8 +69,9 @@ fn test_resize_functionality() {
             width: Some(10),
             height: Some(10),
             fit: "fill".to_string(),
+            ..Default::default()
         }),
-        optimize: None,
+        ..Default::default()
     };
 
     let result = engine
@@ -101,20 +93,13 @@ fn test_auto_format_selection() {
     let img = image::RgbImage::from_fn(10, 10, |_x, _y| image::Rgb([128, 128, 128]));
     let dynamic_img = image::DynamicImage::ImageRgb8(img);
 
-    let mut png_data = Vec::new();
-    dynamic_img
-        .write_to(
-            &mut std::io::Cursor::new(&mut png_data),
-            image::ImageFormat::Png,
-        )
-        .expect("Failed to encode test image");
+    let png_data = encode_png(&dynamic_img);
 
     let engine = CompressionEngine::new();
     let options = CompressionOptions {
         format: Some("auto".to_string()),
         quality: Some(80),
-        resize: None,
-        optimize: None,
+        ..Default::default()
     };
 
     let result = engine
@@ -125,3 +110,3227 @@ fn test_auto_format_selection() {
     // Should select png as recommended format for this simple, low-color image
     assert_eq!(result.format, "png");
 }
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_webp_near_lossless_smaller_than_lossless() {
+    use rusty_pic_core::compression::OptimizeOptions;
+
+    let png_data = create_photo_png(256, 256);
+    let original = image::load_from_memory(&png_data).unwrap();
+    let engine = CompressionEngine::new();
+
+    let lossless = engine
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("webp".to_string()),
+                quality: Some(100),
+                optimize: Some(OptimizeOptions {
+                    lossless: true,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .expect("Lossless WebP should succeed");
+
+    let near_lossless = engine
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("webp".to_string()),
+                quality: Some(100),
+                optimize: Some(OptimizeOptions {
+                    near_lossless: Some(60),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .expect("Near-lossless WebP should succeed");
+
+    assert!(near_lossless.compressed_size < lossless.compressed_size);
+
+    let decoded = image::load_from_memory(&near_lossless.data).unwrap();
+    let score = rusty_pic_core::metrics::ssim(&original, &decoded).unwrap();
+    assert!(score > 0.95, "near-lossless SSIM too low: {score}");
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_convert_png_graphic_to_lossless_webp() {
+    // Flat-colour graphic: a lossless source should stay lossless
+    let img = image::RgbImage::from_fn(64, 64, |x, _y| {
+        if x < 32 {
+            image::Rgb([200, 30, 30])
+        } else {
+            image::Rgb([30, 30, 200])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img.clone()));
+
+    let engine = CompressionEngine::new();
+    let result = engine
+        .convert(&png_data, "webp")
+        .expect("Conversion should succeed");
+
+    assert_eq!(result.format, "webp");
+    // Lossless WebP bitstreams use the VP8L chunk
+    assert_eq!(&result.data[12..16], b"VP8L");
+
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgb8();
+    assert_eq!(decoded, img);
+}
+
+#[test]
+fn test_analyze_and_compress_matches_separate_calls() {
+    let png_data = create_photo_png(96, 64);
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: Some(80),
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let (analysis, result) = engine.analyze_and_compress(&png_data, &options).unwrap();
+    let expected = ImageAnalyzer::new().analyze(&png_data).unwrap();
+    assert_eq!((analysis.width, analysis.height), (96, 64));
+    assert_eq!(analysis.format, expected.format);
+    assert_eq!(analysis.recommended_format, expected.recommended_format);
+    assert_eq!(analysis.recommended_quality, expected.recommended_quality);
+    assert_eq!(analysis.already_optimized, expected.already_optimized);
+
+    let separate = engine.compress(&png_data, &options).unwrap();
+    assert_eq!(result.format, "png");
+    assert_eq!(result.data, separate.data);
+}
+
+#[test]
+fn test_smallest_file_profile_is_no_larger_than_fastest() {
+    use rusty_pic_core::Profile;
+
+    let png_data = create_photo_png(192, 128);
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    let fastest = CompressionEngine::with_profile(Profile::FastestEncode)
+        .compress(&png_data, &options)
+        .unwrap();
+    let smallest = CompressionEngine::with_profile(Profile::SmallestFile)
+        .compress(&png_data, &options)
+        .unwrap();
+    assert!(
+        smallest.compressed_size <= fastest.compressed_size,
+        "SmallestFile {} bytes vs FastestEncode {} bytes",
+        smallest.compressed_size,
+        fastest.compressed_size
+    );
+
+    // 两者都是无损 PNG，像素一致
+    let decode = |data: &[u8]| image::load_from_memory(data).unwrap().to_rgba8();
+    assert_eq!(decode(&smallest.data), decode(&fastest.data));
+}
+
+#[test]
+fn test_compress_preview_fits_max_side() {
+    let png_data = create_photo_png(256, 128);
+    let engine = CompressionEngine::new();
+
+    let preview = engine
+        .compress_preview(&png_data, "png", 75, Some(64))
+        .expect("Preview encode should succeed");
+    assert_eq!(preview.format, "png");
+    assert_eq!((preview.output_width, preview.output_height), (64, 32));
+    let decoded = image::load_from_memory(&preview.data).unwrap();
+    assert_eq!(decoded.dimensions(), (64, 32));
+
+    // 未指定 max_side 时保持原尺寸，也不会放大
+    let preview = engine.compress_preview(&png_data, "png", 75, None).unwrap();
+    assert_eq!((preview.output_width, preview.output_height), (256, 128));
+    let preview = engine
+        .compress_preview(&png_data, "png", 75, Some(1024))
+        .unwrap();
+    assert_eq!((preview.output_width, preview.output_height), (256, 128));
+}
+
+#[test]
+fn test_compress_preview_keeps_aspect_of_large_source() {
+    // 超过 4MP 的源走分块缩放路径，非正方形输入同样要保持宽高比
+    let img = image::RgbImage::from_fn(2400, 1800, |x, y| {
+        image::Rgb([(x / 10) as u8, (y / 8) as u8, 128])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let preview = CompressionEngine::new()
+        .compress_preview(&png_data, "png", 75, Some(240))
+        .expect("Preview encode should succeed");
+    assert_eq!((preview.output_width, preview.output_height), (240, 180));
+    let decoded = image::load_from_memory(&preview.data).unwrap();
+    assert_eq!(decoded.dimensions(), (240, 180));
+}
+
+#[test]
+fn test_png_level_zero_skips_filter_search() {
+    let png_data = create_photo_png(96, 64);
+    let engine = CompressionEngine::new();
+    let encode = |png: rusty_pic_core::PngOptions| {
+        let options = CompressionOptions {
+            format: Some("png".to_string()),
+            quality: None,
+            optimize: Some(rusty_pic_core::compression::OptimizeOptions {
+                png: Some(png),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        engine.compress(&png_data, &options).unwrap().data
+    };
+
+    // 默认开启的 deflate_optimization 在级别 0 时不生效
+    let level_zero = rusty_pic_core::PngOptions {
+        optimization_level: 0,
+        ..Default::default()
+    };
+    let without_search = rusty_pic_core::PngOptions {
+        deflate_optimization: false,
+        ..level_zero.clone()
+    };
+    assert_eq!(encode(level_zero), encode(without_search));
+    assert_ne!(
+        encode(rusty_pic_core::PngOptions::default()),
+        encode(rusty_pic_core::PngOptions {
+            deflate_optimization: false,
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn test_optimize_same_format_keeps_png() {
+    let png_data = create_photo_png(128, 128);
+
+    let result = CompressionEngine::new()
+        .optimize_same_format(&png_data, 80)
+        .expect("PNG optimization should succeed");
+
+    assert_eq!(result.format, "png");
+    assert!(result.data.len() <= png_data.len());
+    assert_eq!(
+        image::guess_format(&result.data).unwrap(),
+        image::ImageFormat::Png
+    );
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_optimize_same_format_keeps_jpeg() {
+    let photo = image::load_from_memory(&create_photo_png(128, 128)).unwrap();
+    let mut jpeg_data = Vec::new();
+    photo
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg_data),
+            image::ImageOutputFormat::Jpeg(95),
+        )
+        .unwrap();
+
+    let result = CompressionEngine::new()
+        .optimize_same_format(&jpeg_data, 75)
+        .expect("JPEG optimization should succeed");
+
+    assert_eq!(result.format, "jpeg");
+    assert!(result.data.len() <= jpeg_data.len());
+    assert_eq!(
+        image::guess_format(&result.data).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_cmyk_jpeg_converts_without_inversion() {
+    // 纯 C+M+Y=0、M/Y=100% 的印刷色：应还原为红色
+    let cmyk = [0u8, 255, 255, 0].repeat(32 * 32);
+    for color_type in [
+        jpeg_encoder::ColorType::Cmyk,
+        jpeg_encoder::ColorType::CmykAsYcck,
+    ] {
+        let mut jpeg_data = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg_data, 95)
+            .encode(&cmyk, 32, 32, color_type)
+            .unwrap();
+
+        let engine = CompressionEngine::new();
+        let analysis = ImageAnalyzer::new().analyze(&jpeg_data).unwrap();
+        assert_eq!(analysis.metadata.color_type, "cmyk");
+
+        let options = CompressionOptions {
+            format: Some("png".to_string()),
+            quality: None,
+            ..Default::default()
+        };
+        let result = engine.compress(&jpeg_data, &options).unwrap();
+        let decoded = image::load_from_memory(&result.data).unwrap().to_rgb8();
+        let pixel = decoded.get_pixel(16, 16);
+        assert!(
+            pixel[0] > 200 && pixel[1] < 50 && pixel[2] < 50,
+            "{color_type:?}: expected red, got {pixel:?}"
+        );
+    }
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_webp_quality_zero_is_clamped() {
+    let engine = CompressionEngine::new();
+    let png_data = create_photo_png(64, 64);
+
+    let options = CompressionOptions {
+        format: Some("webp".to_string()),
+        quality: Some(0),
+        ..Default::default()
+    };
+
+    let result = engine.compress(&png_data, &options).unwrap();
+    assert!(!result.data.is_empty());
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (64, 64));
+}
+
+#[test]
+fn test_extract_frame_from_animated_gif() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, RgbaImage};
+
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif_data);
+        for shade in [0u8, 120, 240] {
+            let frame = Frame::from_parts(
+                RgbaImage::from_pixel(24, 16, image::Rgba([shade, 0, 0, 255])),
+                0,
+                0,
+                Delay::from_numer_denom_ms(100, 1),
+            );
+            encoder.encode_frame(frame).unwrap();
+        }
+    }
+
+    let analyzer = ImageAnalyzer::new();
+    let frame = analyzer.extract_frame(&gif_data, 1).unwrap();
+    assert_eq!(frame.dimensions(), (24, 16));
+    assert_eq!(frame.to_rgba8().get_pixel(5, 5)[0], 120);
+
+    let err = analyzer.extract_frame(&gif_data, 3).unwrap_err();
+    assert!(err.to_string().contains("3 frame(s)"));
+}
+
+#[test]
+fn test_transparent_gif_to_apng_keeps_frames() {
+    use image::codecs::gif::GifEncoder;
+    use image::codecs::png::PngDecoder;
+    use image::{AnimationDecoder, Delay, Frame, RgbaImage};
+
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif_data);
+        for shade in [0u8, 120, 240] {
+            // 左半透明，右半为不同颜色
+            let img = RgbaImage::from_fn(24, 16, |x, _| {
+                if x < 12 {
+                    image::Rgba([0, 0, 0, 0])
+                } else {
+                    image::Rgba([shade, 40, 80, 255])
+                }
+            });
+            let frame = Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1));
+            encoder.encode_frame(frame).unwrap();
+        }
+    }
+
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("apng".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let result = engine.compress(&gif_data, &options).unwrap();
+    assert_eq!(result.format, "apng");
+    assert!(CompressionEngine::supported_formats().contains(&"apng".to_string()));
+
+    let decoder = PngDecoder::new(std::io::Cursor::new(&result.data)).unwrap();
+    assert!(decoder.is_apng());
+    let frames = decoder.apng().into_frames().collect_frames().unwrap();
+    assert_eq!(frames.len(), 3);
+
+    for (frame, shade) in frames.iter().zip([0u8, 120, 240]) {
+        let buffer = frame.buffer();
+        assert_eq!(buffer.dimensions(), (24, 16));
+        assert_eq!(buffer.get_pixel(2, 2)[3], 0);
+        assert_eq!(buffer.get_pixel(20, 2).0, [shade, 40, 80, 255]);
+        assert_eq!(
+            std::time::Duration::from(frame.delay()),
+            std::time::Duration::from_millis(100)
+        );
+    }
+}
+
+#[test]
+fn test_apng_applies_autocrop_and_force_color_type() {
+    use image::codecs::gif::GifEncoder;
+    use image::codecs::png::PngDecoder;
+    use image::{AnimationDecoder, Delay, Frame, RgbaImage};
+
+    // 透明画布上移动的方块：两帧内容范围的并集为 (4, 4) 起 16x12
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif_data);
+        for (left, top) in [(4u32, 4u32), (12, 8)] {
+            let img = RgbaImage::from_fn(32, 32, |x, y| {
+                if (left..left + 8).contains(&x) && (top..top + 8).contains(&y) {
+                    image::Rgba([30, 60, 200, 255])
+                } else {
+                    image::Rgba([0, 0, 0, 0])
+                }
+            });
+            let frame = Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1));
+            encoder.encode_frame(frame).unwrap();
+        }
+    }
+
+    let options = CompressionOptions {
+        format: Some("apng".to_string()),
+        quality: None,
+        autocrop: true,
+        force_color_type: Some("rgb".to_string()),
+        background: Some([255, 0, 0]),
+        ..Default::default()
+    };
+    let result = CompressionEngine::new()
+        .compress(&gif_data, &options)
+        .unwrap();
+    assert_eq!((result.output_width, result.output_height), (16, 12));
+
+    let frames = PngDecoder::new(std::io::Cursor::new(&result.data))
+        .unwrap()
+        .apng()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    assert_eq!(frames.len(), 2);
+    for frame in &frames {
+        let buffer = frame.buffer();
+        assert_eq!(buffer.dimensions(), (16, 12));
+        assert!(buffer.pixels().all(|pixel| pixel[3] == 255));
+    }
+    // 第一帧方块位于左上角，右下角原为透明，已压平到红色背景
+    assert_eq!(frames[0].buffer().get_pixel(2, 2).0, [30, 60, 200, 255]);
+    assert_eq!(frames[0].buffer().get_pixel(15, 11).0, [255, 0, 0, 255]);
+}
+
+#[test]
+fn test_analysis_downscale_complexity_close_to_full() {
+    let png_data = create_photo_png(1024, 768);
+
+    let full = ImageAnalyzer::new().analyze(&png_data).unwrap();
+    let fast = ImageAnalyzer::with_analysis_downscale(256)
+        .analyze(&png_data)
+        .unwrap();
+
+    // 尺寸仍按原图报告
+    assert_eq!((fast.width, fast.height), (1024, 768));
+    assert!(
+        (full.complexity - fast.complexity).abs() < 0.1,
+        "full {} vs downscaled {}",
+        full.complexity,
+        fast.complexity
+    );
+}
+
+#[test]
+fn test_quality_bounds_clamp_recommendation() {
+    // 强纹理噪声图：未加限制时推荐质量高于 80
+    let mut seed = 0x2545_f491u32;
+    let img = image::RgbImage::from_fn(256, 256, |_, _| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        image::Rgb([seed as u8, (seed >> 8) as u8, (seed >> 16) as u8])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let unbounded = ImageAnalyzer::new().analyze(&png_data).unwrap();
+    assert_ne!(unbounded.recommended_format, "png");
+    assert!(
+        unbounded.recommended_quality > 80,
+        "precondition: got {}",
+        unbounded.recommended_quality
+    );
+
+    let capped = ImageAnalyzer::with_quality_bounds(0, 80)
+        .analyze(&png_data)
+        .unwrap();
+    assert_eq!(capped.recommended_quality, 80);
+    assert_eq!(capped.recommended_format, unbounded.recommended_format);
+
+    let floored = ImageAnalyzer::with_quality_bounds(95, 100)
+        .analyze(&png_data)
+        .unwrap();
+    assert_eq!(floored.recommended_quality, 95);
+}
+
+#[test]
+fn test_tiff_deflate_round_trip() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::{TiffCompression, TiffOptions};
+
+    let engine = CompressionEngine::new();
+    let img = image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 4) as u8, 96])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+    assert!(CompressionEngine::supported_formats().contains(&"tiff".to_string()));
+
+    let tiff_with = |compression| CompressionOptions {
+        format: Some("tiff".to_string()),
+        quality: None,
+        optimize: Some(OptimizeOptions {
+            tiff: Some(TiffOptions { compression }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let deflate = engine
+        .compress(&png_data, &tiff_with(TiffCompression::Deflate))
+        .unwrap();
+    let uncompressed = engine
+        .compress(&png_data, &tiff_with(TiffCompression::None))
+        .unwrap();
+    assert!(deflate.compressed_size < uncompressed.compressed_size);
+
+    let original = image::load_from_memory(&png_data).unwrap().to_rgb8();
+    let decoded = image::load_from_memory(&deflate.data).unwrap().to_rgb8();
+    assert_eq!(original, decoded);
+}
+
+#[test]
+fn test_png_data_uri_round_trip() {
+    use base64::Engine;
+
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let result = engine
+        .compress(&create_photo_png(16, 16), &options)
+        .unwrap();
+
+    let uri = result.to_data_uri();
+    let payload = uri
+        .strip_prefix("data:image/png;base64,")
+        .expect("PNG data URI prefix");
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(payload)
+        .unwrap();
+    assert_eq!(decoded, result.data);
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_jpeg_restart_interval_markers() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::JpegOptions;
+
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("jpeg".to_string()),
+        quality: Some(80),
+        optimize: Some(OptimizeOptions {
+            jpeg: Some(JpegOptions {
+                restart_interval: Some(16),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let result = engine
+        .compress(&create_photo_png(96, 64), &options)
+        .unwrap();
+    assert!(result.data.windows(2).any(|w| w == [0xFF, 0xDD]));
+
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (96, 64));
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_regenerate_embedded_thumbnail_follows_resize() {
+    use rusty_pic_core::compression::ResizeOptions;
+    use rusty_pic_core::exif;
+
+    let encode_jpeg = |img: &image::DynamicImage| {
+        let mut out = Vec::new();
+        img.write_to(
+            &mut std::io::Cursor::new(&mut out),
+            image::ImageOutputFormat::Jpeg(85),
+        )
+        .unwrap();
+        out
+    };
+    let source = image::load_from_memory(&create_photo_png(640, 480)).unwrap();
+    // 旧缩略图尺寸与原图无关，重新生成后应与输出图等比例
+    let stale_thumbnail = encode_jpeg(&source.thumbnail(64, 64));
+    let source_jpeg = exif::embed_thumbnail(&encode_jpeg(&source), &stale_thumbnail).unwrap();
+
+    let engine = CompressionEngine::new();
+    let mut options = CompressionOptions {
+        format: Some("jpeg".to_string()),
+        quality: Some(80),
+        resize: Some(ResizeOptions {
+            width: Some(320),
+            height: None,
+            ..Default::default()
+        }),
+        regenerate_embedded_thumbnail: true,
+        ..Default::default()
+    };
+
+    let result = engine.compress(&source_jpeg, &options).unwrap();
+    let thumbnail = exif::embedded_thumbnail(&result.data).expect("thumbnail embedded");
+    let thumbnail = image::load_from_memory(thumbnail).unwrap();
+    let output = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(output.dimensions(), (320, 240));
+    assert_eq!(thumbnail.dimensions(), (160, 120));
+
+    options.regenerate_embedded_thumbnail = false;
+    let result = engine.compress(&source_jpeg, &options).unwrap();
+    assert!(exif::embedded_thumbnail(&result.data).is_none());
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_normalize_rotated_jpeg_with_exif() {
+    // 存储方向为横向，左上角标红；EXIF 方向 6 表示需顺时针旋转 90°
+    let img = image::RgbImage::from_fn(2600, 1800, |x, y| {
+        if x < 200 && y < 200 {
+            image::Rgb([255, 0, 0])
+        } else {
+            image::Rgb([(x / 11) as u8, (y / 8) as u8, 128])
+        }
+    });
+    let jpeg = common::jpeg_with_orientation(&image::DynamicImage::ImageRgb8(img), 6);
+    assert_eq!(rusty_pic_core::exif::orientation(&jpeg), Some(6));
+
+    let engine = CompressionEngine::new();
+    let result = engine.normalize(&jpeg).unwrap();
+    assert_eq!(result.format, "webp");
+    assert_eq!(&result.data[..4], b"RIFF");
+    assert_eq!(&result.data[8..12], b"WEBP");
+    assert!(!result.data.windows(4).any(|w| w == b"EXIF" || w == b"Exif"));
+
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgb8();
+    let (width, height) = decoded.dimensions();
+    assert!(width as u64 * height as u64 <= 4_000_000);
+    assert!(height > width);
+    // 旋转后红色角落位于右上
+    let corner = decoded.get_pixel(width - 20, 20);
+    assert!(corner[0] > 200 && corner[1] < 60 && corner[2] < 60);
+}
+
+#[test]
+fn test_normalize_keeps_thin_strip_over_budget() {
+    use rusty_pic_core::compression::NormalizeOptions;
+
+    let strip = image::RgbImage::from_fn(400, 1, |x, _| image::Rgb([x as u8, 64, 128]));
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(strip));
+
+    let result = CompressionEngine::new()
+        .normalize_with(
+            &png_data,
+            &NormalizeOptions {
+                max_pixels: Some(100),
+                format: "png".to_string(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!((result.output_width, result.output_height), (200, 1));
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_preserve_metadata_carries_exif_into_webp() {
+    let img = image::RgbImage::from_fn(96, 64, |x, y| {
+        image::Rgb([(x * 2) as u8, (y * 3) as u8, 128])
+    });
+    let jpeg = common::jpeg_with_orientation(&image::DynamicImage::ImageRgb8(img), 6);
+    let tiff = common::orientation_exif(6);
+
+    let engine = CompressionEngine::new();
+    let mut options = CompressionOptions {
+        format: Some("webp".to_string()),
+        quality: Some(80),
+        allow_upscale: false,
+        preserve_metadata: true,
+        ..Default::default()
+    };
+
+    let result = engine.compress(&jpeg, &options).unwrap();
+    let chunks: Vec<_> = rusty_pic_core::exif::webp_chunks(&result.data).collect();
+    let (_, vp8x) = chunks.iter().find(|(kind, _)| kind == b"VP8X").unwrap();
+    assert_ne!(vp8x[0] & 0x08, 0, "VP8X EXIF flag set");
+    let (_, exif) = chunks.iter().find(|(kind, _)| kind == b"EXIF").unwrap();
+    assert_eq!(*exif, &tiff[..]);
+    assert_eq!(
+        rusty_pic_core::exif::source_exif(&result.data),
+        Some(&tiff[..])
+    );
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (96, 64));
+
+    // 像素已转正时，复制的方向标签复位为 1
+    options.auto_orient = true;
+    let result = engine.compress(&jpeg, &options).unwrap();
+    let exif = rusty_pic_core::exif::source_exif(&result.data).unwrap();
+    assert_eq!(exif.len(), tiff.len());
+    assert_eq!(&exif[18..20], &1u16.to_le_bytes());
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (64, 96));
+
+    options.preserve_metadata = false;
+    let result = engine.compress(&jpeg, &options).unwrap();
+    assert!(rusty_pic_core::exif::source_exif(&result.data).is_none());
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_jpeg_adaptive_regions_smaller_at_matched_ssim() {
+    use rusty_pic_core::compression::OptimizeOptions;
+
+    // 左半平坦（轻微噪声），右半高细节纹理
+    let mut seed = 0x2545_f491u32;
+    let img = image::RgbImage::from_fn(256, 256, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if x < 128 {
+            let v = (100 + (seed % 7) as i32 - 3) as u8;
+            image::Rgb([v, v + 20, v + 40])
+        } else {
+            let v = ((seed % 200) + (x * 7 + y * 13) % 50) as u8;
+            image::Rgb([v, 255 - v, (v / 2).wrapping_add((y as u8).wrapping_mul(3))])
+        }
+    });
+    let source = image::DynamicImage::ImageRgb8(img);
+    let png_data = encode_png(&source);
+
+    let engine = CompressionEngine::new();
+    let encode = |quality: u8, adaptive_regions: bool| {
+        let options = CompressionOptions {
+            format: Some("jpeg".to_string()),
+            quality: Some(quality),
+            optimize: Some(OptimizeOptions {
+                adaptive_regions,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let data = engine.compress(&png_data, &options).unwrap().data;
+        let decoded = image::load_from_memory(&data).unwrap();
+        let ssim = rusty_pic_core::metrics::ssim(&source, &decoded).unwrap();
+        (data.len(), ssim)
+    };
+
+    let (adaptive_size, adaptive_ssim) = encode(75, true);
+    // 找到达到相同 SSIM 的最低统一质量
+    let (uniform_size, _) = (50..=100)
+        .map(|quality| encode(quality, false))
+        .find(|&(_, ssim)| ssim >= adaptive_ssim)
+        .expect("uniform quality reaching adaptive SSIM");
+
+    assert!(
+        adaptive_size < uniform_size,
+        "adaptive {adaptive_size} bytes vs uniform {uniform_size} bytes at SSIM {adaptive_ssim}"
+    );
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn test_avif_ten_bit_from_sixteen_bit_source() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::{AvifOptions, CompressionError};
+
+    let source = image::ImageBuffer::<image::Rgb<u16>, _>::from_fn(32, 32, |x, y| {
+        image::Rgb([(x * 2047) as u16, (y * 2047) as u16, 40_000])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb16(source));
+
+    let engine = CompressionEngine::new();
+    let options_for = |bit_depth| CompressionOptions {
+        format: Some("avif".to_string()),
+        quality: Some(70),
+        optimize: Some(OptimizeOptions {
+            avif: Some(AvifOptions {
+                speed: 10,
+                bit_depth,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let result = engine.compress(&png_data, &options_for(10)).unwrap();
+    assert_eq!(&result.data[4..8], b"ftyp");
+
+    // pixi 盒: fourcc + version/flags(4) + 通道数(1) + 每通道位深
+    let pixi = result
+        .data
+        .windows(4)
+        .position(|w| w == b"pixi")
+        .expect("pixi box present");
+    let channels = result.data[pixi + 8] as usize;
+    let depths = &result.data[pixi + 9..pixi + 9 + channels];
+    assert!(channels > 0);
+    assert!(depths.iter().all(|&d| d == 10));
+
+    assert!(matches!(
+        engine.compress(&png_data, &options_for(12)),
+        Err(CompressionError::UnsupportedFeature(_))
+    ));
+    assert!(matches!(
+        engine.compress(&png_data, &options_for(9)),
+        Err(CompressionError::InvalidFormat(_))
+    ));
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_compare_formats_png_vs_webp() {
+    let engine = CompressionEngine::new();
+    let png_data = create_photo_png(96, 96);
+    let formats = vec!["png".to_string(), "webp".to_string()];
+
+    let comparisons = engine.compare_formats(&png_data, &formats, 80).unwrap();
+    assert_eq!(comparisons.len(), 2);
+
+    for (comparison, format) in comparisons.iter().zip(&formats) {
+        assert_eq!(&comparison.format, format);
+        assert!(comparison.size > 0);
+        assert!(comparison.ratio > 0.0);
+        assert!(comparison.ssim > 0.8 && comparison.ssim <= 1.0 + 1e-6);
+        assert!(comparison.encode_ms >= 0.0);
+    }
+    // PNG 无损
+    assert!((comparisons[0].ssim - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_one_bit_png_keeps_low_bit_depth() {
+    let (width, height) = (128u32, 64u32);
+    // 1 位黑白扫描：每字节 8 像素，MSB 在前
+    let row_bytes = (width / 8) as usize;
+    let packed: Vec<u8> = (0..height)
+        .flat_map(|y| {
+            (0..row_bytes).map(move |x| {
+                if (x + y as usize / 8).is_multiple_of(2) {
+                    0xF0
+                } else {
+                    0x0F
+                }
+            })
+        })
+        .collect();
+
+    let mut png_data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&packed).unwrap();
+    }
+
+    let analysis = ImageAnalyzer::new().analyze(&png_data).unwrap();
+    assert_eq!(analysis.metadata.bit_depth, 1);
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let result = CompressionEngine::new()
+        .compress(&png_data, &options)
+        .unwrap();
+    // IHDR 位深字节
+    assert_eq!(result.data[24], 1);
+    assert!(result.compressed_size <= png_data.len() + 64);
+
+    let original = image::load_from_memory(&png_data).unwrap().to_luma8();
+    let decoded = image::load_from_memory(&result.data).unwrap().to_luma8();
+    assert_eq!(original, decoded);
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_compress_paths_to_dir_writes_webp() {
+    let root = std::env::temp_dir().join(format!("rusty-pic-paths-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let (dir_a, dir_b, out_dir) = (root.join("a"), root.join("b"), root.join("out"));
+    std::fs::create_dir_all(&dir_a).unwrap();
+    std::fs::create_dir_all(&dir_b).unwrap();
+
+    // 同名输入触发冲突后缀
+    let inputs = vec![dir_a.join("photo.png"), dir_b.join("photo.png")];
+    for input in &inputs {
+        std::fs::write(input, create_photo_png(32, 32)).unwrap();
+    }
+
+    let options = CompressionOptions {
+        format: Some("webp".to_string()),
+        quality: Some(80),
+        ..Default::default()
+    };
+    let outputs: Vec<_> = CompressionEngine::new()
+        .compress_paths_to_dir(&inputs, &out_dir, &options)
+        .into_iter()
+        .map(|r| r.unwrap())
+        .collect();
+
+    assert_eq!(outputs[0], out_dir.join("photo.webp"));
+    assert_eq!(outputs[1], out_dir.join("photo-1.webp"));
+    for output in &outputs {
+        assert!(output.exists());
+        assert_eq!(output.extension().unwrap(), "webp");
+    }
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_png_filter_search_not_larger_than_paeth() {
+    use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+    use image::ImageEncoder;
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::PngOptions;
+
+    let png_data = create_photo_png(128, 96);
+    let rgba = image::load_from_memory(&png_data).unwrap().to_rgba8();
+    let mut paeth = Vec::new();
+    PngEncoder::new_with_quality(&mut paeth, CompressionType::Default, FilterType::Paeth)
+        .write_image(rgba.as_raw(), 128, 96, image::ColorType::Rgba8)
+        .unwrap();
+
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        optimize: Some(OptimizeOptions {
+            png: Some(PngOptions {
+                optimization_level: 6,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let result = engine.compress(&png_data, &options).unwrap();
+    assert!(
+        result.data.len() <= paeth.len(),
+        "searched {} bytes vs Paeth {} bytes",
+        result.data.len(),
+        paeth.len()
+    );
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgba8();
+    assert_eq!(decoded, rgba);
+}
+
+#[test]
+fn test_already_optimized_jpeg_detected() {
+    // 平滑渐变在高质量下码率依然很低，已接近编码器极限
+    let img = image::RgbImage::from_fn(512, 512, |x, y| {
+        image::Rgb([(x / 2) as u8, (y / 2) as u8, ((x + y) / 4) as u8])
+    });
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageOutputFormat::Jpeg(90),
+        )
+        .unwrap();
+
+    let analyzer = ImageAnalyzer::new();
+    let analysis = analyzer.analyze(&jpeg).unwrap();
+    assert!(analysis.already_optimized);
+    assert!(analysis.estimated_savings < 0.1);
+
+    // 未压缩的照片类 PNG 仍有明显空间
+    let analysis = analyzer.analyze(&create_photo_png(256, 256)).unwrap();
+    assert!(!analysis.already_optimized);
+    assert!(analysis.estimated_savings > 0.5);
+}
+
+#[test]
+fn test_skip_if_savings_below_returns_input() {
+    let img = image::RgbImage::from_fn(512, 512, |x, y| {
+        image::Rgb([(x / 2) as u8, (y / 2) as u8, ((x + y) / 4) as u8])
+    });
+    let mut jpeg = Vec::new();
+    image::DynamicImage::ImageRgb8(img)
+        .write_to(
+            &mut std::io::Cursor::new(&mut jpeg),
+            image::ImageOutputFormat::Jpeg(90),
+        )
+        .unwrap();
+
+    let mut options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        skip_if_savings_below: Some(0.1),
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let result = engine.compress(&jpeg, &options).unwrap();
+    assert!(result.fell_back);
+    assert_eq!(result.data, jpeg);
+    assert_eq!(result.format, "jpeg");
+    assert_eq!((result.output_width, result.output_height), (512, 512));
+    assert_eq!(
+        result.ratio_vs_raw,
+        jpeg.len() as f32 / result.metadata.raw_size() as f32
+    );
+    assert!(result.ratio_vs_raw < 1.0);
+
+    // 照片类 PNG 预计节省明显，照常编码
+    let photo = create_photo_png(256, 256);
+    let result = engine.compress(&photo, &options).unwrap();
+    assert!(!result.fell_back);
+
+    options.skip_if_savings_below = None;
+    let result = engine.compress(&jpeg, &options).unwrap();
+    assert!(!result.fell_back);
+    assert_eq!(result.format, "png");
+}
+
+#[test]
+fn test_compress_responsive_widths() {
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    let results = engine
+        .compress_responsive(&create_photo_png(1280, 720), &[320, 640, 1280], &options)
+        .unwrap();
+    assert_eq!(results.len(), 3);
+
+    for (result, width) in results.iter().zip([320u32, 640, 1280]) {
+        let decoded = image::load_from_memory(&result.data).unwrap();
+        assert_eq!(decoded.dimensions(), (width, width * 9 / 16));
+    }
+}
+
+#[test]
+fn test_compress_raw_rgba_buffer() {
+    let pixels: Vec<u8> = (0..10 * 10)
+        .flat_map(|i| {
+            [
+                (i * 2) as u8,
+                100,
+                (255 - i) as u8,
+                if i % 2 == 0 { 255 } else { 128 },
+            ]
+        })
+        .collect();
+
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    let result = engine.compress_raw(&pixels, 10, 10, 4, &options).unwrap();
+    assert_eq!(result.format, "png");
+    assert_eq!(result.original_size, pixels.len());
+
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (10, 10));
+    assert_eq!(decoded.to_rgba8().as_raw(), &pixels);
+
+    // 长度与尺寸不符时报错
+    assert!(engine
+        .compress_raw(&pixels[..399], 10, 10, 4, &options)
+        .is_err());
+}
+
+#[test]
+fn test_resize_extreme_aspect_ratio_never_zero() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    let strip = image::RgbImage::from_pixel(2000, 1, image::Rgb([120, 60, 30]));
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(strip));
+
+    let engine = CompressionEngine::new();
+    let options_for = |width, height| CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        resize: Some(ResizeOptions {
+            width,
+            height,
+            fit: "contain".to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    let result = engine
+        .compress(&png_data, &options_for(Some(100), Some(100)))
+        .unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (100, 1));
+
+    // 显式请求 0 尺寸应报错而不是 panic
+    for (width, height) in [(Some(0), Some(100)), (Some(100), Some(0)), (Some(0), None)] {
+        assert!(matches!(
+            engine.compress(&png_data, &options_for(width, height)),
+            Err(rusty_pic_core::CompressionError::InvalidFormat(_))
+        ));
+    }
+}
+
+#[test]
+fn test_linear_light_resize_preserves_brightness() {
+    let checker = image::RgbImage::from_fn(64, 64, |x, y| {
+        if (x + y) % 2 == 0 {
+            image::Rgb([255, 255, 255])
+        } else {
+            image::Rgb([0, 0, 0])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(checker));
+
+    let engine = CompressionEngine::new();
+    let mean_linear = |linear_light_resize: bool| {
+        let options = CompressionOptions {
+            format: Some("png".to_string()),
+            quality: None,
+            resize: Some(rusty_pic_core::compression::ResizeOptions {
+                width: Some(8),
+                height: Some(8),
+                fit: "fill".to_string(),
+                linear_light_resize,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let result = engine.compress(&png_data, &options).unwrap();
+        let gray = image::load_from_memory(&result.data).unwrap().to_luma8();
+        assert_eq!(gray.dimensions(), (8, 8));
+        let sum: f32 = gray
+            .pixels()
+            .map(|p| {
+                let c = p[0] as f32 / 255.0;
+                if c <= 0.04045 {
+                    c / 12.92
+                } else {
+                    ((c + 0.055) / 1.055).powf(2.4)
+                }
+            })
+            .sum();
+        sum / 64.0
+    };
+
+    let linear = mean_linear(true);
+    let naive = mean_linear(false);
+    assert!((linear - 0.5).abs() < 0.05, "linear-light mean {linear}");
+    assert!(
+        linear > naive,
+        "linear {linear} should be brighter than naive {naive}"
+    );
+}
+
+#[test]
+fn test_chroma_key_green_background() {
+    // 绿色背景上的红色方块
+    let img = image::RgbImage::from_fn(32, 32, |x, y| {
+        if (8..24).contains(&x) && (8..24).contains(&y) {
+            image::Rgb([200, 30, 30])
+        } else {
+            image::Rgb([0, 255, 0])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let keyed = ImageAnalyzer::new()
+        .chroma_key(&png_data, [0, 255, 0], 10)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(keyed.get_pixel(0, 0)[3], 0);
+    assert_eq!(keyed.get_pixel(16, 16)[3], 255);
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        chroma_key: Some(([0, 255, 0], 10)),
+        ..Default::default()
+    };
+    let result = CompressionEngine::new()
+        .compress(&png_data, &options)
+        .unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgba8();
+    assert_eq!(decoded.get_pixel(2, 30)[3], 0);
+    assert_eq!(decoded.get_pixel(12, 12)[3], 255);
+}
+
+#[test]
+fn test_chroma_key_with_auto_format_keeps_transparency() {
+    // 绿色竖条加高噪声纹理：未抠色时分析器推荐 JPEG
+    let mut seed = 0x2545_f491u32;
+    let img = image::RgbImage::from_fn(128, 128, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        if x < 16 {
+            return image::Rgb([0, 255, 0]);
+        }
+        let noise = (seed % 128) as u8;
+        image::Rgb([noise, noise.wrapping_add((x * 2) as u8), (y * 2) as u8])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+    assert_eq!(
+        ImageAnalyzer::new()
+            .analyze(&png_data)
+            .unwrap()
+            .recommended_format,
+        "jpeg"
+    );
+
+    let engine = CompressionEngine::new();
+    for format in [None, Some("auto".to_string())] {
+        let options = CompressionOptions {
+            format,
+            quality: Some(80),
+            chroma_key: Some(([0, 255, 0], 10)),
+            ..Default::default()
+        };
+        let result = engine.compress(&png_data, &options).unwrap();
+        assert!(
+            engine
+                .format_capabilities(&result.format)
+                .unwrap()
+                .supports_alpha,
+            "chose {} for keyed output",
+            result.format
+        );
+        let decoded = image::load_from_memory(&result.data).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(4, 64)[3], 0);
+        assert_eq!(decoded.get_pixel(64, 64)[3], 255);
+    }
+}
+
+#[test]
+fn test_log_callback_receives_messages() {
+    use std::sync::{Arc, Mutex};
+
+    let messages = Arc::new(Mutex::new(Vec::<String>::new()));
+    let sink = Arc::clone(&messages);
+
+    let mut engine = CompressionEngine::new();
+    engine.set_log_callback(move |message| sink.lock().unwrap().push(message.to_string()));
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    engine
+        .compress(&create_photo_png(16, 16), &options)
+        .unwrap();
+
+    let messages = messages.lock().unwrap();
+    assert!(messages
+        .iter()
+        .any(|m| m.starts_with("Starting compression")));
+    assert!(messages.iter().any(|m| m.starts_with("Analysis complete")));
+    assert!(messages
+        .iter()
+        .any(|m| m.starts_with("Compression complete")));
+}
+
+#[test]
+fn test_content_bounds_and_autocrop() {
+    // 白底中央 20x12 的红色方块
+    let img = image::RgbImage::from_fn(64, 48, |x, y| {
+        if (22..42).contains(&x) && (18..30).contains(&y) {
+            image::Rgb([255, 0, 0])
+        } else {
+            image::Rgb([255, 255, 255])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let bounds = ImageAnalyzer::new().content_bounds(&png_data, 8).unwrap();
+    assert_eq!(bounds, (22, 18, 20, 12));
+
+    let engine = CompressionEngine::new();
+    let result = engine
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                autocrop: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.dimensions(), (20, 12));
+    assert!(decoded
+        .to_rgb8()
+        .pixels()
+        .all(|p| *p == image::Rgb([255, 0, 0])));
+}
+
+#[test]
+fn test_suggest_palette_size() {
+    // 4x4 色块，共 16 种差异明显的颜色
+    let img = image::RgbImage::from_fn(64, 64, |x, y| {
+        let i = (y / 16 * 4 + x / 16) as u8;
+        image::Rgb([i * 16, 255 - i * 12, (i % 4) * 80])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let analyzer = ImageAnalyzer::new();
+    assert_eq!(analyzer.suggest_palette_size(&png_data, 256).unwrap(), 16);
+    // 上限低于实际颜色数时不超过上限
+    assert_eq!(analyzer.suggest_palette_size(&png_data, 8).unwrap(), 8);
+
+    let photo = create_photo_png(128, 128);
+    assert_eq!(analyzer.suggest_palette_size(&photo, 64).unwrap(), 64);
+
+    assert!(analyzer.suggest_palette_size(&png_data, 0).is_err());
+}
+
+#[test]
+fn test_palette_swatch_renders_dominant_colors() {
+    // 三条竖带，覆盖率依次递减
+    let img = image::RgbImage::from_fn(60, 20, |x, _| match x {
+        0..=29 => image::Rgb([200, 30, 30]),
+        30..=49 => image::Rgb([30, 200, 30]),
+        _ => image::Rgb([30, 30, 200]),
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let analyzer = ImageAnalyzer::new();
+    let swatch = analyzer.palette_swatch(&png_data, 3, 8).unwrap();
+    let swatch = image::load_from_memory(&swatch).unwrap().to_rgb8();
+    assert_eq!(swatch.dimensions(), (24, 8));
+
+    let colors = analyzer
+        .dominant_colors_with_coverage(&png_data, 3)
+        .unwrap();
+    for (i, ((r, g, b), _)) in colors.iter().enumerate() {
+        let x = i as u32 * 8;
+        for (cx, cy) in [(x, 0), (x + 7, 7), (x + 3, 4)] {
+            assert_eq!(*swatch.get_pixel(cx, cy), image::Rgb([*r, *g, *b]));
+        }
+    }
+    assert_eq!(*swatch.get_pixel(0, 0), image::Rgb([200, 30, 30]));
+
+    assert!(analyzer.palette_swatch(&png_data, 0, 8).is_err());
+}
+
+#[test]
+fn test_most_frequent_edge_ignores_busy_corners() {
+    use rusty_pic_core::BackgroundDetect;
+
+    // 白色边框，四角各不相同，中央为蓝色内容
+    let corners = [[255, 0, 0], [0, 255, 0], [0, 0, 0], [255, 255, 0]];
+    let img = image::RgbImage::from_fn(40, 30, |x, y| match (x, y) {
+        (0, 0) => image::Rgb(corners[0]),
+        (39, 0) => image::Rgb(corners[1]),
+        (0, 29) => image::Rgb(corners[2]),
+        (39, 29) => image::Rgb(corners[3]),
+        _ if (10..30).contains(&x) && (8..22).contains(&y) => image::Rgb([0, 0, 255]),
+        _ => image::Rgb([255, 255, 255]),
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let analyzer = ImageAnalyzer::new();
+    assert_eq!(
+        analyzer
+            .detect_background(&png_data, BackgroundDetect::Corners)
+            .unwrap(),
+        corners[0]
+    );
+    assert_eq!(
+        analyzer
+            .detect_background(&png_data, BackgroundDetect::MostFrequentEdge)
+            .unwrap(),
+        [255, 255, 255]
+    );
+    assert_eq!(
+        analyzer
+            .detect_background(&png_data, BackgroundDetect::Explicit([1, 2, 3]))
+            .unwrap(),
+        [1, 2, 3]
+    );
+
+    let keyed = analyzer
+        .key_background(&png_data, BackgroundDetect::MostFrequentEdge, 0)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(keyed.get_pixel(5, 5)[3], 0);
+    assert_eq!(keyed.get_pixel(0, 0)[3], 255);
+    assert_eq!(keyed.get_pixel(20, 15)[3], 255);
+}
+
+#[test]
+fn test_palette_dithering_breaks_up_banding() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::DitherMethod;
+
+    // 水平方向的平滑灰度渐变
+    let img = image::RgbImage::from_fn(128, 32, |x, _| {
+        let v = (x * 2) as u8;
+        image::Rgb([v, v, v])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let engine = CompressionEngine::new();
+    let quantize = |dither_method| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    optimize: Some(OptimizeOptions {
+                        max_colors: Some(8),
+                        dither_method,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().to_rgb8()
+    };
+    let transitions = |img: &image::RgbImage| {
+        img.rows()
+            .map(|row| {
+                let row: Vec<_> = row.collect();
+                row.windows(2).filter(|p| p[0] != p[1]).count()
+            })
+            .sum::<usize>()
+    };
+
+    let flat = quantize(DitherMethod::None);
+    let mut colors = std::collections::HashSet::new();
+    colors.extend(flat.pixels().map(|p| p.0));
+    assert!(colors.len() <= 8);
+
+    let flat_transitions = transitions(&flat);
+    for method in [DitherMethod::FloydSteinberg, DitherMethod::Ordered] {
+        let dithered = quantize(method);
+        assert!(
+            transitions(&dithered) > flat_transitions,
+            "{method:?}: {} vs {flat_transitions}",
+            transitions(&dithered)
+        );
+    }
+}
+
+#[test]
+fn test_compress_batch_cancel_after_first_image() {
+    use rusty_pic_core::CompressionError;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let images: Vec<Vec<u8>> = (0..4).map(|_| create_photo_png(32, 32)).collect();
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    // 第一张图完成后立即取消
+    let flag = Arc::clone(&cancel);
+    let mut engine = CompressionEngine::new();
+    engine.set_log_callback(move |message| {
+        if message.starts_with("Compression complete") {
+            flag.store(true, Ordering::Release);
+        }
+    });
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    // 单线程线程池保证按输入顺序逐张处理
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let results = pool.install(|| {
+        engine.compress_batch_cancellable(
+            images.iter().map(Vec::as_slice).collect(),
+            &options,
+            Arc::clone(&cancel),
+        )
+    });
+
+    assert_eq!(results.len(), 4);
+    assert!(results[0].is_ok());
+    for result in &results[1..] {
+        assert!(matches!(result, Err(CompressionError::Cancelled(_))));
+    }
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_transcode_opaque_png_to_lossless_webp() {
+    use rusty_pic_core::formats::webp::transcode_png_lossless;
+
+    // 平滑渐变加少量块状图形，典型的界面/插图类 PNG
+    let img = image::RgbImage::from_fn(96, 64, |x, y| {
+        if (x / 16 + y / 16) % 3 == 0 {
+            image::Rgb([30, 60, 200])
+        } else {
+            image::Rgb([(x * 2) as u8, (y * 3) as u8, 128])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img.clone()));
+
+    let webp_data = transcode_png_lossless(&png_data).unwrap();
+    assert!(webp_data.len() < png_data.len());
+
+    // 简单格式 VP8L：签名 0x2f 之后 32 位字段的第 28 位为 alpha_is_used
+    assert_eq!(&webp_data[12..16], b"VP8L");
+    assert_eq!(webp_data[20], 0x2f);
+    let header = u32::from_le_bytes(webp_data[21..25].try_into().unwrap());
+    assert_eq!(header >> 28 & 1, 0, "WebP should not carry alpha");
+
+    let decoded = image::load_from_memory(&webp_data).unwrap();
+    assert_eq!(decoded.to_rgb8(), img);
+}
+
+#[test]
+fn test_compress_batch_per_image_timeout() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::{CompressionError, FormatEncoder};
+    use std::time::Duration;
+
+    const BUDGET: Duration = Duration::from_millis(500);
+
+    // 宽于 16 像素的图像编码时睡过整个预算，小图立即返回
+    struct SlowEncoder;
+    impl FormatEncoder for SlowEncoder {
+        fn name(&self) -> &str {
+            "slow"
+        }
+
+        fn encode(
+            &self,
+            img: &image::DynamicImage,
+            _quality: u8,
+            _opts: &OptimizeOptions,
+        ) -> rusty_pic_core::Result<Vec<u8>> {
+            if img.width() > 16 {
+                std::thread::sleep(BUDGET * 2);
+            }
+            Ok(encode_png(img))
+        }
+    }
+
+    let small = create_photo_png(16, 16);
+    let slow = create_photo_png(64, 64);
+
+    let mut engine = CompressionEngine::new();
+    engine.register_encoder(Box::new(SlowEncoder));
+    let options = CompressionOptions {
+        format: Some("slow".to_string()),
+        quality: None,
+        per_image_timeout: Some(BUDGET),
+        ..Default::default()
+    };
+    let results = engine.compress_batch(
+        vec![small.as_slice(), slow.as_slice(), small.as_slice()],
+        &options,
+    );
+
+    assert!(results[0].is_ok());
+    assert!(matches!(results[1], Err(CompressionError::Timeout(_))));
+    assert!(results[2].is_ok());
+
+    // 已耗尽的预算在编码前即失败
+    let expired = CompressionOptions {
+        per_image_timeout: Some(Duration::ZERO),
+        ..options
+    };
+    assert!(matches!(
+        engine.compress(&small, &expired),
+        Err(CompressionError::Timeout(_))
+    ));
+}
+
+#[test]
+fn test_batch_pass_through_keeps_undecodable_input() {
+    use rusty_pic_core::{BatchOptions, ErrorPolicy};
+
+    let valid = create_photo_png(32, 32);
+    // PNG 签名完整但数据被截断，以及完全无法识别的字节
+    let truncated = valid[..valid.len() / 2].to_vec();
+    let garbage = b"definitely not an image".to_vec();
+    let images = vec![valid.as_slice(), truncated.as_slice(), garbage.as_slice()];
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let failed = engine.compress_batch(images.clone(), &options);
+    assert!(failed[0].is_ok());
+    assert!(failed[1].is_err() && failed[2].is_err());
+
+    let batch = BatchOptions {
+        on_error: ErrorPolicy::PassThrough,
+    };
+    let results = engine.compress_batch_with(images, &options, &batch);
+    let results: Vec<_> = results.into_iter().map(|r| r.unwrap()).collect();
+    assert!(!results[0].fell_back);
+    assert!(image::load_from_memory(&results[0].data).is_ok());
+
+    assert!(results[1].fell_back);
+    assert_eq!(results[1].data, truncated);
+    assert_eq!(results[1].format, "png");
+    // 头部完整，尺寸仍可读出
+    assert_eq!(
+        (results[1].output_width, results[1].output_height),
+        (32, 32)
+    );
+    assert!(results[1].ratio_vs_raw < 1.0);
+
+    assert!(results[2].fell_back);
+    assert_eq!(results[2].data, garbage);
+    assert_eq!(results[2].format, "unknown");
+    assert_eq!((results[2].output_width, results[2].output_height), (0, 0));
+    assert_eq!(results[2].ratio_vs_raw, 1.0);
+}
+
+#[test]
+fn test_resize_filter_follows_direction() {
+    use image::imageops::FilterType;
+    use rusty_pic_core::compression::ResizeOptions;
+    use std::sync::{Arc, Mutex};
+
+    let defaults = ResizeOptions::default();
+    assert_eq!(
+        defaults.filters_for((64, 48), (128, 96)),
+        (FilterType::CatmullRom, FilterType::CatmullRom)
+    );
+    assert_eq!(
+        defaults.filters_for((64, 48), (32, 24)),
+        (FilterType::Lanczos3, FilterType::Lanczos3)
+    );
+    // 单轴放大、另一轴缩小时按轴分别选择；尺寸不变的轴跟随另一轴
+    assert_eq!(
+        defaults.filters_for((64, 48), (128, 24)),
+        (FilterType::CatmullRom, FilterType::Lanczos3)
+    );
+    assert_eq!(
+        defaults.filters_for((64, 48), (64, 96)),
+        (FilterType::CatmullRom, FilterType::CatmullRom)
+    );
+
+    let png_data = create_photo_png(64, 48);
+    let messages = Arc::new(Mutex::new(Vec::<String>::new()));
+    let sink = Arc::clone(&messages);
+    let mut engine = CompressionEngine::new();
+    engine.set_log_callback(move |message| sink.lock().unwrap().push(message.to_string()));
+
+    for (width, expected_size, expected_filters) in [
+        (128, (128, 96), "CatmullRom/CatmullRom"),
+        (32, (32, 24), "Lanczos3/Lanczos3"),
+    ] {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    resize: Some(ResizeOptions {
+                        width: Some(width),
+                        fit: "inside".to_string(),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let decoded = image::load_from_memory(&result.data).unwrap();
+        assert_eq!(decoded.dimensions(), expected_size);
+        assert!(messages
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|m| m.starts_with("Resizing") && m.contains(expected_filters)));
+    }
+}
+
+#[test]
+fn test_sixteen_bit_grayscale_png_round_trip() {
+    // 取值避开 257 的倍数，截断为 8 位必然丢失信息
+    let img = image::ImageBuffer::<image::Luma<u16>, _>::from_fn(32, 24, |x, y| {
+        image::Luma([(x * 2003 + y * 131 + 7) as u16])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageLuma16(img.clone()));
+
+    let result = CompressionEngine::new()
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    match image::load_from_memory(&result.data).unwrap() {
+        image::DynamicImage::ImageLuma16(decoded) => assert_eq!(decoded, img),
+        other => panic!("expected 16-bit grayscale, got {:?}", other.color()),
+    }
+}
+
+#[test]
+fn test_tone_adjustments() {
+    use rusty_pic_core::compression::Adjustments;
+
+    let png_data = create_photo_png(32, 32);
+    let original = image::load_from_memory(&png_data).unwrap().to_rgb8();
+
+    let engine = CompressionEngine::new();
+    let adjusted = |adjust: Adjustments| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    adjust: Some(adjust),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().to_rgb8()
+    };
+
+    // 提亮 0.5：每个通道都变亮（已饱和的保持 255）
+    let brighter = adjusted(Adjustments {
+        brightness: 0.5,
+        ..Default::default()
+    });
+    for (before, after) in original.pixels().zip(brighter.pixels()) {
+        for c in 0..3 {
+            assert!(after[c] > before[c] || after[c] == 255);
+        }
+    }
+
+    // 饱和度 0：结果与按亮度取灰一致
+    let gray = adjusted(Adjustments {
+        saturation: 0.0,
+        ..Default::default()
+    });
+    for (before, after) in original.pixels().zip(gray.pixels()) {
+        assert!(after[0] == after[1] && after[1] == after[2]);
+        let luma = 0.299 * before[0] as f32 + 0.587 * before[1] as f32 + 0.114 * before[2] as f32;
+        assert!((after[0] as f32 - luma).abs() <= 1.0);
+    }
+}
+
+#[test]
+fn test_tone_adjustments_keep_color_type() {
+    use rusty_pic_core::compression::Adjustments;
+
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        adjust: Some(Adjustments {
+            brightness: 0.1,
+            contrast: 1.2,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let gray: Vec<u8> = (0..32 * 24).map(|i| (i * 37 % 251) as u8).collect();
+    let result = engine.compress_raw(&gray, 32, 24, 1, &options).unwrap();
+    assert_eq!(
+        image::load_from_memory(&result.data).unwrap().color(),
+        image::ColorType::L8
+    );
+
+    let img = image::ImageBuffer::<image::Luma<u16>, _>::from_fn(32, 24, |x, y| {
+        image::Luma([(x * 1001 + y * 131 + 7) as u16])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageLuma16(img));
+    let result = engine.compress(&png_data, &options).unwrap();
+    match image::load_from_memory(&result.data).unwrap() {
+        // 16 位精度得以保留：结果不全是 257 的倍数
+        image::DynamicImage::ImageLuma16(decoded) => {
+            assert!(decoded.as_raw().iter().any(|v| v % 257 != 0))
+        }
+        other => panic!("expected 16-bit grayscale, got {:?}", other.color()),
+    }
+}
+
+#[test]
+fn test_encoding_is_deterministic() {
+    use rusty_pic_core::compression::OptimizeOptions;
+
+    let png_data = create_photo_png(96, 64);
+    let engine = CompressionEngine::new();
+    let options_for = |format: &str, optimize| CompressionOptions {
+        format: Some(format.to_string()),
+        quality: None,
+        optimize,
+        ..Default::default()
+    };
+
+    assert!(engine
+        .is_deterministic(&png_data, &options_for("png", None), 5)
+        .unwrap());
+    // 调色板量化曾依赖 HashMap 遍历顺序
+    let quantized = Some(OptimizeOptions {
+        max_colors: Some(16),
+        ..Default::default()
+    });
+    assert!(engine
+        .is_deterministic(&png_data, &options_for("png", quantized), 5)
+        .unwrap());
+
+    #[cfg(feature = "webp")]
+    {
+        let lossless = Some(OptimizeOptions {
+            lossless: true,
+            ..Default::default()
+        });
+        assert!(engine
+            .is_deterministic(&png_data, &options_for("webp", lossless), 5)
+            .unwrap());
+    }
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_lossless_webp_skips_color_preprocessing_by_default() {
+    use rusty_pic_core::compression::OptimizeOptions;
+
+    // 超过预处理的 10 万像素阈值
+    let png_data = create_photo_png(400, 300);
+    let source = image::load_from_memory(&png_data).unwrap().to_rgb8();
+
+    let engine = CompressionEngine::new();
+    let mut options = CompressionOptions {
+        format: Some("webp".to_string()),
+        quality: None,
+        optimize: Some(OptimizeOptions {
+            lossless: true,
+            ..Default::default()
+        }),
+        allow_upscale: false,
+        ..Default::default()
+    };
+
+    let result = engine.compress(&png_data, &options).unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgb8();
+    assert_eq!(decoded.as_raw(), source.as_raw());
+
+    // 开启后 YUV 往返的舍入误差会改变像素
+    options.preprocess_color = true;
+    let result = engine.compress(&png_data, &options).unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap().to_rgb8();
+    assert_ne!(decoded.as_raw(), source.as_raw());
+}
+
+/// Semi-transparent gradient with a noisy colour layer
+#[cfg(any(feature = "webp", feature = "avif"))]
+fn create_translucent_png(width: u32, height: u32) -> Vec<u8> {
+    let img = image::RgbaImage::from_fn(width, height, |x, y| {
+        let noise = ((x * 7919 + y * 104_729) % 61) as u8;
+        image::Rgba([
+            noise.wrapping_mul(4),
+            (x * 255 / width) as u8,
+            (y * 255 / height) as u8,
+            ((x + y) * 255 / (width + height)) as u8,
+        ])
+    });
+    encode_png(&image::DynamicImage::ImageRgba8(img))
+}
+
+#[cfg(any(feature = "webp", feature = "avif"))]
+fn alpha_quality_options(format: &str, alpha_quality: u8) -> CompressionOptions {
+    CompressionOptions {
+        format: Some(format.to_string()),
+        quality: Some(10),
+        optimize: Some(rusty_pic_core::compression::OptimizeOptions {
+            alpha_quality: Some(alpha_quality),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_webp_alpha_quality_keeps_alpha_at_low_colour_quality() {
+    let png_data = create_translucent_png(64, 64);
+    let original = image::load_from_memory(&png_data).unwrap().to_rgba8();
+    let engine = CompressionEngine::new();
+
+    let max_alpha_error = |alpha_quality| {
+        let result = engine
+            .compress(&png_data, &alpha_quality_options("webp", alpha_quality))
+            .unwrap();
+        let decoded = image::load_from_memory(&result.data).unwrap().to_rgba8();
+        original
+            .pixels()
+            .zip(decoded.pixels())
+            .map(|(a, b)| a[3].abs_diff(b[3]))
+            .max()
+            .unwrap()
+    };
+
+    assert!(max_alpha_error(100) <= 1);
+    // 低 alpha 质量会量化 alpha，证明该选项确实传到了编码器
+    assert!(max_alpha_error(0) > max_alpha_error(100));
+}
+
+#[cfg(feature = "avif")]
+#[test]
+fn test_avif_alpha_quality_is_applied() {
+    let png_data = create_translucent_png(64, 64);
+    let engine = CompressionEngine::new();
+
+    let size = |alpha_quality| {
+        engine
+            .compress(&png_data, &alpha_quality_options("avif", alpha_quality))
+            .unwrap()
+            .compressed_size
+    };
+    assert!(size(100) > size(10));
+}
+
+#[test]
+fn test_derive_quant_tables_follow_frequency_content() {
+    use rusty_pic_core::formats::jpeg::{derive_quant_tables, standard_quant_tables};
+
+    // 1 像素棋盘叠加噪声：能量集中在最高频系数
+    let img = image::RgbImage::from_fn(64, 64, |x, y| {
+        let v = if (x + y) % 2 == 0 { 40 } else { 215 };
+        let n = ((x * 31 + y * 17) % 13) as u8;
+        image::Rgb([v + n, v, v - n])
+    });
+    let img = image::DynamicImage::ImageRgb8(img);
+
+    let (std_luma, _) = standard_quant_tables(75);
+    let (luma, chroma) = derive_quant_tables(&img, 75);
+    assert_ne!(luma, std_luma);
+    assert_eq!(luma[0], std_luma[0]);
+    // 最高频 (7,7) 系数能量远超标准表的假设，步长应更细
+    assert!(luma[63] < std_luma[63]);
+    assert!(luma
+        .iter()
+        .chain(chroma.iter())
+        .all(|&q| (1..=255).contains(&q)));
+
+    #[cfg(feature = "jpeg")]
+    {
+        use rusty_pic_core::formats::jpeg::{encode, JpegOptions};
+
+        let jpeg = encode(
+            &img,
+            &JpegOptions {
+                quality: 75,
+                adaptive_quantization: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(decoded.dimensions(), (64, 64));
+    }
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_progressive_jpeg_preview_has_less_detail() {
+    use rusty_pic_core::formats::jpeg::{encode, JpegOptions};
+
+    let png_data = create_photo_png(128, 96);
+    let progressive = encode(
+        &image::load_from_memory(&png_data).unwrap(),
+        &JpegOptions {
+            quality: 90,
+            progressive: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let analyzer = ImageAnalyzer::new();
+    let preview = analyzer.decode_preview(&progressive, 1).unwrap();
+    let full = analyzer.decode_preview(&progressive, u32::MAX).unwrap();
+    assert_eq!(preview.dimensions(), (128, 96));
+    assert_eq!(full.dimensions(), (128, 96));
+
+    // 相邻像素差之和衡量细节；只有首个扫描时应明显更少
+    let detail = |img: &image::DynamicImage| {
+        let luma = img.to_luma8();
+        luma.rows()
+            .map(|row| {
+                let row: Vec<u8> = row.map(|p| p[0]).collect();
+                row.windows(2)
+                    .map(|w| w[0].abs_diff(w[1]) as u64)
+                    .sum::<u64>()
+            })
+            .sum::<u64>()
+    };
+    assert!(detail(&preview) * 2 < detail(&full));
+    assert_eq!(
+        full.to_rgb8(),
+        image::load_from_memory(&progressive).unwrap().to_rgb8()
+    );
+}
+
+#[test]
+fn test_allow_upscale_false_never_enlarges() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    let png_data = create_photo_png(1000, 200);
+    let engine = CompressionEngine::new();
+    let options_for = |allow_upscale| CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        resize: Some(ResizeOptions {
+            width: Some(2000),
+            fit: "inside".to_string(),
+            ..Default::default()
+        }),
+        allow_upscale,
+        ..Default::default()
+    };
+
+    let capped = engine.compress(&png_data, &options_for(false)).unwrap();
+    let decoded = image::load_from_memory(&capped.data).unwrap();
+    assert_eq!(decoded.dimensions(), (1000, 200));
+
+    // 默认行为保持不变：允许放大
+    let enlarged = engine.compress(&png_data, &options_for(true)).unwrap();
+    let decoded = image::load_from_memory(&enlarged.data).unwrap();
+    assert_eq!(decoded.dimensions(), (2000, 400));
+}
+
+#[test]
+fn test_fill_resize_reports_upscaled() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    let png_data = create_photo_png(200, 100);
+    let engine = CompressionEngine::new();
+    let options_for = |width, height| CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        resize: Some(ResizeOptions {
+            width: Some(width),
+            height: Some(height),
+            fit: "fill".to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+
+    // 只在一个方向上超出源尺寸也算放大
+    let enlarged = engine.compress(&png_data, &options_for(100, 150)).unwrap();
+    assert_eq!((enlarged.output_width, enlarged.output_height), (100, 150));
+    assert!(enlarged.upscaled);
+
+    let shrunk = engine.compress(&png_data, &options_for(100, 50)).unwrap();
+    assert_eq!((shrunk.output_width, shrunk.output_height), (100, 50));
+    assert!(!shrunk.upscaled);
+}
+
+#[test]
+fn test_sharpen_restores_edge_contrast_after_downscale() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    // 8px 黑白棋盘，缩小到 3/8 后边缘变软
+    let img = image::RgbImage::from_fn(128, 128, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 {
+            image::Rgb([20, 20, 20])
+        } else {
+            image::Rgb([235, 235, 235])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let engine = CompressionEngine::new();
+    let downscaled = |sharpen| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    resize: Some(ResizeOptions {
+                        width: Some(48),
+                        fit: "inside".to_string(),
+                        ..Default::default()
+                    }),
+                    sharpen,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().to_luma8()
+    };
+    // 相邻像素差的最大值与总和衡量边缘对比度
+    let edge_contrast = |img: &image::GrayImage| {
+        img.rows()
+            .flat_map(|row| {
+                let row: Vec<u8> = row.map(|p| p[0]).collect();
+                row.windows(2)
+                    .map(|w| w[0].abs_diff(w[1]) as u64)
+                    .collect::<Vec<_>>()
+            })
+            .sum::<u64>()
+    };
+
+    let soft = downscaled(None);
+    let sharp = downscaled(Some(1.0));
+    assert_eq!(soft.dimensions(), sharp.dimensions());
+    assert!(edge_contrast(&sharp) > edge_contrast(&soft));
+}
+
+#[test]
+fn test_recommended_options_match_analysis() {
+    let img = image::RgbImage::from_fn(64, 64, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 4) as u8, ((x ^ y) * 4) as u8])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let analyzer = ImageAnalyzer::new();
+    let analysis = analyzer.analyze(&png_data).unwrap();
+    let options = analyzer.recommended_options(&png_data).unwrap();
+    assert_eq!(
+        options.format.as_deref(),
+        Some(analysis.recommended_format.as_str())
+    );
+    assert_eq!(options.quality, Some(analysis.recommended_quality));
+}
+
+#[test]
+fn test_assume_srgb_writes_compact_srgb_chunk() {
+    let img = image::RgbImage::from_fn(32, 32, |x, y| {
+        image::Rgb([(x * 8) as u8, (y * 8) as u8, 90])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let engine = CompressionEngine::new();
+    let compress = |assume_srgb| {
+        engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: Some(80),
+                    assume_srgb,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .data
+    };
+
+    let plain = compress(false);
+    let tagged = compress(true);
+    let chunk_types = |png: &[u8]| {
+        rusty_pic_core::icc::png_chunks(png)
+            .map(|(kind, _)| kind)
+            .collect::<Vec<_>>()
+    };
+    let types = chunk_types(&tagged);
+    assert_eq!(&types[..2], &[*b"IHDR", *b"sRGB"]);
+    assert!(!types.contains(b"iCCP"));
+    assert!(!chunk_types(&plain).contains(b"sRGB"));
+    // sRGB 块只有 13 字节
+    assert_eq!(tagged.len(), plain.len() + 13);
+    assert_eq!(
+        image::load_from_memory(&tagged).unwrap().to_rgb8(),
+        image::load_from_memory(&plain).unwrap().to_rgb8()
+    );
+}
+
+#[test]
+fn test_outside_fit_crop_to_box_yields_exact_dimensions() {
+    // 宽 300 高 120，中间三分之一为绿色
+    let img = image::RgbImage::from_fn(300, 120, |x, _| match x / 100 {
+        0 => image::Rgb([255, 0, 0]),
+        1 => image::Rgb([0, 255, 0]),
+        _ => image::Rgb([0, 0, 255]),
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let engine = CompressionEngine::new();
+    let resized = |crop_to_box| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    resize: Some(rusty_pic_core::compression::ResizeOptions {
+                        width: Some(100),
+                        height: Some(100),
+                        fit: "outside".to_string(),
+                        crop_to_box,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().to_rgb8()
+    };
+
+    // 不裁剪时按较大比例覆盖目标框
+    assert_eq!(resized(false).dimensions(), (250, 100));
+
+    let cropped = resized(true);
+    assert_eq!(cropped.dimensions(), (100, 100));
+    // 居中裁剪保留中间的绿色区域
+    let center = cropped.get_pixel(50, 50);
+    assert!(center[1] > 200 && center[0] < 50 && center[2] < 50);
+}
+
+#[test]
+fn test_ratio_vs_raw_for_simple_png() {
+    let img = image::RgbImage::from_fn(128, 128, |x, _| {
+        if x < 64 {
+            image::Rgb([20, 40, 200])
+        } else {
+            image::Rgb([240, 240, 240])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+
+    let result = CompressionEngine::new()
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let raw_size = 128 * 128 * 3;
+    assert!((result.ratio_vs_raw - result.compressed_size as f32 / raw_size as f32).abs() < 1e-6);
+    assert!(result.ratio_vs_raw < 0.1);
+}
+
+#[test]
+fn test_malformed_inputs_fail_gracefully() {
+    let png_data = encode_png(&image::DynamicImage::ImageRgb8(image::RgbImage::new(
+        16, 16,
+    )));
+
+    // 截断的 PNG、篡改 IHDR 尺寸的 PNG、只有 SOI 的 JPEG、GIF 头后跟垃圾数据
+    let truncated = png_data[..png_data.len() / 2].to_vec();
+    let mut huge_ihdr = png_data.clone();
+    huge_ihdr[16..24].copy_from_slice(&[0x7F, 0xFF, 0xFF, 0xFF, 0x7F, 0xFF, 0xFF, 0xFF]);
+    let inputs: Vec<Vec<u8>> = vec![
+        truncated,
+        huge_ihdr,
+        vec![0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x02],
+        b"GIF89a\x10\x00\x10\x00\xff\xff\xff".to_vec(),
+    ];
+
+    let analyzer = ImageAnalyzer::new();
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    for data in &inputs {
+        assert!(ImageAnalyzer::safe_load(data).is_err());
+        assert!(analyzer.analyze(data).is_err());
+        assert!(engine.compress(data, &options).is_err());
+    }
+}
+
+#[test]
+fn test_nearest_integer_upscale_replicates_pixels() {
+    use image::imageops::FilterType;
+    use rusty_pic_core::compression::ResizeOptions;
+
+    // 4x4 图像，每个像素颜色各不相同
+    let pixels: Vec<u8> = (0..16u8)
+        .flat_map(|i| [i * 16, 255 - i * 16, (i % 4) * 60])
+        .collect();
+    let result = CompressionEngine::new()
+        .compress_raw(
+            &pixels,
+            4,
+            4,
+            3,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                resize: Some(ResizeOptions {
+                    width: Some(8),
+                    height: Some(8),
+                    fit: "fill".to_string(),
+                    upscale_filter: FilterType::Nearest,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let scaled = image::load_from_memory(&result.data).unwrap().to_rgb8();
+    assert_eq!(scaled.dimensions(), (8, 8));
+    for (x, y, px) in scaled.enumerate_pixels() {
+        let i = ((y / 2) * 4 + x / 2) as usize * 3;
+        assert_eq!(
+            px.0,
+            [pixels[i], pixels[i + 1], pixels[i + 2]],
+            "({x}, {y})"
+        );
+    }
+}
+
+#[test]
+fn test_register_custom_encoder() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::FormatEncoder;
+
+    // 直接输出 RGB 像素的最简编码器
+    struct RawEncoder;
+    impl FormatEncoder for RawEncoder {
+        fn name(&self) -> &str {
+            "raw"
+        }
+
+        fn encode(
+            &self,
+            img: &image::DynamicImage,
+            _quality: u8,
+            _opts: &OptimizeOptions,
+        ) -> rusty_pic_core::Result<Vec<u8>> {
+            Ok(img.to_rgb8().into_raw())
+        }
+    }
+
+    let pixels: Vec<u8> = (0..6 * 4 * 3).map(|i| i as u8).collect();
+    let mut options = CompressionOptions {
+        format: Some("raw".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    let mut engine = CompressionEngine::new();
+    assert!(engine.compress_raw(&pixels, 6, 4, 3, &options).is_err());
+
+    engine.register_encoder(Box::new(RawEncoder));
+    let result = engine.compress_raw(&pixels, 6, 4, 3, &options).unwrap();
+    assert_eq!(result.format, "raw");
+    assert_eq!(result.data, pixels);
+
+    // 内置格式仍然可用
+    options.format = Some("png".to_string());
+    let png = engine.compress_raw(&pixels, 6, 4, 3, &options).unwrap();
+    assert_eq!(
+        image::load_from_memory(&png.data)
+            .unwrap()
+            .to_rgb8()
+            .into_raw(),
+        pixels
+    );
+}
+
+#[test]
+fn test_compress_returning_image_matches_output() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    let png_data = create_photo_png(96, 64);
+    let (result, image) = CompressionEngine::new()
+        .compress_returning_image(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                resize: Some(ResizeOptions {
+                    width: Some(48),
+                    fit: "inside".to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    assert_eq!(
+        image.dimensions(),
+        (result.output_width, result.output_height)
+    );
+    assert_eq!(image.dimensions(), (48, 32));
+    // 返回的图像即编码内容（PNG 无损）
+    assert_eq!(
+        image::load_from_memory(&result.data).unwrap().to_rgba8(),
+        image.to_rgba8()
+    );
+}
+
+#[test]
+fn test_elide_similar_frames_merges_duplicates() {
+    use image::codecs::gif::GifEncoder;
+    use image::codecs::png::PngDecoder;
+    use image::{AnimationDecoder, Delay, Frame, RgbaImage};
+
+    // 第二帧与第一帧相同，第三帧不同
+    let mut gif_data = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif_data);
+        for shade in [40u8, 40, 220] {
+            let img = RgbaImage::from_fn(32, 32, |x, y| {
+                image::Rgba([shade, (x * 8) as u8, (y * 8) as u8, 255])
+            });
+            let frame = Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1));
+            encoder.encode_frame(frame).unwrap();
+        }
+    }
+
+    let engine = CompressionEngine::new();
+    let frames_of = |elide_similar_frames| {
+        let result = engine
+            .compress(
+                &gif_data,
+                &CompressionOptions {
+                    format: Some("apng".to_string()),
+                    quality: None,
+                    elide_similar_frames,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        PngDecoder::new(std::io::Cursor::new(result.data))
+            .unwrap()
+            .apng()
+            .into_frames()
+            .collect_frames()
+            .unwrap()
+    };
+
+    assert_eq!(frames_of(None).len(), 3);
+
+    let elided = frames_of(Some(0.99));
+    assert_eq!(elided.len(), 2);
+    let delays: Vec<std::time::Duration> =
+        elided.iter().map(|frame| frame.delay().into()).collect();
+    assert_eq!(
+        delays,
+        [
+            std::time::Duration::from_millis(200),
+            std::time::Duration::from_millis(100)
+        ]
+    );
+}
+
+#[test]
+fn test_sharpen_keeps_color_type() {
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        sharpen: Some(1.0),
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let gray: Vec<u8> = (0..32 * 24).map(|i| (i * 37 % 251) as u8).collect();
+    let result = engine.compress_raw(&gray, 32, 24, 1, &options).unwrap();
+    assert_eq!(
+        image::load_from_memory(&result.data).unwrap().color(),
+        image::ColorType::L8
+    );
+    let rgb: Vec<u8> = (0..32 * 24 * 3).map(|i| (i * 37 % 251) as u8).collect();
+    let result = engine.compress_raw(&rgb, 32, 24, 3, &options).unwrap();
+    assert_eq!(
+        image::load_from_memory(&result.data).unwrap().color(),
+        image::ColorType::Rgb8
+    );
+
+    let img = image::ImageBuffer::<image::Rgb<u16>, _>::from_fn(32, 24, |x, y| {
+        image::Rgb([(x * 2003 + 7) as u16, (y * 2657) as u16, 30_001])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgb16(img));
+    let result = engine.compress(&png_data, &options).unwrap();
+    assert_eq!(
+        image::load_from_memory(&result.data).unwrap().color(),
+        image::ColorType::Rgb16
+    );
+}
+
+#[test]
+fn test_equalize_widens_tonal_range() {
+    // 低对比度渐变：灰阶只覆盖 100-150
+    let pixels: Vec<u8> = (0..64 * 32)
+        .flat_map(|i| {
+            let v = 100 + ((i % 64) * 50 / 63) as u8;
+            [v, v, v.saturating_sub(10)]
+        })
+        .collect();
+    let equalized = |equalize| {
+        let result = CompressionEngine::new()
+            .compress_raw(
+                &pixels,
+                64,
+                32,
+                3,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    equalize,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let luma = image::load_from_memory(&result.data).unwrap().to_luma8();
+        let min = *luma.as_raw().iter().min().unwrap();
+        let max = *luma.as_raw().iter().max().unwrap();
+        (min, max)
+    };
+
+    let (min, max) = equalized(false);
+    assert!(min >= 90 && max <= 155);
+    let (min, max) = equalized(true);
+    assert!(min < 20, "min {min}");
+    assert!(max > 235, "max {max}");
+}
+
+#[test]
+fn test_equalize_keeps_color_type() {
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        equalize: true,
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    // 灰阶只覆盖 100-150 的灰度扫描件
+    let gray: Vec<u8> = (0..64 * 32)
+        .map(|i| 100 + (i % 64 * 50 / 63) as u8)
+        .collect();
+    let result = engine.compress_raw(&gray, 64, 32, 1, &options).unwrap();
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert_eq!(decoded.color(), image::ColorType::L8);
+    assert!(decoded.to_luma8().as_raw().iter().any(|&v| v < 20));
+
+    let img = image::ImageBuffer::<image::Luma<u16>, _>::from_fn(64, 32, |x, _| {
+        image::Luma([25_000 + x as u16 * 97])
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageLuma16(img));
+    let result = engine.compress(&png_data, &options).unwrap();
+    match image::load_from_memory(&result.data).unwrap() {
+        image::DynamicImage::ImageLuma16(decoded) => {
+            let (min, max) = decoded
+                .as_raw()
+                .iter()
+                .fold((u16::MAX, 0), |(lo, hi), &v| (lo.min(v), hi.max(v)));
+            assert!(min < 5_000 && max > 60_000, "range {min}-{max}");
+        }
+        other => panic!("expected 16-bit grayscale, got {:?}", other.color()),
+    }
+}
+
+#[cfg(feature = "gif")]
+#[test]
+fn test_gif_shared_palette_uses_single_global_table() {
+    let encode = |colors: [[u8; 3]; 4]| {
+        let img = image::RgbImage::from_fn(32, 32, |x, y| {
+            image::Rgb(colors[((y / 16) * 2 + x / 16) as usize])
+        });
+        encode_png(&image::DynamicImage::ImageRgb8(img))
+    };
+    // 两张图共享红、绿两色，合计 6 种颜色
+    let first = encode([[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 0]]);
+    let second = encode([[255, 0, 0], [0, 255, 0], [0, 255, 255], [255, 0, 255]]);
+
+    let engine = CompressionEngine::new();
+    let max_colors = 8u8;
+    let gif_data = engine
+        .compress_gif_shared_palette(vec![&first, &second], max_colors)
+        .unwrap();
+
+    // 逻辑屏幕描述符：第 10 字节最高位为全局颜色表标志，低 3 位为表大小
+    assert_eq!(&gif_data[..6], b"GIF89a");
+    let flags = gif_data[10];
+    assert!(flags & 0x80 != 0, "missing global color table");
+    let table_len = 2usize << (flags & 0x07);
+    assert!(
+        table_len <= max_colors as usize,
+        "table has {table_len} entries"
+    );
+
+    let mut decoder = gif::DecodeOptions::new()
+        .read_info(std::io::Cursor::new(&gif_data))
+        .unwrap();
+    let mut frames = 0;
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert!(frame.palette.is_none(), "frame carries a local color table");
+        frames += 1;
+    }
+    assert_eq!(frames, 2);
+
+    // 颜色数不超过上限时调色板精确，共享颜色在两帧中保持一致
+    let decoded = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(&gif_data)).unwrap();
+    use image::AnimationDecoder;
+    let frames: Vec<_> = decoded.into_frames().collect_frames().unwrap();
+    assert_eq!(frames[0].buffer().get_pixel(0, 0).0, [255, 0, 0, 255]);
+    assert_eq!(frames[1].buffer().get_pixel(16, 0).0, [0, 255, 0, 255]);
+    assert_eq!(frames[1].buffer().get_pixel(16, 16).0, [255, 0, 255, 255]);
+}
+
+#[test]
+fn test_format_capabilities() {
+    let engine = CompressionEngine::new();
+
+    let png = engine.format_capabilities("png").unwrap();
+    assert!(png.supports_alpha);
+    assert!(png.supports_lossless);
+    assert!(!png.supports_animation);
+    assert_eq!(png.max_bit_depth, 16);
+
+    assert!(engine.format_capabilities("bmp").is_none());
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_jpeg_capabilities_exclude_alpha_and_animation() {
+    let engine = CompressionEngine::new();
+    let jpeg = engine.format_capabilities("jpg").unwrap();
+    assert!(!jpeg.supports_alpha);
+    assert!(!jpeg.supports_animation);
+    assert_eq!(jpeg.max_bit_depth, 8);
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_webp_capabilities() {
+    let engine = CompressionEngine::new();
+    let webp = engine.format_capabilities("webp").unwrap();
+    assert!(webp.supports_alpha);
+    assert!(webp.supports_animation);
+    assert!(webp.supports_lossless);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn test_capabilities_match_enabled_features() {
+    let engine = CompressionEngine::new();
+    let supported = CompressionEngine::supported_formats();
+    let png_data = create_photo_png(16, 16);
+
+    for (format, enabled) in [
+        ("png", cfg!(feature = "png")),
+        ("apng", cfg!(feature = "png")),
+        ("jpeg", cfg!(feature = "jpeg")),
+        ("webp", cfg!(feature = "webp")),
+        ("tiff", cfg!(feature = "tiff")),
+        ("avif", cfg!(feature = "avif")),
+    ] {
+        assert_eq!(supported.contains(&format.to_string()), enabled, "{format}");
+        assert_eq!(
+            engine.format_capabilities(format).is_some(),
+            enabled,
+            "{format}"
+        );
+
+        if !enabled {
+            let options = CompressionOptions {
+                format: Some(format.to_string()),
+                quality: Some(80),
+                ..Default::default()
+            };
+            match engine.compress(&png_data, &options) {
+                Err(rusty_pic_core::CompressionError::UnsupportedFeature(message)) => {
+                    assert!(message.contains("feature"), "{message}")
+                }
+                other => panic!("expected UnsupportedFeature for {format}, got {other:?}"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_snap_even_rounds_target_dimensions_down() {
+    let png_data = create_photo_png(160, 120);
+    let engine = CompressionEngine::new();
+    let output_size = |format: &str, snap_even| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some(format.to_string()),
+                    quality: Some(80),
+                    resize: Some(rusty_pic_core::compression::ResizeOptions {
+                        width: Some(801),
+                        height: Some(601),
+                        fit: "fill".to_string(),
+                        snap_even,
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().dimensions()
+    };
+
+    assert_eq!(output_size("png", false), (801, 601));
+    assert_eq!(output_size("png", true), (800, 600));
+    // 4:2:0 JPEG 自动对齐到偶数尺寸
+    #[cfg(feature = "jpeg")]
+    assert_eq!(output_size("jpeg", false), (800, 600));
+}
+
+#[test]
+fn test_force_color_type_rgb_drops_alpha() {
+    // 左半透明、右半不透明红色
+    let img = image::RgbaImage::from_fn(32, 32, |x, _| {
+        if x < 16 {
+            image::Rgba([0, 0, 0, 0])
+        } else {
+            image::Rgba([200, 0, 0, 255])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageRgba8(img));
+
+    let result = CompressionEngine::new()
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                force_color_type: Some("rgb".to_string()),
+                background: Some([0, 0, 255]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    assert!(!decoded.color().has_alpha(), "got {:?}", decoded.color());
+    let rgb = decoded.to_rgb8();
+    // 透明区域按背景色填充
+    assert_eq!(rgb.get_pixel(0, 0).0, [0, 0, 255]);
+    assert_eq!(rgb.get_pixel(31, 0).0, [200, 0, 0]);
+}
+
+#[test]
+fn test_result_reports_tiled_resize() {
+    let engine = CompressionEngine::new();
+    let compress = |size: u32| {
+        let img = image::RgbImage::from_fn(size, size, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, 128])
+        });
+        let png_data = encode_png(&image::DynamicImage::ImageRgb8(img));
+        engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("png".to_string()),
+                    quality: None,
+                    resize: Some(rusty_pic_core::compression::ResizeOptions {
+                        width: Some(size / 10),
+                        height: Some(size / 10),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+    };
+
+    // 3000x3000 超过 4MP，走分块并行缩放
+    let large = compress(3000);
+    assert!(large.used_tiled_resize);
+    assert!(large.tile_count >= 8, "tile count {}", large.tile_count);
+    assert_eq!((large.output_width, large.output_height), (300, 300));
+
+    let small = compress(200);
+    assert!(!small.used_tiled_resize);
+    assert_eq!(small.tile_count, 0);
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_extra_params_reach_webp_encoder() {
+    let png_data = create_photo_png(256, 256);
+    let engine = CompressionEngine::new();
+    let compress = |params: &[(&str, &str)]| {
+        engine.compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("webp".to_string()),
+                quality: Some(75),
+                extra_params: params
+                    .iter()
+                    .map(|&(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                ..Default::default()
+            },
+        )
+    };
+
+    let plain = compress(&[]).unwrap();
+    let unfiltered = compress(&[("filter_strength", "0"), ("sns_strength", "0")]).unwrap();
+    assert_ne!(unfiltered.compressed_size, plain.compressed_size);
+
+    // 未识别的键被忽略，输出与不带参数时一致
+    let unknown = compress(&[("no_such_knob", "1")]).unwrap();
+    assert_eq!(unknown.data, plain.data);
+
+    assert!(compress(&[("filter_strength", "strong")]).is_err());
+}
+
+#[test]
+fn test_compress_ico_packs_requested_sizes() {
+    let png_data = create_photo_png(120, 90);
+    let engine = CompressionEngine::new();
+    let ico = engine.compress_ico(&png_data, &[16, 32, 48]).unwrap();
+
+    // ICONDIR：保留字 0、类型 1（图标）、图像数
+    assert_eq!(&ico[..4], &[0, 0, 1, 0]);
+    assert_eq!(u16::from_le_bytes([ico[4], ico[5]]), 3);
+    for (i, size) in [16u32, 32, 48].into_iter().enumerate() {
+        let entry = &ico[6 + 16 * i..6 + 16 * (i + 1)];
+        assert_eq!((entry[0] as u32, entry[1] as u32), (size, size));
+        let len = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as usize;
+        let offset = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as usize;
+        let icon = image::load_from_memory(&ico[offset..offset + len]).unwrap();
+        assert_eq!(icon.dimensions(), (size, size));
+    }
+
+    assert!(engine.compress_ico(&png_data, &[16, 257]).is_err());
+    assert!(engine.compress_ico(&png_data, &[]).is_err());
+}
+
+/// Names and data of the entries in a ZIP archive, read from its central directory
+fn zip_entries(zip: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let u16_at = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes(zip[at..at + 4].try_into().unwrap()) as usize;
+
+    // 中央目录结束记录位于末尾 22 字节
+    let eocd = zip.len() - 22;
+    assert_eq!(u32_at(eocd), 0x0605_4b50);
+
+    let mut at = u32_at(eocd + 16);
+    let mut entries = Vec::new();
+    for _ in 0..u16_at(eocd + 10) {
+        assert_eq!(u32_at(at), 0x0201_4b50);
+        // 方法 0 = stored
+        assert_eq!(u16_at(at + 10), 0);
+        let name_len = u16_at(at + 28);
+        let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+
+        let local = u32_at(at + 42);
+        let size = u32_at(local + 18);
+        let data_start = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+        entries.push((name, zip[data_start..data_start + size].to_vec()));
+
+        at += 46 + name_len + u16_at(at + 30) + u16_at(at + 32);
+    }
+    entries
+}
+
+#[test]
+fn test_compress_to_zip_lists_renamed_entries() {
+    let hero = create_photo_png(64, 48);
+    let logo = create_photo_png(32, 32);
+    let options = CompressionOptions {
+        format: Some("tiff".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+
+    let zip = CompressionEngine::new()
+        .compress_to_zip(
+            vec![
+                ("hero.png".to_string(), hero.as_slice()),
+                ("icons/logo.png".to_string(), logo.as_slice()),
+            ],
+            &options,
+        )
+        .unwrap();
+
+    let entries: Vec<_> = zip_entries(&zip)
+        .into_iter()
+        .map(|(name, data)| (name, image::load_from_memory(&data).unwrap().dimensions()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            ("hero.tiff".to_string(), (64, 48)),
+            ("icons/logo.tiff".to_string(), (32, 32)),
+        ]
+    );
+}
+
+#[test]
+fn test_compress_to_zip_normalises_entry_names() {
+    let png_data = create_photo_png(16, 16);
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+
+    let zip = engine
+        .compress_to_zip(
+            vec![
+                ("/srv/uploads/a.png".to_string(), png_data.as_slice()),
+                ("C:\\photos\\b.png".to_string(), png_data.as_slice()),
+                ("./nested//c.png".to_string(), png_data.as_slice()),
+            ],
+            &options,
+        )
+        .unwrap();
+    let names: Vec<String> = zip_entries(&zip)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, ["srv/uploads/a.png", "photos/b.png", "nested/c.png"]);
+
+    for name in ["../escape.png", "photos/../../escape.png", "/"] {
+        assert!(
+            engine
+                .compress_to_zip(vec![(name.to_string(), png_data.as_slice())], &options)
+                .is_err(),
+            "{name} was accepted"
+        );
+    }
+}
+
+#[test]
+fn test_timings_add_up_to_processing_time() {
+    let png_data = create_photo_png(1600, 1200);
+    let engine = CompressionEngine::new();
+    let result = engine
+        .compress(
+            &png_data,
+            &CompressionOptions {
+                format: Some("png".to_string()),
+                quality: None,
+                resize: Some(rusty_pic_core::compression::ResizeOptions {
+                    width: Some(400),
+                    height: None,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+    let timings = result.timings;
+    let sum = timings.decode_ms + timings.analyze_ms + timings.resize_ms + timings.encode_ms;
+    assert!(
+        sum.abs_diff(result.processing_time) <= 1,
+        "{timings:?} sums to {sum}ms, total {}ms",
+        result.processing_time
+    );
+    assert_eq!(timings.total_ms(), sum);
+    // 解码 1600x1200 PNG 不可能瞬间完成
+    assert!(result.processing_time > 0);
+}
+
+#[test]
+fn test_verify_output() {
+    use rusty_pic_core::compression::OptimizeOptions;
+    use rusty_pic_core::FormatEncoder;
+
+    // 输出被截断的 PNG 编码器
+    struct TruncatedPng;
+    impl FormatEncoder for TruncatedPng {
+        fn name(&self) -> &str {
+            "png"
+        }
+
+        fn encode(
+            &self,
+            img: &image::DynamicImage,
+            _quality: u8,
+            _opts: &OptimizeOptions,
+        ) -> rusty_pic_core::Result<Vec<u8>> {
+            let mut out = encode_png(img);
+            out.truncate(out.len() / 2);
+            Ok(out)
+        }
+    }
+
+    let png_data = create_photo_png(64, 48);
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        verify_output: true,
+        ..Default::default()
+    };
+
+    let mut engine = CompressionEngine::new();
+    let result = engine.compress(&png_data, &options).unwrap();
+    assert_eq!((result.output_width, result.output_height), (64, 48));
+
+    engine.register_encoder(Box::new(TruncatedPng));
+    let err = engine.compress(&png_data, &options).unwrap_err();
+    assert!(
+        matches!(err, rusty_pic_core::CompressionError::EncodingError(_)),
+        "got {err:?}"
+    );
+}
+
+#[cfg(feature = "jpeg")]
+#[test]
+fn test_grayscale_source_yields_single_component_jpeg() {
+    // SOFn 段：FF Cn 长度(2) 精度(1) 高(2) 宽(2) 分量数(1)
+    let sof_components = |jpeg: &[u8]| {
+        jpeg.windows(10)
+            .find(|w| w[0] == 0xFF && matches!(w[1], 0xC0..=0xC2))
+            .map(|w| w[9])
+            .expect("JPEG has no SOF marker")
+    };
+    let encode = |img: image::DynamicImage| {
+        let png_data = encode_png(&img);
+        CompressionEngine::new()
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some("jpeg".to_string()),
+                    quality: Some(80),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .data
+    };
+
+    let gray = image::GrayImage::from_fn(64, 64, |x, y| image::Luma([((x * 3 + y) % 256) as u8]));
+    let jpeg = encode(image::DynamicImage::ImageLuma8(gray));
+    assert_eq!(sof_components(&jpeg), 1);
+    let decoded = image::load_from_memory(&jpeg).unwrap();
+    assert_eq!(decoded.color(), image::ColorType::L8);
+
+    // 近似灰度的 RGB 源同样写单分量
+    let near_gray = image::RgbImage::from_fn(64, 64, |x, _| {
+        let v = (x * 4) as u8;
+        image::Rgb([v, v.saturating_add(1), v])
+    });
+    assert_eq!(
+        sof_components(&encode(image::DynamicImage::ImageRgb8(near_gray))),
+        1
+    );
+
+    let color = create_photo_png(64, 64);
+    let color = image::load_from_memory(&color).unwrap();
+    assert_eq!(sof_components(&encode(color)), 3);
+}
+
+#[test]
+fn test_compress_tiles_splits_into_grid() {
+    let png_data = create_photo_png(100, 100);
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let engine = CompressionEngine::new();
+    let tiles = engine.compress_tiles(&png_data, 50, &options).unwrap();
+
+    let origins: Vec<(u32, u32)> = tiles.iter().map(|&(x, y, _)| (x, y)).collect();
+    assert_eq!(origins, vec![(0, 0), (50, 0), (0, 50), (50, 50)]);
+
+    let source = image::load_from_memory(&png_data).unwrap().to_rgb8();
+    for (x, y, result) in &tiles {
+        let tile = image::load_from_memory(&result.data).unwrap().to_rgb8();
+        assert_eq!(tile.dimensions(), (50, 50));
+        assert_eq!(tile.get_pixel(0, 0), source.get_pixel(*x, *y));
+    }
+
+    // 边缘分块可以更小
+    let tiles = engine.compress_tiles(&png_data, 64, &options).unwrap();
+    let sizes: Vec<(u32, u32)> = tiles
+        .iter()
+        .map(|(_, _, r)| (r.output_width, r.output_height))
+        .collect();
+    assert_eq!(sizes, vec![(64, 64), (36, 64), (64, 36), (36, 36)]);
+}
+
+#[test]
+fn test_compress_to_area_equalizes_pixel_counts() {
+    let engine = CompressionEngine::new();
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let target = 40_000u64;
+
+    for (width, height) in [(640, 360), (160, 160)] {
+        let result = engine
+            .compress_to_area(&create_photo_png(width, height), target, &options)
+            .unwrap();
+        let pixels = result.output_width as u64 * result.output_height as u64;
+        assert!(
+            pixels.abs_diff(target) * 100 <= target,
+            "{width}x{height} became {}x{}",
+            result.output_width,
+            result.output_height
+        );
+        // 宽高比保持不变
+        let aspect = result.output_width as f32 / result.output_height as f32;
+        assert!((aspect - width as f32 / height as f32).abs() < 0.02);
+    }
+}
+
+#[test]
+fn test_indexed_png_with_trns_keeps_transparency() {
+    // 4x4 调色板图：索引 0 完全透明，索引 1 为不透明红色
+    let indices: Vec<u8> = (0..16).map(|i| (i % 2) as u8).collect();
+    let mut png_data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png_data, 4, 4);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![0u8, 0, 255, 255, 0, 0]);
+        encoder.set_trns(vec![0u8, 255]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&indices).unwrap();
+    }
+
+    let analysis = ImageAnalyzer::new().analyze(&png_data).unwrap();
+    assert!(analysis.has_alpha);
+    assert!(analysis.metadata.has_transparency);
+
+    let engine = CompressionEngine::new();
+    let reencode = |format: &str| {
+        let result = engine
+            .compress(
+                &png_data,
+                &CompressionOptions {
+                    format: Some(format.to_string()),
+                    quality: Some(90),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        image::load_from_memory(&result.data).unwrap().to_rgba8()
+    };
+
+    #[allow(unused_mut)]
+    let mut formats = vec!["png"];
+    #[cfg(feature = "webp")]
+    formats.push("webp");
+    for format in formats {
+        let decoded = reencode(format);
+        for (i, pixel) in decoded.pixels().enumerate() {
+            if i % 2 == 0 {
+                assert!(pixel[3] < 16, "{format}: pixel {i} alpha {}", pixel[3]);
+            } else {
+                assert!(pixel[3] > 240, "{format}: pixel {i} alpha {}", pixel[3]);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_max_output_bytes_is_a_hard_cap() {
+    let png_data = create_photo_png(256, 256);
+    let engine = CompressionEngine::new();
+    let mut options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        ..Default::default()
+    };
+    let uncapped = engine.compress(&png_data, &options).unwrap();
+
+    #[allow(unused_mut)]
+    let mut formats = vec!["png"];
+    #[cfg(feature = "jpeg")]
+    formats.push("jpeg");
+    for format in formats {
+        for cap in [uncapped.compressed_size / 3, 4096, 1024] {
+            options.format = Some(format.to_string());
+            options.max_output_bytes = Some(cap);
+            let result = engine.compress(&png_data, &options).unwrap();
+            assert!(
+                result.compressed_size <= cap,
+                "{format}: {} bytes over the {cap} byte cap",
+                result.compressed_size
+            );
+            assert_eq!(result.compressed_size, result.data.len());
+        }
+    }
+
+    // 连 1x1 都放不下时报错
+    options.max_output_bytes = Some(8);
+    assert!(engine.compress(&png_data, &options).is_err());
+}
+
+#[test]
+fn test_max_output_bytes_applies_to_decoded_paths() {
+    let png_data = create_photo_png(256, 256);
+    let engine = CompressionEngine::new();
+    let cap = 2048;
+    let options = CompressionOptions {
+        format: Some("png".to_string()),
+        quality: None,
+        max_output_bytes: Some(cap),
+        ..Default::default()
+    };
+
+    let pixels = image::load_from_memory(&png_data)
+        .unwrap()
+        .to_rgb8()
+        .into_raw();
+    let raw = engine.compress_raw(&pixels, 256, 256, 3, &options).unwrap();
+    assert!(raw.compressed_size <= cap);
+
+    for (_, _, tile) in engine.compress_tiles(&png_data, 128, &options).unwrap() {
+        assert!(tile.compressed_size <= cap);
+    }
+    for variant in engine
+        .compress_responsive(&png_data, &[128, 256], &options)
+        .unwrap()
+    {
+        assert!(variant.compressed_size <= cap);
+    }
+}
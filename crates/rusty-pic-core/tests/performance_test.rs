@@ -1,3 +1,7 @@
+// pub 使各测试二进制未用到的共享夹具不触发 dead_code
+pub mod common;
+
+use common::encode_png;
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb, Rgba};
 use rusty_pic_core::{
     performance::{
@@ -295,22 +299,18 @@ fn test_zero_copy_transfer() {
 #[test]
 fn test_compression_engine_with_optimizations() {
     let img = create_test_image(512, 512, 3);
-    let mut test_data = Vec::new();
-    img.write_to(
-        &mut std::io::Cursor::new(&mut test_data),
-        image::ImageFormat::Png,
-    )
-    .unwrap();
+    let test_data = encode_png(&img);
 
     let options = CompressionOptions {
         format: Some("png".to_string()),
         quality: Some(80),
-        resize: None,
         optimize: Some(rusty_pic_core::compression::OptimizeOptions {
             colors: true,
             progressive: true,
             lossless: false,
+            ..Default::default()
         }),
+        ..Default::default()
     };
 
     // Test single compression
@@ -350,13 +350,7 @@ fn test_compression_engine_with_optimizations() {
 fn test_memory_usage_optimization() {
     // Test that large image processing doesn't cause memory spikes
     let large_img = create_test_image(2048, 2048, 3);
-    let mut test_data = Vec::new();
-    large_img
-        .write_to(
-            &mut std::io::Cursor::new(&mut test_data),
-            image::ImageFormat::Png,
-        )
-        .unwrap();
+    let test_data = encode_png(&large_img);
 
     let options = CompressionOptions {
         format: Some("png".to_string()),
@@ -365,8 +359,9 @@ fn test_memory_usage_optimization() {
             width: Some(1024),
             height: Some(1024),
             fit: "contain".to_string(),
+            ..Default::default()
         }),
-        optimize: None,
+        ..Default::default()
     };
 
     // Create engine with custom memory pool
@@ -400,18 +395,12 @@ fn test_performance_regression() {
 
     for (width, height, channels, format) in test_cases {
         let img = create_test_image(width, height, channels);
-        let mut test_data = Vec::new();
-        img.write_to(
-            &mut std::io::Cursor::new(&mut test_data),
-            image::ImageFormat::Png,
-        )
-        .unwrap();
+        let test_data = encode_png(&img);
 
         let options = CompressionOptions {
             format: Some(format.to_string()),
             quality: Some(80),
-            resize: None,
-            optimize: None,
+            ..Default::default()
         };
 
         let result = engine.compress(&test_data, &options);
@@ -1,10 +1,14 @@
+// pub 使各测试二进制未用到的共享夹具不触发 dead_code
+pub mod common;
+
+use common::{create_photo_png, encode_png};
 use rusty_pic_core::{SmartCompressionConstraints, SmartCompressionEngine};
 
 #[test]
 fn test_smart_compression_engine_creation() {
     let engine = SmartCompressionEngine::new();
     // Test that the engine can be created without panicking
-    assert!(std::ptr::addr_of!(engine) as *const _ != std::ptr::null());
+    assert!(!std::ptr::addr_of!(engine).is_null());
 }
 
 #[test]
@@ -55,6 +59,7 @@ fn test_smart_compression_constraints_with_resize() {
         width: Some(800),
         height: Some(600),
         fit: "contain".to_string(),
+        ..Default::default()
     };
 
     let constraints = SmartCompressionConstraints {
@@ -92,6 +97,8 @@ fn test_advanced_image_analysis_structure() {
 
     let analysis = AdvancedImageAnalysis {
         edge_density: 0.45,
+        luma_complexity: 0.45,
+        chroma_complexity: 0.2,
         texture_complexity: 0.72,
         color_analysis,
         frequency_analysis,
@@ -123,3 +130,181 @@ fn test_parse_target_size_edge_cases() {
     // Test whitespace handling (should fail)
     assert!(engine.parse_target_size(" 100kb ").is_err());
 }
+
+#[test]
+fn test_compress_to_ssim_meets_threshold() {
+    let engine = SmartCompressionEngine::new();
+    let png_data = create_photo_png(128, 128);
+    let original = image::load_from_memory(&png_data).unwrap();
+
+    #[cfg(feature = "webp")]
+    let format = "webp";
+    #[cfg(not(feature = "webp"))]
+    let format = "png";
+
+    let result = engine
+        .compress_to_ssim(&png_data, format, 0.97)
+        .expect("SSIM-targeted compression should succeed");
+    let decoded = image::load_from_memory(&result.data).unwrap();
+    let score = rusty_pic_core::metrics::ssim(&original, &decoded).unwrap();
+
+    assert_eq!(result.format, format);
+    assert!(
+        score >= 0.97,
+        "measured SSIM {score} below requested minimum"
+    );
+}
+
+#[cfg(all(feature = "jpeg", feature = "webp"))]
+#[test]
+fn test_fit_to_size_joint_meets_tight_target() {
+    use rusty_pic_core::{CompressionEngine, CompressionOptions};
+
+    let engine = SmartCompressionEngine::new();
+    let png_data = create_photo_png(256, 256);
+
+    // 目标取全尺寸 JPEG/WebP 最低质量档位中较小输出的一半，需要缩小分辨率才能达到
+    let full_size_floor = ["jpeg", "webp"]
+        .into_iter()
+        .map(|format| {
+            CompressionEngine::new()
+                .compress(
+                    &png_data,
+                    &CompressionOptions {
+                        format: Some(format.to_string()),
+                        quality: Some(40),
+                        ..Default::default()
+                    },
+                )
+                .unwrap()
+                .compressed_size
+        })
+        .min()
+        .unwrap();
+    let target = full_size_floor / 2;
+
+    let constraints = SmartCompressionConstraints {
+        target_size: None,
+        max_width: None,
+        max_height: None,
+        min_quality: None,
+        preferred_formats: Some(vec!["jpeg".to_string()]),
+        resize: None,
+    };
+    let fit = engine
+        .fit_to_size_joint(&png_data, target, &constraints)
+        .expect("a downscaled candidate should fit");
+
+    assert!(fit.result.compressed_size <= target);
+    assert!(fit.scale < 1.0, "chose scale {}", fit.scale);
+    assert!((40..=90).contains(&fit.quality));
+    assert!(fit.ssim > 0.0 && fit.ssim <= 1.0);
+
+    let decoded = image::load_from_memory(&fit.result.data).unwrap();
+    assert_eq!(decoded.width(), (256.0 * fit.scale).round() as u32);
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn test_compress_to_bpp_lands_near_requested_rate() {
+    use rusty_pic_core::compression::ResizeOptions;
+
+    let engine = SmartCompressionEngine::new();
+    let png_data = create_photo_png(512, 384);
+    let constraints = SmartCompressionConstraints {
+        target_size: None,
+        max_width: None,
+        max_height: None,
+        min_quality: None,
+        preferred_formats: Some(vec!["webp".to_string()]),
+        resize: Some(ResizeOptions {
+            width: Some(256),
+            height: None,
+            ..Default::default()
+        }),
+    };
+
+    // 预算按缩放后的尺寸计算
+    let target_bpp = 0.1;
+    let result = engine
+        .compress_to_bpp(&png_data, target_bpp, &constraints)
+        .expect("bpp-targeted compression should succeed");
+    assert_eq!((result.output_width, result.output_height), (256, 192));
+    let actual_bpp = result.compressed_size as f32 * 8.0 / (256.0 * 192.0);
+    assert!(
+        actual_bpp <= target_bpp && actual_bpp >= target_bpp * 0.5,
+        "actual {actual_bpp} bpp too far from requested {target_bpp}"
+    );
+
+    assert!(engine
+        .compress_to_bpp(&png_data, 0.0, &constraints)
+        .is_err());
+}
+
+#[test]
+fn test_small_line_art_stays_lossless() {
+    // 白底上的 1px 黑色网格与对角线：边缘密集、高频，但无损 PNG 很小
+    let img = image::GrayImage::from_fn(160, 120, |x, y| {
+        if x % 12 == 0 || y % 12 == 0 || x == y {
+            image::Luma([0])
+        } else {
+            image::Luma([255])
+        }
+    });
+    let png_data = encode_png(&image::DynamicImage::ImageLuma8(img));
+
+    let constraints = SmartCompressionConstraints {
+        target_size: None,
+        max_width: None,
+        max_height: None,
+        min_quality: None,
+        preferred_formats: None,
+        resize: None,
+    };
+    let result = SmartCompressionEngine::new()
+        .smart_compress(&png_data, &constraints)
+        .expect("Smart compression should succeed");
+
+    assert_eq!(result.format, "png");
+    let decoded = image::load_from_memory(&result.data).unwrap().to_luma8();
+    assert_eq!(decoded.get_pixel(12, 5).0, [0]);
+    assert_eq!(decoded.get_pixel(5, 7).0, [255]);
+}
+
+#[test]
+fn test_smart_compress_with_progress_reports_each_iteration() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let png_data = create_photo_png(96, 64);
+    // 1 字节的目标无法达到，每个质量档位都会被尝试
+    let constraints = SmartCompressionConstraints {
+        target_size: Some("1".to_string()),
+        max_width: None,
+        max_height: None,
+        min_quality: None,
+        preferred_formats: Some(vec!["png".to_string()]),
+        resize: None,
+    };
+
+    let steps = Rc::new(RefCell::new(Vec::new()));
+    let sink = steps.clone();
+    let result = SmartCompressionEngine::new()
+        .smart_compress_with_progress(
+            &png_data,
+            &constraints,
+            Some(Box::new(move |iteration, quality, size| {
+                sink.borrow_mut().push((iteration, quality, size));
+            })),
+        )
+        .expect("best effort result should be returned");
+
+    let steps = steps.borrow();
+    assert!(steps.len() > 1, "only {} progress reports", steps.len());
+    for (index, pair) in steps.windows(2).enumerate() {
+        assert_eq!(pair[0].0 as usize, index + 1);
+        assert_eq!(pair[1].0, pair[0].0 + 1);
+        assert!(pair[1].1 < pair[0].1, "quality did not decrease: {pair:?}");
+    }
+    assert_eq!(steps.last().unwrap().2, result.compressed_size);
+}
@@ -1,4 +1,8 @@
-use rusty_pic_core::{CompressionEngine, CompressionOptions};
+use rusty_pic_core::compression::ResizeOptions;
+use rusty_pic_core::{
+    CompressionEngine, CompressionOptions, CompressionResult, ImageAnalysis,
+    SmartCompressionConstraints, SmartCompressionEngine,
+};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::future_to_promise;
 
@@ -20,6 +24,10 @@ pub fn start() {
 pub struct JsCompressionOptions {
     format: Option<String>,
     quality: Option<u8>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    fit: Option<String>,
+    target_size: Option<String>,
 }
 
 impl Default for JsCompressionOptions {
@@ -35,6 +43,10 @@ impl JsCompressionOptions {
         JsCompressionOptions {
             format: None,
             quality: None,
+            max_width: None,
+            max_height: None,
+            fit: None,
+            target_size: None,
         }
     }
 
@@ -47,6 +59,66 @@ impl JsCompressionOptions {
     pub fn set_quality(&mut self, q: Option<u8>) {
         self.quality = q;
     }
+
+    #[wasm_bindgen(js_name = setMaxWidth)]
+    pub fn set_max_width(&mut self, width: Option<u32>) {
+        self.max_width = width;
+    }
+
+    #[wasm_bindgen(js_name = setMaxHeight)]
+    pub fn set_max_height(&mut self, height: Option<u32>) {
+        self.max_height = height;
+    }
+
+    /// 缩放方式："contain"（默认）、"cover"、"fill" 等
+    #[wasm_bindgen(js_name = setFit)]
+    pub fn set_fit(&mut self, fit: Option<String>) {
+        self.fit = fit;
+    }
+
+    /// 目标文件大小，如 "100kb"、"1mb" 或字节数；仅 smartCompress 使用
+    #[wasm_bindgen(js_name = setTargetSize)]
+    pub fn set_target_size(&mut self, target_size: Option<String>) {
+        self.target_size = target_size;
+    }
+}
+
+impl JsCompressionOptions {
+    /// 设置了最大宽/高时的缩放选项
+    fn resize(&self) -> Option<ResizeOptions> {
+        if self.max_width.is_none() && self.max_height.is_none() {
+            return None;
+        }
+        Some(ResizeOptions {
+            width: self.max_width,
+            height: self.max_height,
+            fit: self.fit.clone().unwrap_or_else(|| "contain".to_string()),
+            ..Default::default()
+        })
+    }
+
+    /// 转换为智能压缩约束；format 作为首选格式
+    fn to_constraints(&self) -> SmartCompressionConstraints {
+        SmartCompressionConstraints {
+            target_size: self.target_size.clone(),
+            max_width: self.max_width,
+            max_height: self.max_height,
+            min_quality: None,
+            preferred_formats: self.format.clone().map(|format| vec![format]),
+            resize: self.resize(),
+        }
+    }
+
+    /// 转换为核心引擎的压缩选项（默认 PNG、质量 80）
+    fn to_core(&self) -> CompressionOptions {
+        CompressionOptions {
+            format: self.format.clone().or_else(|| Some("png".to_string())),
+            quality: self.quality.or(Some(80)),
+            resize: self.resize(),
+            allow_upscale: false,
+            ..Default::default()
+        }
+    }
 }
 
 /// 简化的压缩结果
@@ -60,6 +132,19 @@ pub struct JsCompressionResult {
     processing_time: u64,
 }
 
+impl From<CompressionResult> for JsCompressionResult {
+    fn from(result: CompressionResult) -> Self {
+        JsCompressionResult {
+            data: js_sys::Uint8Array::from(&result.data[..]),
+            format: result.format,
+            original_size: result.original_size,
+            compressed_size: result.compressed_size,
+            compression_ratio: result.compression_ratio,
+            processing_time: result.processing_time,
+        }
+    }
+}
+
 #[wasm_bindgen]
 impl JsCompressionResult {
     #[wasm_bindgen(getter)]
@@ -124,28 +209,10 @@ impl RustyPic {
             let engine = CompressionEngine::new();
 
             // 构建压缩选项
-            let compression_options = CompressionOptions {
-                format: options.format.or_else(|| Some("png".to_string())),
-                quality: options.quality.or(Some(80)),
-                resize: None,
-                optimize: None,
-            };
+            let compression_options = options.to_core();
 
             match engine.compress(&input_vec, &compression_options) {
-                Ok(result) => {
-                    let output_data = js_sys::Uint8Array::from(&result.data[..]);
-
-                    let js_result = JsCompressionResult {
-                        data: output_data,
-                        format: result.format,
-                        original_size: result.original_size,
-                        compressed_size: result.compressed_size,
-                        compression_ratio: result.compression_ratio,
-                        processing_time: result.processing_time,
-                    };
-
-                    Ok(JsValue::from(js_result))
-                }
+                Ok(result) => Ok(JsValue::from(JsCompressionResult::from(result))),
                 Err(e) => {
                     // 如果压缩失败，返回错误信息
                     let error_msg = format!("Compression failed: {e}");
@@ -154,6 +221,116 @@ impl RustyPic {
             }
         })
     }
+
+    /// 智能压缩：自动选择格式与质量，支持目标文件大小与最大宽高
+    ///
+    /// 设置目标大小时，`progress(iteration, quality, size)` 在每次试压后调用
+    #[wasm_bindgen(js_name = smartCompress)]
+    pub fn smart_compress(
+        &self,
+        data: js_sys::Uint8Array,
+        options: JsCompressionOptions,
+        progress: Option<js_sys::Function>,
+    ) -> js_sys::Promise {
+        let input_vec = data.to_vec();
+
+        future_to_promise(async move {
+            let engine = SmartCompressionEngine::new();
+            let constraints = options.to_constraints();
+
+            // 目标大小格式错误时直接拒绝 Promise
+            if let Some(target_size) = &constraints.target_size {
+                engine.parse_target_size(target_size).map_err(|e| {
+                    JsValue::from_str(&format!("Invalid target size '{target_size}': {e}"))
+                })?;
+            }
+
+            // 回调抛出的异常不影响压缩本身
+            let progress = progress.map(|callback| -> rusty_pic_core::ProgressCallback {
+                Box::new(move |iteration, quality, size| {
+                    let _ = callback.call3(
+                        &JsValue::NULL,
+                        &JsValue::from(iteration),
+                        &JsValue::from(quality),
+                        &JsValue::from(size as f64),
+                    );
+                })
+            });
+
+            engine
+                .smart_compress_with_progress(&input_vec, &constraints, progress)
+                .map(|result| JsValue::from(JsCompressionResult::from(result)))
+                .map_err(|e| JsValue::from_str(&format!("Smart compression failed: {e}")))
+        })
+    }
+}
+
+/// 传给 JS 的分析结果（字段名使用 camelCase）
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsImageAnalysis {
+    width: u32,
+    height: u32,
+    format: String,
+    has_alpha: bool,
+    color_count: u32,
+    complexity: f32,
+    recommended_format: String,
+    recommended_quality: u8,
+    estimated_savings: f32,
+    already_optimized: bool,
+    average_color: [u8; 3],
+    color_type: String,
+    bit_depth: u8,
+    srgb_compatible: bool,
+}
+
+impl From<ImageAnalysis> for JsImageAnalysis {
+    fn from(analysis: ImageAnalysis) -> Self {
+        let (r, g, b) = analysis.average_color;
+        JsImageAnalysis {
+            width: analysis.width,
+            height: analysis.height,
+            format: analysis.format,
+            has_alpha: analysis.has_alpha,
+            color_count: analysis.color_count,
+            complexity: analysis.complexity,
+            recommended_format: analysis.recommended_format,
+            recommended_quality: analysis.recommended_quality,
+            estimated_savings: analysis.estimated_savings,
+            already_optimized: analysis.already_optimized,
+            average_color: [r, g, b],
+            color_type: analysis.metadata.color_type,
+            bit_depth: analysis.metadata.bit_depth,
+            srgb_compatible: analysis.metadata.srgb_compatible,
+        }
+    }
+}
+
+/// 一次调用完成分析与压缩，返回 `{ analysis, result }`
+///
+/// 源图像只解码一次并在两个阶段间复用，避免 JS 侧分别调用时跨边界重复解码。
+#[wasm_bindgen(js_name = analyzeAndCompress)]
+pub fn analyze_and_compress(data: &[u8], options: JsCompressionOptions) -> js_sys::Promise {
+    let input_vec = data.to_vec();
+
+    future_to_promise(async move {
+        let engine = CompressionEngine::new();
+        let (analysis, result) = engine
+            .analyze_and_compress(&input_vec, &options.to_core())
+            .map_err(|e| JsValue::from_str(&format!("Compression failed: {e}")))?;
+
+        let analysis = serde_wasm_bindgen::to_value(&JsImageAnalysis::from(analysis))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize analysis: {e}")))?;
+        let output = js_sys::Object::new();
+        js_sys::Reflect::set(&output, &JsValue::from_str("analysis"), &analysis)?;
+        js_sys::Reflect::set(
+            &output,
+            &JsValue::from_str("result"),
+            &JsValue::from(JsCompressionResult::from(result)),
+        )?;
+        Ok(output.into())
+    })
 }
 
 /// 工厂方法